@@ -0,0 +1,29 @@
+use std::{env, path::PathBuf};
+
+/// When set, the committed header is regenerated as well.
+const UPDATE_HEADER: &str = "CATBF_UPDATE_HEADER";
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-env-changed={}", UPDATE_HEADER);
+
+    let mut config = cbindgen::Config::default();
+    config.language = cbindgen::Language::C;
+    config.include_guard = Some("CATBF_H".to_owned());
+    // Usable from C++ too.
+    config.cpp_compat = true;
+    config.usize_is_size_t = true;
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("could not generate the C header");
+    // Builds leave the source tree alone, `tests/header.rs` checks that the
+    // committed header is up to date.
+    bindings.write_to_file(out_dir.join("catbf.h"));
+    if env::var_os(UPDATE_HEADER).is_some() {
+        bindings.write_to_file(crate_dir.join("include").join("catbf.h"));
+    }
+}
@@ -0,0 +1,125 @@
+#ifndef CATBF_H
+#define CATBF_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Returned by a read callback at the end of the input.
+ */
+#define CATBF_EOF -1
+
+/**
+ * Returned by functions that succeed.
+ */
+#define CATBF_OK 0
+
+/**
+ * Returned by functions that fail.
+ */
+#define CATBF_ERROR -1
+
+/**
+ * A program compiled Just-In-Time.
+ */
+typedef struct CatbfJit CatbfJit;
+
+/**
+ * A parsed program.
+ */
+typedef struct CatbfProgram CatbfProgram;
+
+/**
+ * Reads a byte of input, returning it as a value from 0 to 255, `CATBF_EOF`
+ * at the end of the input, or any other negative value on error.
+ */
+typedef int (*CatbfReadFn)(void *context);
+
+/**
+ * Writes a byte of output, returning zero on success and any other value on
+ * error.
+ */
+typedef int (*CatbfWriteFn)(void *context, uint8_t byte);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message of the last error on the calling thread, or `NULL` if there was
+ * none. Valid until the next failing call on the same thread.
+ */
+const char *catbf_last_error(void);
+
+/**
+ * Parses `len` bytes of source code. Returns `NULL` on error. The program
+ * must be freed with `catbf_program_free`.
+ *
+ * # Safety
+ *
+ * `code` must point to `len` readable bytes, or be `NULL` if `len` is zero.
+ */
+struct CatbfProgram *catbf_program_parse(const uint8_t *code, size_t len);
+
+/**
+ * Frees a program. Does nothing if it is `NULL`.
+ *
+ * # Safety
+ *
+ * `program` must come from `catbf_program_parse` and not be freed already.
+ */
+void catbf_program_free(struct CatbfProgram *program);
+
+/**
+ * Runs a program interpreted until it halts, reading input and writing
+ * output through the callbacks, which receive `context`. Returns
+ * `CATBF_OK`, or `CATBF_ERROR` on error.
+ *
+ * # Safety
+ *
+ * `program` must be a live program, and the callbacks must be safe to call
+ * with `context`.
+ */
+int catbf_run(const struct CatbfProgram *program,
+              CatbfReadFn read,
+              CatbfWriteFn write,
+              void *context);
+
+/**
+ * Compiles a program Just-In-Time. Returns `NULL` on error, such as an
+ * unsupported platform. The compiled program must be freed with
+ * `catbf_jit_free`.
+ *
+ * # Safety
+ *
+ * `program` must be a live program.
+ */
+struct CatbfJit *catbf_jit_compile(const struct CatbfProgram *program);
+
+/**
+ * Runs a program compiled Just-In-Time, like `catbf_run`.
+ *
+ * # Safety
+ *
+ * `jit` must be a live compiled program, and the callbacks must be safe to
+ * call with `context`.
+ */
+int catbf_jit_run(const struct CatbfJit *jit, CatbfReadFn read, CatbfWriteFn write, void *context);
+
+/**
+ * Frees a program compiled Just-In-Time. Does nothing if it is `NULL`.
+ *
+ * # Safety
+ *
+ * `jit` must come from `catbf_jit_compile` and not be freed already.
+ */
+void catbf_jit_free(struct CatbfJit *jit);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CATBF_H */
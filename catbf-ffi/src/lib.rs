@@ -0,0 +1,246 @@
+//! C ABI of catbf, for embedding it in applications not written in Rust. The
+//! header `include/catbf.h` is generated from this file when building.
+//!
+//! Functions that fail return `NULL` or a negative status, and leave a message
+//! retrievable with `catbf_last_error` on the calling thread.
+
+use catbf::{
+    compiler::jit,
+    interpreter::{Interface, Machine, Tape},
+    io::{FromRead, FromWrite},
+    ir::Program,
+    source::Source,
+};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CString},
+    fmt, io, ptr, slice,
+};
+
+/// Returned by a read callback at the end of the input.
+pub const CATBF_EOF: c_int = -1;
+
+/// Returned by functions that succeed.
+pub const CATBF_OK: c_int = 0;
+
+/// Returned by functions that fail.
+pub const CATBF_ERROR: c_int = -1;
+
+/// Reads a byte of input, returning it as a value from 0 to 255, `CATBF_EOF`
+/// at the end of the input, or any other negative value on error.
+pub type CatbfReadFn =
+    Option<unsafe extern "C" fn(context: *mut c_void) -> c_int>;
+
+/// Writes a byte of output, returning zero on success and any other value on
+/// error.
+pub type CatbfWriteFn =
+    Option<unsafe extern "C" fn(context: *mut c_void, byte: u8) -> c_int>;
+
+/// A parsed program.
+pub struct CatbfProgram(Program);
+
+/// A program compiled Just-In-Time.
+pub struct CatbfJit(jit::Executable);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error<E>(error: E)
+where
+    E: fmt::Display,
+{
+    // Interior NULs would cut the message short, so they are dropped.
+    let message = error.to_string().replace('\0', "");
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Message of the last error on the calling thread, or `NULL` if there was
+/// none. Valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn catbf_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Parses `len` bytes of source code. Returns `NULL` on error. The program
+/// must be freed with `catbf_program_free`.
+///
+/// # Safety
+///
+/// `code` must point to `len` readable bytes, or be `NULL` if `len` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn catbf_program_parse(
+    code: *const u8,
+    len: usize,
+) -> *mut CatbfProgram {
+    let code =
+        if len == 0 { &[][..] } else { slice::from_raw_parts(code, len) };
+    match Program::parse(Source::new(code)) {
+        Ok(program) => Box::into_raw(Box::new(CatbfProgram(program))),
+        Err(error) => {
+            set_last_error(error);
+            ptr::null_mut()
+        },
+    }
+}
+
+/// Frees a program. Does nothing if it is `NULL`.
+///
+/// # Safety
+///
+/// `program` must come from `catbf_program_parse` and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn catbf_program_free(program: *mut CatbfProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// Runs a program interpreted until it halts, reading input and writing
+/// output through the callbacks, which receive `context`. Returns
+/// `CATBF_OK`, or `CATBF_ERROR` on error.
+///
+/// # Safety
+///
+/// `program` must be a live program, and the callbacks must be safe to call
+/// with `context`.
+#[no_mangle]
+pub unsafe extern "C" fn catbf_run(
+    program: *const CatbfProgram,
+    read: CatbfReadFn,
+    write: CatbfWriteFn,
+    context: *mut c_void,
+) -> c_int {
+    let Some(program) = program.as_ref() else {
+        set_last_error("program is NULL");
+        return CATBF_ERROR;
+    };
+    let interface = Interface::new(
+        FromRead(CallbackReader { read, context }),
+        FromWrite(CallbackWriter { write, context }),
+    );
+    let machine = Machine::new(program.0.clone(), Tape::new(), interface);
+    status(machine.run())
+}
+
+/// Compiles a program Just-In-Time. Returns `NULL` on error, such as an
+/// unsupported platform. The compiled program must be freed with
+/// `catbf_jit_free`.
+///
+/// # Safety
+///
+/// `program` must be a live program.
+#[no_mangle]
+pub unsafe extern "C" fn catbf_jit_compile(
+    program: *const CatbfProgram,
+) -> *mut CatbfJit {
+    let Some(program) = program.as_ref() else {
+        set_last_error("program is NULL");
+        return ptr::null_mut();
+    };
+    match jit::compile(&program.0) {
+        Ok(executable) => Box::into_raw(Box::new(CatbfJit(executable))),
+        Err(error) => {
+            set_last_error(error);
+            ptr::null_mut()
+        },
+    }
+}
+
+/// Runs a program compiled Just-In-Time, like `catbf_run`.
+///
+/// # Safety
+///
+/// `jit` must be a live compiled program, and the callbacks must be safe to
+/// call with `context`.
+#[no_mangle]
+pub unsafe extern "C" fn catbf_jit_run(
+    jit: *const CatbfJit,
+    read: CatbfReadFn,
+    write: CatbfWriteFn,
+    context: *mut c_void,
+) -> c_int {
+    let Some(jit) = jit.as_ref() else {
+        set_last_error("compiled program is NULL");
+        return CATBF_ERROR;
+    };
+    status(jit.0.run(
+        CallbackReader { read, context },
+        CallbackWriter { write, context },
+    ))
+}
+
+/// Frees a program compiled Just-In-Time. Does nothing if it is `NULL`.
+///
+/// # Safety
+///
+/// `jit` must come from `catbf_jit_compile` and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn catbf_jit_free(jit: *mut CatbfJit) {
+    if !jit.is_null() {
+        drop(Box::from_raw(jit));
+    }
+}
+
+fn status<T, E>(result: Result<T, E>) -> c_int
+where
+    E: fmt::Display,
+{
+    match result {
+        Ok(_) => CATBF_OK,
+        Err(error) => {
+            set_last_error(error);
+            CATBF_ERROR
+        },
+    }
+}
+
+/// Input read through a callback, a byte per call.
+struct CallbackReader {
+    read: CatbfReadFn,
+    context: *mut c_void,
+}
+
+impl io::Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (Some(read), Some(first)) = (self.read, buf.first_mut()) else {
+            return Ok(0);
+        };
+        match unsafe { read(self.context) } {
+            byte @ 0 ..= 255 => {
+                *first = byte as u8;
+                Ok(1)
+            },
+            CATBF_EOF => Ok(0),
+            _ => Err(io::Error::other("read callback failed")),
+        }
+    }
+}
+
+/// Output written through a callback, a byte per call. Output is discarded
+/// if there is no callback.
+struct CallbackWriter {
+    write: CatbfWriteFn,
+    context: *mut c_void,
+}
+
+impl io::Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(write) = self.write {
+            for &byte in buf {
+                if unsafe { write(self.context, byte) } != 0 {
+                    Err(io::Error::other("write callback failed"))?;
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+/* Exercises the C API: parsing, running interpreted and compiled Just-In-Time,
+ * and error reporting. Exits with a non-zero status on failure. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "catbf.h"
+
+struct buffers {
+    const char *input;
+    size_t input_len;
+    size_t read;
+    char output[256];
+    size_t written;
+};
+
+static int read_byte(void *context) {
+    struct buffers *buffers = context;
+    if (buffers->read == buffers->input_len) {
+        return CATBF_EOF;
+    }
+    return (unsigned char) buffers->input[buffers->read++];
+}
+
+static int write_byte(void *context, uint8_t byte) {
+    struct buffers *buffers = context;
+    if (buffers->written == sizeof(buffers->output)) {
+        return 1;
+    }
+    buffers->output[buffers->written++] = (char) byte;
+    return 0;
+}
+
+static int failures = 0;
+
+static void check(int condition, const char *message) {
+    if (!condition) {
+        fprintf(stderr, "failed: %s\n", message);
+        failures++;
+    }
+}
+
+static void check_output(struct buffers *buffers, const char *expected) {
+    check(buffers->written == strlen(expected)
+              && memcmp(buffers->output, expected, buffers->written) == 0,
+          expected);
+}
+
+int main(void) {
+    const char *cat = ",[>.<,]";
+    const char *input = "hello, world";
+
+    CatbfProgram *program =
+        catbf_program_parse((const uint8_t *) cat, strlen(cat));
+    check(program != NULL, "parse cat");
+    if (program == NULL) {
+        return 1;
+    }
+
+    struct buffers buffers = { input, strlen(input), 0, { 0 }, 0 };
+    check(catbf_run(program, read_byte, write_byte, &buffers) == CATBF_OK,
+          "interpret cat");
+    check_output(&buffers, input);
+
+    CatbfJit *jit = catbf_jit_compile(program);
+    if (jit != NULL) {
+        struct buffers buffers = { input, strlen(input), 0, { 0 }, 0 };
+        check(catbf_jit_run(jit, read_byte, write_byte, &buffers) == CATBF_OK,
+              "run cat compiled Just-In-Time");
+        check_output(&buffers, input);
+        catbf_jit_free(jit);
+    } else {
+        /* Platforms without a JIT compiler report why. */
+        check(catbf_last_error() != NULL, "JIT error message");
+    }
+
+    /* Writes fail once the output is full. */
+    const char *forever = "+[.]";
+    CatbfProgram *writer =
+        catbf_program_parse((const uint8_t *) forever, strlen(forever));
+    struct buffers full = { "", 0, 0, { 0 }, 0 };
+    check(catbf_run(writer, read_byte, write_byte, &full) == CATBF_ERROR,
+          "failing write callback");
+    check(catbf_last_error() != NULL, "write error message");
+    catbf_program_free(writer);
+
+    const char *unmatched = "+[";
+    check(catbf_program_parse((const uint8_t *) unmatched, strlen(unmatched))
+              == NULL,
+          "parse unmatched loop");
+    const char *error = catbf_last_error();
+    check(error != NULL && strstr(error, "unmatched") != NULL,
+          "parse error message");
+
+    catbf_program_free(program);
+    catbf_program_free(NULL);
+
+    return failures == 0 ? 0 : 1;
+}
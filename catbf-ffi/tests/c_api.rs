@@ -0,0 +1,32 @@
+use std::{env, path::PathBuf, process::Command};
+
+/// Compiles the C test program against the shared library and runs it.
+#[test]
+fn c_api() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // Tests run from `target/<profile>/deps`, next to which the library is.
+    let mut library_dir = env::current_exe().unwrap();
+    library_dir.pop();
+    library_dir.pop();
+    let executable = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("c_api");
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let status = Command::new(cc)
+        .arg(crate_dir.join("tests").join("c").join("api.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg("-L")
+        .arg(&library_dir)
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .arg("-lcatbf_ffi")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-o")
+        .arg(&executable)
+        .status()
+        .expect("could not run the C compiler");
+    assert!(status.success(), "C test program did not compile");
+
+    let status = Command::new(&executable).status().unwrap();
+    assert!(status.success(), "C test program failed");
+}
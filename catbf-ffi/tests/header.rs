@@ -0,0 +1,11 @@
+/// The committed header must match the one generated from the sources.
+#[test]
+fn header_is_up_to_date() {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/catbf.h"));
+    let committed = include_str!("../include/catbf.h");
+    assert!(
+        committed == generated,
+        "include/catbf.h is outdated, regenerate it with \
+         `CATBF_UPDATE_HEADER=1 cargo build -p catbf-ffi`"
+    );
+}
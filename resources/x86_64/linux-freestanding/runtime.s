@@ -0,0 +1,168 @@
+    .set TAPE_CHUNK_SIZE, {TAPE_CHUNK_SIZE}
+    .set SYS_READ, 0
+    .set SYS_WRITE, 1
+    .set SYS_MMAP, 9
+    .set SYS_MREMAP, 25
+    .set SYS_EXIT_GROUP, 231
+    .set EINTR, 4
+    .section .note.GNU-stack,"",@progbits
+    .section .rodata
+.io_error:
+    .ascii "stdio: input/output error\n"
+    .set IO_ERROR_LEN, . - .io_error
+    .text
+    .globl _start
+    .globl catbf_create_tape
+    .globl catbf_destroy_tape
+    .globl catbf_grow_next
+    .globl catbf_grow_prev
+    .globl catbf_get
+    .globl catbf_put
+
+    ### start begin
+_start:
+    # the stack is aligned to 16 bytes at entry, the interface is unused
+    xorl %edi, %edi
+    call catbf_main
+    # exit_code = 0
+    xorl %edi, %edi
+    # return_0 ?= return_0
+    testb %al, %al
+    # return_0 >= 0
+    jns .exit
+    # write(2, io_error, IO_ERROR_LEN)
+    movl $SYS_WRITE, %eax
+    movl $2, %edi
+    leaq .io_error(%rip), %rsi
+    movl $IO_ERROR_LEN, %edx
+    syscall
+    # exit_code = 1
+    movl $1, %edi
+.exit:
+    # exit_group(exit_code)
+    movl $SYS_EXIT_GROUP, %eax
+    syscall
+    ### start end
+
+    ### create tape begin
+catbf_create_tape:
+    # mmap(null, TAPE_CHUNK_SIZE, PROT_READ | PROT_WRITE,
+    #      MAP_PRIVATE | MAP_ANONYMOUS, -1, 0), zeroed by the kernel
+    movl $SYS_MMAP, %eax
+    xorl %edi, %edi
+    movl $TAPE_CHUNK_SIZE, %esi
+    movl $3, %edx
+    movl $0x22, %r10d
+    movq $-1, %r8
+    xorl %r9d, %r9d
+    syscall
+    jmp .mapped
+    ### create tape end
+
+    ### destroy tape begin
+catbf_destroy_tape:
+    # the mapping is released when the process exits
+    ret
+    ### destroy tape end
+
+    ### grow next begin
+catbf_grow_next:
+    # mremap(tape_start, tape_len, tape_len + TAPE_CHUNK_SIZE, MREMAP_MAYMOVE),
+    # new pages are zeroed by the kernel
+    movl $SYS_MREMAP, %eax
+    leaq TAPE_CHUNK_SIZE(%rsi), %rdx
+    movl $1, %r10d
+    syscall
+.mapped:
+    # errors are returned as -4095..-1
+    cmpq $-4095, %rax
+    jae .null
+    ret
+.null:
+    xorl %eax, %eax
+    ret
+    ### grow next end
+
+    ### grow prev begin
+catbf_grow_prev:
+    # tape_len is needed after the call
+    pushq %rsi
+    call catbf_grow_next
+    popq %rcx
+    # return_0 ?= null
+    testq %rax, %rax
+    # return_0 == null
+    jz .grown_prev
+    # move the old cells backwards to the end, from their last byte
+    std
+    leaq -1(%rax, %rcx), %rsi
+    leaq TAPE_CHUNK_SIZE(%rsi), %rdi
+    rep movsb
+    cld
+    # zero the first chunk
+    movq %rax, %rdi
+    movq %rax, %rdx
+    xorl %eax, %eax
+    movl $TAPE_CHUNK_SIZE, %ecx
+    rep stosb
+    movq %rdx, %rax
+.grown_prev:
+    ret
+    ### grow prev end
+
+    ### get begin
+catbf_get:
+    # buffer on the stack
+    subq $8, %rsp
+.get_retry:
+    # read(0, buffer, 1)
+    movl $SYS_READ, %eax
+    xorl %edi, %edi
+    movq %rsp, %rsi
+    movl $1, %edx
+    syscall
+    cmpq $-EINTR, %rax
+    je .get_retry
+    # return_0 ?= 0
+    testq %rax, %rax
+    # return_0 < 0
+    js .get_failure
+    # return_0 == 0, end of file
+    jz .get_end
+    # return_0 = 1 << 8 | buffer
+    movzbl (%rsp), %eax
+    orl $0x100, %eax
+    jmp .get_end
+.get_failure:
+    movl $-1, %eax
+.get_end:
+    addq $8, %rsp
+    ret
+    ### get end
+
+    ### put begin
+catbf_put:
+    # buffer on the stack
+    subq $8, %rsp
+    movb %sil, (%rsp)
+.put_retry:
+    # write(1, buffer, 1)
+    movl $SYS_WRITE, %eax
+    movl $1, %edi
+    movq %rsp, %rsi
+    movl $1, %edx
+    syscall
+    cmpq $-EINTR, %rax
+    je .put_retry
+    # return_0 ?= 0
+    testq %rax, %rax
+    # return_0 <= 0
+    jle .put_failure
+    xorl %eax, %eax
+    jmp .put_end
+.put_failure:
+    movl $-1, %eax
+.put_end:
+    addq $8, %rsp
+    ret
+    ### put end
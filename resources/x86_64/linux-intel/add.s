@@ -0,0 +1,4 @@
+    ### add begin
+    # *(tape_start + tape_pos) += {count}
+    add byte ptr [r12 + r14], {count}
+    ### add end
@@ -0,0 +1,4 @@
+    ### dec begin
+    # *(tape_start + tape_pos) -= 1
+    dec byte ptr [r12 + r14]
+    ### dec end
@@ -0,0 +1,27 @@
+    ### enter begin
+    # save registers, five pushes keep the stack aligned to 16 bytes at calls
+    # unused: u64
+    push r15
+    # tape_pos: u64
+    push r14
+    # tape_len: u64
+    push r13
+    # tape_start: *mut u8
+    push r12
+    # interface: *mut interface
+    push rbx
+    # interface = arg_0
+    mov rbx, rdi
+    # tape_pos = 0
+    xor r14, r14
+    # result_0 = catbf_create_tape()
+    call catbf_create_tape
+    # tape_len = TAPE_CHUNK_SIZE
+    mov r13, {TAPE_CHUNK_SIZE}
+    # result_0 ?= null
+    test rax, rax
+    # result == null
+    jz .failure
+    # tape_start = result_0
+    mov r12, rax
+    ### enter end
@@ -0,0 +1,36 @@
+    ### get begin
+    # input is written to two cells, the next one must exist too
+    # temp = tape_pos + 1
+    lea rax, [r14 + 1]
+    # temp =? end
+    cmp rax, r13
+    # temp == end
+    jne .get_growed_next_{id}
+    # arg_0 = tape_start
+    mov rdi, r12
+    # arg_1 = tape_len
+    mov rsi, r13
+    # return_0 = catbf_grow_next(arg_0, arg_1)
+    call catbf_grow_next
+    # result_0 ?= null
+    test rax, rax
+    # result == null
+    jz .failure
+    # tape_start = return_0
+    mov r12, rax
+    # tape_len += TAPE_CHUNK_SIZE
+    add r13, {TAPE_CHUNK_SIZE}
+.get_growed_next_{id}:
+    # arg_0 = interface
+    mov rdi, rbx
+    # return_0 = catbf_get(arg_0)
+    call catbf_get
+    # return_0 ?= return_0
+    test ax, ax
+    # return_0 < 0
+    js .failure
+    # bswap return_0
+    ror ax, 8
+    # *(tape_start + tape_pos) = return_0
+    mov word ptr [r12 + r14], ax
+    ### get end
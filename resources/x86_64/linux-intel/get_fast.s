@@ -0,0 +1,22 @@
+    ### get fast begin
+    # input is written to two cells, the next one must exist too
+    # temp = tape_pos + 1
+    lea rax, [r14 + 1]
+    # temp =? end
+    cmp rax, r13
+    # temp == end
+    je .get_slow_{id}
+.get_done_{id}:
+    # arg_0 = interface
+    mov rdi, rbx
+    # return_0 = catbf_get(arg_0)
+    call catbf_get
+    # return_0 ?= return_0
+    test ax, ax
+    # return_0 < 0
+    js .failure
+    # bswap return_0
+    ror ax, 8
+    # *(tape_start + tape_pos) = return_0
+    mov word ptr [r12 + r14], ax
+    ### get fast end
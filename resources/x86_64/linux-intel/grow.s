@@ -0,0 +1,47 @@
+    ### grow begin
+    # shared by the slow paths, which reach these with a call
+.grow_next:
+    # the return address misaligns the stack, calls need it aligned to 16
+    sub rsp, 8
+    # arg_0 = tape_start
+    mov rdi, r12
+    # arg_1 = tape_len
+    mov rsi, r13
+    # return_0 = catbf_grow_next(arg_0, arg_1)
+    call catbf_grow_next
+    add rsp, 8
+    # result_0 ?= null
+    test rax, rax
+    # result == null
+    jz .grow_failure
+    # tape_start = return_0
+    mov r12, rax
+    # tape_len += TAPE_CHUNK_SIZE
+    add r13, {TAPE_CHUNK_SIZE}
+    ret
+.grow_prev:
+    # the return address misaligns the stack, calls need it aligned to 16
+    sub rsp, 8
+    # arg_0 = tape_start
+    mov rdi, r12
+    # arg_1 = tape_len
+    mov rsi, r13
+    # return_0 = catbf_grow_prev(arg_0, arg_1)
+    call catbf_grow_prev
+    add rsp, 8
+    # result_0 ?= null
+    test rax, rax
+    # result == null
+    jz .grow_failure
+    # tape_pos += TAPE_CHUNK_SIZE
+    add r14, {TAPE_CHUNK_SIZE}
+    # tape_start = return_0
+    mov r12, rax
+    # tape_len += TAPE_CHUNK_SIZE
+    add r13, {TAPE_CHUNK_SIZE}
+    ret
+.grow_failure:
+    # drop the return address, leave from the frame of catbf_main
+    add rsp, 8
+    jmp .failure
+    ### grow end
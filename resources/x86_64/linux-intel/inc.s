@@ -0,0 +1,4 @@
+    ### inc begin
+    # *(tape_start + tape_pos) += 1
+    inc byte ptr [r12 + r14]
+    ### inc end
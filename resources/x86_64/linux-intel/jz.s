@@ -0,0 +1,7 @@
+    ### jz begin
+    # *(tape_start + tape_pos) ?= 0
+    mov al, byte ptr [r12 + r14]
+    test al, al
+    # *(tape_start + tape_pos) == 0
+    jz .label_{target}
+    ### jz end
@@ -0,0 +1,24 @@
+    ### leave begin
+.success:
+    # temp = 0
+    xor r14b, r14b
+    jmp .leave
+.failure:
+    # temp = -1
+    mov r14b, -1
+.leave:
+    # arg_0 = tape_start
+    mov rdi, r12
+    # catbf_detroy_tape(tape_start)
+    call catbf_destroy_tape
+    # return_0 = temp
+    mov al, r14b
+    # restore registers
+    pop rbx
+    pop r12
+    pop r13
+    pop r14
+    pop r15
+    # return to caller
+    ret
+    ### leave end
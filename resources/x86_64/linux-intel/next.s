@@ -0,0 +1,23 @@
+    ### next begin
+    # tape_pos += 1
+    inc r14
+    # tape_pos =? end
+    cmp r14, r13
+    # tape_pos == end
+    jne .growed_next_{id}
+    # arg_0 = tape_start
+    mov rdi, r12
+    # arg_1 = tape_len
+    mov rsi, r13
+    # return_0 = catbf_grow_next(arg_0, arg_1)
+    call catbf_grow_next
+    # result_0 ?= null
+    test rax, rax
+    # result == null
+    jz .failure
+    # tape_start = return_0
+    mov r12, rax
+    # tape_len += TAPE_CHUNK_SIZE
+    add r13, {TAPE_CHUNK_SIZE}
+.growed_next_{id}:
+    ### next end
@@ -0,0 +1,9 @@
+    ### next fast begin
+    # tape_pos += {count}
+    add r14, {count}
+    # tape_pos >=? end
+    cmp r14, r13
+    # tape_pos >= end
+    jae .next_slow_{id}
+.next_done_{id}:
+    ### next fast end
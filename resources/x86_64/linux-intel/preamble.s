@@ -0,0 +1,12 @@
+    .intel_syntax noprefix
+    .section .note.GNU-stack,"",@progbits
+    .text
+    .extern catbf_create_tape
+    .extern catbf_destroy_tape
+    .extern catbf_grow_next
+    .extern catbf_grow_prev
+    .extern catbf_get
+    .extern catbf_put
+    .globl catbf_main
+
+catbf_main:
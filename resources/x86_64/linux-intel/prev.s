@@ -0,0 +1,25 @@
+    ### prev begin
+    # start &? start
+    test r14, r14
+    # start != 0
+    jnz .growed_prev_{id}
+    # arg_0 = tape_start
+    mov rdi, r12
+    # arg_1 = tape_len
+    mov rsi, r13
+    # return_0 = catbf_grow_prev(arg_0, arg_1)
+    call catbf_grow_prev
+    # result_0 ?= null
+    test rax, rax
+    # result == null
+    jz .failure
+    # tape_pos += TAPE_CHUNK_SIZE
+    add r14, {TAPE_CHUNK_SIZE}
+    # tape_start = return_0
+    mov r12, rax
+    # tape_len += TAPE_CHUNK_SIZE
+    add r13, {TAPE_CHUNK_SIZE}
+.growed_prev_{id}:
+    # tape_pos -= 1
+    dec r14
+    ### prev end
@@ -0,0 +1,9 @@
+    ### prev fast begin
+    # tape_pos <? {count}
+    cmp r14, {count}
+    # tape_pos < {count}
+    jb .prev_slow_{id}
+.prev_done_{id}:
+    # tape_pos -= {count}
+    sub r14, {count}
+    ### prev fast end
@@ -0,0 +1,10 @@
+    ### prev slow begin
+.prev_slow_{id}:
+    # grow_prev()
+    call .grow_prev
+    # tape_pos <? {count}
+    cmp r14, {count}
+    # tape_pos < {count}
+    jb .prev_slow_{id}
+    jmp .prev_done_{id}
+    ### prev slow end
@@ -0,0 +1,14 @@
+    ### put begin
+    # arg_0 = interface
+    mov rdi, rbx
+    # arg_1 = *(tape_start + tape_pos)
+    xor eax, eax
+    mov al, byte ptr [r12 + r14]
+    mov si, ax
+    # return_0 = catbf_put(arg_0, arg_1)
+    call catbf_put
+    # return_0 ?= return_0
+    test al, al
+    # result0 < 0
+    js .failure
+    ### put end
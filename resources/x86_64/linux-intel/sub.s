@@ -0,0 +1,4 @@
+    ### sub begin
+    # *(tape_start + tape_pos) -= {count}
+    sub byte ptr [r12 + r14], {count}
+    ### sub end
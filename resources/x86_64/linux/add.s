@@ -0,0 +1,4 @@
+    ### add begin
+    # *(tape_start + tape_pos) += {count}
+    addb ${count}, 0(%r12, %r14)
+    ### add end
@@ -1,5 +1,7 @@
     ### enter begin
-    # save registers
+    # save registers, five pushes keep the stack aligned to 16 bytes at calls
+    # unused: u64
+    pushq %r15
     # tape_pos: u64
     pushq %r14
     # tape_len: u64
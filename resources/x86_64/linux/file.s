@@ -0,0 +1 @@
+    .file 1 "{file}"
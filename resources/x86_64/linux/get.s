@@ -1,8 +1,11 @@
     ### get begin
-    # start =? end
-    cmpq %r13, %r14
-    # start == end
-    jne .get_growed_next
+    # input is written to two cells, the next one must exist too
+    # temp = tape_pos + 1
+    leaq 1(%r14), %rax
+    # temp =? end
+    cmpq %r13, %rax
+    # temp == end
+    jne .get_growed_next_{id}
     # arg_0 = tape_start
     movq %r12, %rdi
     # arg_1 = tape_len
@@ -17,7 +20,7 @@
     movq %rax, %r12
     # tape_len += TAPE_CHUNK_SIZE
     addq $TAPE_CHUNK_SIZE, %r13
-.get_growed_next:
+.get_growed_next_{id}:
     # arg_0 = interface
     movq %rbx, %rdi
     # return_0 = catbf_get(arg_0)
@@ -0,0 +1,22 @@
+    ### get fast begin
+    # input is written to two cells, the next one must exist too
+    # temp = tape_pos + 1
+    leaq 1(%r14), %rax
+    # temp =? end
+    cmpq %r13, %rax
+    # temp == end
+    je .get_slow_{id}
+.get_done_{id}:
+    # arg_0 = interface
+    movq %rbx, %rdi
+    # return_0 = catbf_get(arg_0)
+    call catbf_get
+    # return_0 ?= return_0
+    testw %ax, %ax
+    # return_0 < 0
+    js .failure
+    # bswap return_0
+    ror $8, %ax
+    # *(tape_start + tape_pos) = return_0
+    movw %ax, 0(%r12, %r14)
+    ### get fast end
@@ -0,0 +1,6 @@
+    ### get slow begin
+.get_slow_{id}:
+    # grow_next()
+    call .grow_next
+    jmp .get_done_{id}
+    ### get slow end
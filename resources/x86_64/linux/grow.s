@@ -0,0 +1,47 @@
+    ### grow begin
+    # shared by the slow paths, which reach these with a call
+.grow_next:
+    # the return address misaligns the stack, calls need it aligned to 16
+    subq $8, %rsp
+    # arg_0 = tape_start
+    movq %r12, %rdi
+    # arg_1 = tape_len
+    movq %r13, %rsi
+    # return_0 = catbf_grow_next(arg_0, arg_1)
+    call catbf_grow_next
+    addq $8, %rsp
+    # result_0 ?= null
+    test %rax, %rax
+    # result == null
+    jz .grow_failure
+    # tape_start = return_0
+    movq %rax, %r12
+    # tape_len += TAPE_CHUNK_SIZE
+    addq $TAPE_CHUNK_SIZE, %r13
+    ret
+.grow_prev:
+    # the return address misaligns the stack, calls need it aligned to 16
+    subq $8, %rsp
+    # arg_0 = tape_start
+    movq %r12, %rdi
+    # arg_1 = tape_len
+    movq %r13, %rsi
+    # return_0 = catbf_grow_prev(arg_0, arg_1)
+    call catbf_grow_prev
+    addq $8, %rsp
+    # result_0 ?= null
+    test %rax, %rax
+    # result == null
+    jz .grow_failure
+    # tape_pos += TAPE_CHUNK_SIZE
+    addq $TAPE_CHUNK_SIZE, %r14
+    # tape_start = return_0
+    movq %rax, %r12
+    # tape_len += TAPE_CHUNK_SIZE
+    addq $TAPE_CHUNK_SIZE, %r13
+    ret
+.grow_failure:
+    # drop the return address, leave from the frame of catbf_main
+    addq $8, %rsp
+    jmp .failure
+    ### grow end
@@ -0,0 +1 @@
+.label_{id}:
@@ -18,6 +18,7 @@
     popq %r12
     popq %r13
     popq %r14
+    popq %r15
     # return to caller
     ret
     ### leave end
@@ -0,0 +1 @@
+    .loc 1 {line} {column}
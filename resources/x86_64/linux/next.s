@@ -1,8 +1,10 @@
     ### next begin
-    # start =? end
+    # tape_pos += 1
+    incq %r14
+    # tape_pos =? end
     cmpq %r13, %r14
-    # start == end
-    jne .growed_next
+    # tape_pos == end
+    jne .growed_next_{id}
     # arg_0 = tape_start
     movq %r12, %rdi
     # arg_1 = tape_len
@@ -17,7 +19,5 @@
     movq %rax, %r12
     # tape_len += TAPE_CHUNK_SIZE
     addq $TAPE_CHUNK_SIZE, %r13
-.growed_next:
-    # tape_pos += 1
-    incq %r14
+.growed_next_{id}:
     ### next end
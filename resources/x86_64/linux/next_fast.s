@@ -0,0 +1,9 @@
+    ### next fast begin
+    # tape_pos += {count}
+    addq ${count}, %r14
+    # tape_pos >=? end
+    cmpq %r13, %r14
+    # tape_pos >= end
+    jae .next_slow_{id}
+.next_done_{id}:
+    ### next fast end
@@ -0,0 +1,10 @@
+    ### next slow begin
+.next_slow_{id}:
+    # grow_next()
+    call .grow_next
+    # tape_pos >=? end
+    cmpq %r13, %r14
+    # tape_pos >= end
+    jae .next_slow_{id}
+    jmp .next_done_{id}
+    ### next slow end
@@ -1,4 +1,5 @@
-    .set TAPE_CHUNK_SIZE, 8192
+    .set TAPE_CHUNK_SIZE, {TAPE_CHUNK_SIZE}
+    .section .note.GNU-stack,"",@progbits
     .text
     .extern catbf_create_tape
     .extern catbf_destroy_tape
@@ -2,7 +2,7 @@
     # start &? start
     testq %r14, %r14
     # start != 0
-    jnz .growed_prev
+    jnz .growed_prev_{id}
     # arg_0 = tape_start
     movq %r12, %rdi
     # arg_1 = tape_len
@@ -19,7 +19,7 @@
     movq %rax, %r12
     # tape_len += TAPE_CHUNK_SIZE
     addq $TAPE_CHUNK_SIZE, %r13
-.growed_prev:
+.growed_prev_{id}:
     # tape_pos -= 1
     decq %r14
     ### prev end
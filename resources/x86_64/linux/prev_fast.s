@@ -0,0 +1,9 @@
+    ### prev fast begin
+    # tape_pos <? {count}
+    cmpq ${count}, %r14
+    # tape_pos < {count}
+    jb .prev_slow_{id}
+.prev_done_{id}:
+    # tape_pos -= {count}
+    subq ${count}, %r14
+    ### prev fast end
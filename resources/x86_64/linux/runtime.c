@@ -3,7 +3,7 @@
 #include <stdlib.h>
 #include <string.h>
 
-#define TAPE_CHUNK_SIZE 8192
+#define TAPE_CHUNK_SIZE {TAPE_CHUNK_SIZE}
 
 struct catbf_interface {
     FILE *in;
@@ -63,7 +63,7 @@ int16_t catbf_get(struct catbf_interface *interface)
 
 uint8_t *catbf_create_tape(void)
 {
-    return calloc(TAPE_CHUNK_SIZE, sizeof(uint8_t *));
+    return calloc(TAPE_CHUNK_SIZE, sizeof(uint8_t));
 }
 
 void catbf_destroy_tape(uint8_t *tape_start)
@@ -84,7 +84,7 @@ uint8_t *catbf_grow_prev(uint8_t *tape_start, uint64_t tape_len)
 {
     uint8_t *new_start = realloc(tape_start, tape_len + TAPE_CHUNK_SIZE);
     if (new_start != NULL) {
-        memmove(new_start + TAPE_CHUNK_SIZE, new_start, TAPE_CHUNK_SIZE);
+        memmove(new_start + TAPE_CHUNK_SIZE, new_start, tape_len);
         memset(new_start, 0, TAPE_CHUNK_SIZE);
     }
     return new_start;
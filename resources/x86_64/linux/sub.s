@@ -0,0 +1,4 @@
+    ### sub begin
+    # *(tape_start + tape_pos) -= {count}
+    subb ${count}, 0(%r12, %r14)
+    ### sub end
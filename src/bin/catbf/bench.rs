@@ -0,0 +1,300 @@
+//! Comparison of the backends running the same program, reported as a table
+//! or as JSON.
+
+use crate::cli::{BenchArgs, BenchBackend, BenchFormat};
+use anyhow::{anyhow, bail};
+use catbf::{
+    engine::{Engine, EngineBuilder},
+    interpreter::Tape,
+};
+use clap::ValueEnum;
+use std::{
+    fmt::Write as _,
+    fs,
+    time::{Duration, Instant},
+};
+
+/// Measurements of a program on a backend.
+struct BenchResult {
+    backend: BenchBackend,
+    /// Time spent compiling, for backends that compile the whole program.
+    compile_time: Option<Duration>,
+    run_times: Vec<Duration>,
+    /// Instructions executed, only counted by the interpreter.
+    instructions: Option<u64>,
+    /// Cells allocated once the program halted, as the tape never shrinks.
+    tape_cells: Option<usize>,
+}
+
+impl BenchResult {
+    fn new(backend: BenchBackend) -> Self {
+        Self {
+            backend,
+            compile_time: None,
+            run_times: Vec::new(),
+            instructions: None,
+            tape_cells: None,
+        }
+    }
+
+    fn min_run_time(&self) -> Duration {
+        self.run_times.iter().copied().min().unwrap_or_default()
+    }
+
+    fn mean_run_time(&self) -> Duration {
+        let total: Duration = self.run_times.iter().sum();
+        total / self.run_times.len().max(1) as u32
+    }
+}
+
+pub fn bench(args: BenchArgs) -> anyhow::Result<()> {
+    let program = args.source.parse()?;
+    let input = match (&args.input, &args.input_string) {
+        (Some(path), _) => fs::read(path)
+            .map_err(|error| anyhow!("{}: {}", path.display(), error))?,
+        (None, Some(text)) => text.clone().into_bytes(),
+        (None, None) => Vec::new(),
+    };
+    let backends = if args.backends.is_empty() {
+        BenchBackend::value_variants()
+            .iter()
+            .copied()
+            .filter(|backend| backend.engine_backend().is_supported())
+            .collect()
+    } else {
+        args.backends.clone()
+    };
+
+    let mut expected = None;
+    let mut results = Vec::new();
+    for backend in backends {
+        let mut result = BenchResult::new(backend);
+        let start = Instant::now();
+        let engine =
+            EngineBuilder::new(backend.engine_backend()).build(&program)?;
+        if matches!(backend, BenchBackend::Jit | BenchBackend::Aot) {
+            result.compile_time = Some(start.elapsed());
+        }
+        for _ in 0 .. args.runs {
+            let output = bench_run(&*engine, &input, &mut result)?;
+            check_bench_output(backend, output, &mut expected)?;
+        }
+        results.push(result);
+    }
+
+    match args.format {
+        BenchFormat::Text => print!("{}", bench_table(&results)),
+        BenchFormat::Json => {
+            let name = args.source.single_file_name();
+            println!("{}", bench_json(name.as_deref(), args.runs, &results));
+        },
+    }
+    Ok(())
+}
+
+/// Runs the program once, returning its output.
+fn bench_run(
+    engine: &dyn Engine,
+    input: &[u8],
+    result: &mut BenchResult,
+) -> anyhow::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let stats = engine.run(&mut &input[..], &mut output)?;
+    result.run_times.push(stats.time);
+    if result.backend == BenchBackend::Interpreter {
+        result.instructions = stats.instructions;
+    }
+    // Executables compiled Ahead-Of-Time keep no statistics.
+    if result.backend != BenchBackend::Aot {
+        let chunks = 1 + stats.tape_growths as usize;
+        result.tape_cells = Some(chunks * Tape::CHUNK_SIZE);
+    }
+    Ok(output)
+}
+
+/// Checks that a backend wrote the same output as the first one.
+fn check_bench_output(
+    backend: BenchBackend,
+    output: Vec<u8>,
+    expected: &mut Option<(BenchBackend, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    match expected {
+        Some((first, expected)) if *expected != output => bail!(
+            "output of the {} backend differs from the one of the {} backend",
+            backend.name(),
+            first.name()
+        ),
+        Some(_) => (),
+        None => *expected = Some((backend, output)),
+    }
+    Ok(())
+}
+
+fn bench_table(results: &[BenchResult]) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+    let mut table = String::new();
+    writeln!(
+        table,
+        "{:<12} {:>12} {:>12} {:>12} {:>14} {:>10}",
+        "backend", "compile", "run min", "run mean", "instructions", "tape"
+    )
+    .ok();
+    for result in results {
+        writeln!(
+            table,
+            "{:<12} {:>12} {:>12} {:>12} {:>14} {:>10}",
+            result.backend.name(),
+            optional(result.compile_time.map(format_duration)),
+            format_duration(result.min_run_time()),
+            format_duration(result.mean_run_time()),
+            optional(result.instructions.map(|count| count.to_string())),
+            optional(result.tape_cells.map(|count| count.to_string())),
+        )
+        .ok();
+    }
+    table
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", milliseconds(duration))
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Results as a JSON object, with times in milliseconds and `null` for what
+/// a backend does not measure.
+fn bench_json(
+    name: Option<&str>,
+    runs: u32,
+    results: &[BenchResult],
+) -> String {
+    let optional =
+        |value: Option<String>| value.unwrap_or_else(|| "null".into());
+    let entries: Vec<_> = results
+        .iter()
+        .map(|result| {
+            let run_times: Vec<_> = result
+                .run_times
+                .iter()
+                .map(|&duration| milliseconds(duration).to_string())
+                .collect();
+            format!(
+                "{{\"backend\":\"{}\",\"compile_ms\":{},\"run_min_ms\":{},\
+                 \"run_mean_ms\":{},\"run_ms\":[{}],\"instructions\":{},\
+                 \"tape_cells\":{}}}",
+                result.backend.name(),
+                optional(
+                    result
+                        .compile_time
+                        .map(|time| milliseconds(time).to_string())
+                ),
+                milliseconds(result.min_run_time()),
+                milliseconds(result.mean_run_time()),
+                run_times.join(","),
+                optional(result.instructions.map(|count| count.to_string())),
+                optional(result.tape_cells.map(|count| count.to_string())),
+            )
+        })
+        .collect();
+    format!(
+        "{{\"program\":{},\"runs\":{},\"results\":[{}]}}",
+        optional(name.map(json_string)),
+        runs,
+        entries.join(",")
+    )
+}
+
+/// Quotes a string for JSON.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            ch if ch.is_control() => {
+                write!(quoted, "\\u{:04x}", ch as u32).ok();
+            },
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bench_json, json_string, BenchResult};
+    use crate::cli::BenchBackend;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn strings_are_escaped() {
+        for text in [
+            "",
+            "plain.bf",
+            "say \"hi\".bf",
+            "C:\\programs\\hi.bf",
+            "new\nline\ttab\r\0nul\x1b\x7f\u{85}",
+            "ção/日本/🦀.bf",
+        ] {
+            let quoted = json_string(text);
+            let parsed: Value = serde_json::from_str(&quoted)
+                .unwrap_or_else(|error| panic!("{}: {}", quoted, error));
+            assert_eq!(parsed, text);
+        }
+    }
+
+    #[test]
+    fn results_are_valid_json() {
+        let mut interpreted = BenchResult::new(BenchBackend::Interpreter);
+        interpreted.run_times =
+            vec![Duration::from_millis(2), Duration::from_millis(4)];
+        interpreted.instructions = Some(42);
+        interpreted.tape_cells = Some(8192);
+        let mut compiled = BenchResult::new(BenchBackend::Aot);
+        compiled.compile_time = Some(Duration::from_micros(1500));
+        compiled.run_times = vec![Duration::from_millis(1)];
+
+        let text =
+            bench_json(Some("dir \"a\"\\b\n.bf"), 2, &[interpreted, compiled]);
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        // Whole milliseconds are printed without a fraction.
+        assert_eq!(
+            parsed,
+            json!({
+                "program": "dir \"a\"\\b\n.bf",
+                "runs": 2,
+                "results": [
+                    {
+                        "backend": "interpreter",
+                        "compile_ms": null,
+                        "run_min_ms": 2,
+                        "run_mean_ms": 3,
+                        "run_ms": [2, 4],
+                        "instructions": 42,
+                        "tape_cells": 8192,
+                    },
+                    {
+                        "backend": "aot",
+                        "compile_ms": 1.5,
+                        "run_min_ms": 1,
+                        "run_mean_ms": 1,
+                        "run_ms": [1],
+                        "instructions": null,
+                        "tape_cells": null,
+                    },
+                ],
+            })
+        );
+
+        let parsed: Value =
+            serde_json::from_str(&bench_json(None, 1, &[])).unwrap();
+        assert_eq!(
+            parsed,
+            json!({ "program": null, "runs": 1, "results": [] })
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! Command line arguments, and the rewriting of the deprecated form without a
+//! subcommand into the subcommands.
+
+use anyhow::{anyhow, bail};
+use catbf::{
+    compiler::{aot, jit},
+    engine,
+    io::FromRead,
+    ir::{ParseError, Program},
+    source::{Location, Source},
+    tiered,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/// A complete brainfuck implementation: interpreter, Ahead-Of-Time (AOT)
+/// compiler and Just-In-Time (JIT) compiler.
+///
+/// The tape is "infinite" both forwards and backwards. Cells are 8-bit. Get
+/// from stdin writes to two cells: the first one is a "boolean" indicating
+/// whether a byte was read (false = EOF), the second one is the byte read.
+///
+/// Running `catbf <PATH>` without a subcommand is the same as `catbf run
+/// <PATH>`. The other options of that form are deprecated in favor of the
+/// subcommands.
+#[derive(Debug, Clone, Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub legacy: LegacyArgs,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run a program, interpreted or compiled Just-In-Time (JIT).
+    Run(RunArgs),
+    /// Compile a program Ahead-Of-Time (AOT).
+    Build(BuildArgs),
+    /// Print the intermediate representation (IR) of a program.
+    Ir(IrArgs),
+    /// Parse a program and warn about suspicious constructs, without running
+    /// it.
+    Check(CheckArgs),
+    /// Run a program interpreted, printing the state of the machine at
+    /// breakpoints and once it halts.
+    Debug(DebugArgs),
+    /// Read Brainfuck lines interactively and run each one interpreted, on a
+    /// tape kept between lines. The input of the programs is read from stdin
+    /// as well. Enter `:help` for the commands.
+    Repl(ReplArgs),
+    /// Run a program several times on each backend with the same input,
+    /// check that their outputs agree, and report how long they took.
+    Bench(BenchArgs),
+}
+
+/// Help heading of the options of the form without a subcommand, except the
+/// source, which is not deprecated.
+pub const DEPRECATED: &str = "Deprecated options";
+
+/// Options of the form without a subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct LegacyArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Print intermediate representation. Deprecated, use `catbf ir`.
+    #[arg(short = 'p', long = "print-ir", help_heading = DEPRECATED)]
+    pub print_ir: bool,
+    /// Compile the program Ahead-Of-Time (AOT) and place the artifacts into
+    /// the directory indetified by the given path. Deprecated, use `catbf
+    /// build`.
+    #[arg(short = 'o', long = "compile-to", help_heading = DEPRECATED)]
+    pub compile_aot: Option<PathBuf>,
+    #[command(flatten, next_help_heading = DEPRECATED)]
+    pub aot: AotArgs,
+    /// Compile the program Just-In-Time (JIT) and run it, or interpret it if
+    /// the target platform is not supported. Deprecated, use `catbf run
+    /// --backend jit`.
+    #[arg(short = 'j', long = "jit", conflicts_with = "force_jit")]
+    pub jit: bool,
+    /// Force Just-In-Time (JIT) compilation of the program and run it.
+    /// Deprecated, use `catbf run --backend force-jit`.
+    #[arg(short = 'J', long = "force-jit", conflicts_with = "jit")]
+    pub force_jit: bool,
+    /// Start running the program interpreted, and compile Just-In-Time (JIT)
+    /// only loops that run often. Deprecated, use `catbf run --backend
+    /// tiered`.
+    #[arg(short = 't', long = "tiered", conflicts_with_all = ["jit", "force_jit"])]
+    pub tiered: bool,
+    #[command(flatten)]
+    pub jit_options: JitArgs,
+}
+
+impl LegacyArgs {
+    /// The subcommand replacing the mode selected through flags, if a mode
+    /// other than running was selected.
+    pub fn deprecation(&self) -> Option<&'static str> {
+        if self.print_ir {
+            Some("catbf ir")
+        } else if self.compile_aot.is_some() {
+            Some("catbf build")
+        } else if self.jit || self.force_jit || self.tiered {
+            Some("catbf run --backend")
+        } else {
+            None
+        }
+    }
+
+    /// Converts this form into the equivalent subcommand.
+    pub fn into_command(self) -> Command {
+        if self.print_ir {
+            Command::Ir(IrArgs {
+                source: self.source,
+                format: IrFormat::Text,
+                opt_level: 0,
+            })
+        } else if let Some(output) = self.compile_aot {
+            Command::Build(BuildArgs {
+                source: self.source,
+                out_dir: output,
+                aot: self.aot,
+            })
+        } else {
+            let backend = if self.force_jit {
+                Backend::ForceJit
+            } else if self.jit {
+                Backend::Jit
+            } else if self.tiered {
+                Backend::Tiered
+            } else {
+                Backend::Interpreter
+            };
+            Command::Run(RunArgs {
+                source: self.source,
+                backend,
+                jit: self.jit_options,
+                max_steps: None,
+                stats: false,
+                io: IoArgs::default(),
+            })
+        }
+    }
+}
+
+/// Where the source of a program is read from.
+#[derive(Debug, Clone, Args)]
+pub struct SourceArgs {
+    /// Source file paths, concatenated into a single program. `-` reads the
+    /// source from stdin, so the input of the program must then be given by
+    /// other means.
+    #[arg(value_name = "PATH", required_unless_present = "eval")]
+    pub paths: Vec<PathBuf>,
+    /// Source code of the program, given inline instead of in files.
+    #[arg(
+        short = 'e',
+        long = "eval",
+        value_name = "CODE",
+        conflicts_with = "paths"
+    )]
+    pub eval: Option<String>,
+}
+
+impl SourceArgs {
+    /// Reads and parses the program.
+    pub fn parse(&self) -> anyhow::Result<Program> {
+        let mut readers: Vec<Box<dyn Read>> = Vec::new();
+        if let Some(code) = &self.eval {
+            readers.push(Box::new(code.as_bytes()));
+        }
+        let mut read_stdin = false;
+        for path in &self.paths {
+            if path == Path::new("-") {
+                if read_stdin {
+                    bail!("the source can only be read from stdin once");
+                }
+                read_stdin = true;
+                readers.push(Box::new(BufReader::new(io::stdin())));
+            } else {
+                let file = File::open(path).map_err(|error| {
+                    anyhow!("{}: {}", path.display(), error)
+                })?;
+                readers.push(Box::new(BufReader::new(file)));
+            }
+        }
+
+        let source = Source::concat(readers.into_iter().map(FromRead));
+        Program::parse(source).map_err(|error| match &error {
+            ParseError::UnmatchedLoopOpen(location)
+            | ParseError::UnmatchedLoopClose(location) => {
+                anyhow!("{}: {}", self.name(location.file), error)
+            },
+            ParseError::IoError(_) => error.into(),
+        })
+    }
+
+    /// Name of the file at the given index of the source.
+    pub fn name(&self, file: usize) -> String {
+        match self.paths.get(file) {
+            _ if self.eval.is_some() => "<inline>".to_owned(),
+            Some(path) if path == Path::new("-") => "<stdin>".to_owned(),
+            Some(path) => path.display().to_string(),
+            None => "<unknown>".to_owned(),
+        }
+    }
+
+    /// Name of the source for debug information, if it is a single file.
+    pub fn single_file_name(&self) -> Option<String> {
+        match &self.paths[..] {
+            [path] if path != Path::new("-") => {
+                Some(path.display().to_string())
+            },
+            _ => None,
+        }
+    }
+
+    /// Describes a location as `<file>:<line>:<column>`.
+    pub fn describe(&self, location: Location) -> String {
+        format!(
+            "{}:{}:{}",
+            self.name(location.file),
+            location.line,
+            location.column
+        )
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// How the program is run.
+    #[arg(
+        short = 'b',
+        long = "backend",
+        value_enum,
+        default_value_t = Backend::Interpreter
+    )]
+    pub backend: Backend,
+    #[command(flatten)]
+    pub jit: JitArgs,
+    /// Fail before running more than the given number of instructions.
+    /// Unsupported when compiling Just-In-Time (JIT) or Ahead-Of-Time (AOT).
+    /// A loop compiled by the tiered backend counts as a single instruction.
+    #[arg(long = "max-steps")]
+    pub max_steps: Option<u64>,
+    /// Print statistics of the run to stderr once it finishes. The
+    /// interpreter counts everything, while code compiled Just-In-Time (JIT)
+    /// only counts input, output, tape growths and time, and the tiered
+    /// backend does not count within compiled loops. Executables compiled
+    /// Ahead-Of-Time (AOT) only count output and time, including starting
+    /// them.
+    #[arg(long = "stats")]
+    pub stats: bool,
+    #[command(flatten)]
+    pub io: IoArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Interpret the program.
+    Interpreter,
+    /// Compile the program Just-In-Time (JIT) and run it. If the target
+    /// platform is not supported, this will fallback to an interpreted
+    /// execution.
+    Jit,
+    /// Compile the program Just-In-Time (JIT) and run it. If the target
+    /// platform is not supported, this will fail and the program will not be
+    /// executed.
+    ForceJit,
+    /// Start running the program interpreted, and compile Just-In-Time (JIT)
+    /// only loops that run often. If the target platform is not supported,
+    /// the whole program is interpreted.
+    Tiered,
+    /// Compile the program Ahead-Of-Time (AOT) into a temporary directory and
+    /// run the executable. An ELF executable is built if the target platform
+    /// is supported, otherwise C built by `cc`.
+    Aot,
+}
+
+impl Backend {
+    pub fn engine_backend(self) -> engine::Backend {
+        match self {
+            Self::Interpreter => engine::Backend::Interpreter,
+            Self::Jit if !jit::TARGET_SUPPORTED => engine::Backend::Interpreter,
+            Self::Jit | Self::ForceJit => engine::Backend::Jit,
+            Self::Tiered => engine::Backend::Tiered,
+            Self::Aot => engine::Backend::Aot,
+        }
+    }
+}
+
+/// Options of Just-In-Time (JIT) compilation.
+#[derive(Debug, Clone, Args)]
+pub struct JitArgs {
+    /// Number of iterations after which a loop is compiled in tiered mode.
+    #[arg(
+        long = "hot-loop-threshold",
+        default_value_t = tiered::DEFAULT_HOT_LOOP_THRESHOLD
+    )]
+    pub hot_loop_threshold: u64,
+    /// When compiling Just-In-Time (JIT), append symbols of the generated code
+    /// to /tmp/perf-<pid>.map, for profiling with `perf`.
+    #[arg(long = "perf-map")]
+    pub perf_map: bool,
+    /// When compiling Just-In-Time (JIT), register symbols and source lines of
+    /// the generated code with the GDB JIT interface.
+    #[arg(long = "gdb-jit")]
+    pub gdb_jit: bool,
+    /// When compiling Just-In-Time (JIT), map generated code twice through an
+    /// anonymous file, one writable and one executable view, instead of
+    /// changing the protection of its pages.
+    #[arg(long = "jit-dual-mapping")]
+    pub jit_dual_mapping: bool,
+    /// When compiling Just-In-Time (JIT), reuse code compiled by previous runs
+    /// from the given cache directory, and store newly compiled code there.
+    #[arg(long = "jit-cache")]
+    pub jit_cache: Option<PathBuf>,
+}
+
+/// Redirection of the input and output of a program.
+#[derive(Debug, Clone, Default, Args)]
+pub struct IoArgs {
+    /// Read the input of the program from the given file instead of stdin.
+    #[arg(short = 'i', long = "input")]
+    pub input: Option<PathBuf>,
+    /// Give the text as the input of the program instead of stdin.
+    #[arg(
+        long = "input-string",
+        value_name = "TEXT",
+        conflicts_with = "input"
+    )]
+    pub input_string: Option<String>,
+    /// Write the output of the program to the given file instead of stdout.
+    #[arg(short = 'o', long = "output")]
+    pub output: Option<PathBuf>,
+    /// Compare the output of the program with the contents of the given file,
+    /// failing with a diff if they differ. The output is then only written if
+    /// `--output` is given.
+    #[arg(long = "expect-output", value_name = "FILE")]
+    pub expect_output: Option<PathBuf>,
+}
+
+impl IoArgs {
+    /// Whether the program reads the input of the process.
+    pub fn reads_stdin(&self) -> bool {
+        self.input.is_none() && self.input_string.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct BuildArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Directory where the artifacts are placed. Unlike `run --output`, this
+    /// is not a file.
+    #[arg(
+        short = 'o',
+        long = "out-dir",
+        visible_alias = "output",
+        default_value = "build"
+    )]
+    pub out_dir: PathBuf,
+    #[command(flatten)]
+    pub aot: AotArgs,
+}
+
+/// Options of Ahead-Of-Time (AOT) compilation.
+#[derive(Debug, Clone, Args)]
+pub struct AotArgs {
+    /// Kind of artifacts produced by Ahead-Of-Time (AOT) compilation.
+    #[arg(
+        long = "format",
+        visible_alias = "aot-format",
+        value_enum,
+        default_value_t = AotFormat::Asm
+    )]
+    pub format: AotFormat,
+    /// Prefix of the symbols exported by AOT compiled objects and archives.
+    #[arg(long = "symbol-prefix", default_value = aot::DEFAULT_SYMBOL_PREFIX)]
+    pub symbol_prefix: String,
+    /// C compiler used to build AOT compiled assembly or C, possibly followed
+    /// by arguments. Defaults to $CC, then cc. Flags in $CFLAGS are also
+    /// passed.
+    #[arg(long = "cc")]
+    pub cc: Option<String>,
+    /// Remove the intermediate sources (runtime.c and prog.s, or prog.c) once
+    /// AOT compiled assembly or C is linked.
+    #[arg(long = "remove-intermediates")]
+    pub remove_intermediates: bool,
+    /// Place an AOT compiled Rust module in a Cargo crate building an
+    /// executable.
+    #[arg(long = "rust-crate")]
+    pub rust_crate: bool,
+    /// Also write an AOT compiled WebAssembly module in the text format.
+    #[arg(long = "wat")]
+    pub wat: bool,
+    /// Map AOT compiled assembly or ELF executables back to the source file,
+    /// so debuggers can break on and step through Brainfuck lines.
+    #[arg(short = 'g', long = "debug-info")]
+    pub debug_info: bool,
+    /// Target of AOT compiled assembly: x86_64-linux, x86_64-linux-intel
+    /// (Intel syntax) or x86_64-linux-freestanding (no libc).
+    #[arg(
+        long = "target",
+        visible_alias = "aot-target",
+        default_value = aot::DEFAULT_TARGET
+    )]
+    pub target: String,
+    /// Directory of a target of AOT compiled assembly, with snippets and a
+    /// target.manifest, used instead of a built-in target.
+    #[arg(long = "target-dir", visible_alias = "aot-target-dir")]
+    pub target_dir: Option<PathBuf>,
+    /// Compile the program to AOT assembly twice, with the tape growth inlined
+    /// and with it shared out of line, and report the sizes of prog.s and prog
+    /// of each. The directory is left with the latter.
+    #[arg(long = "size-report", visible_alias = "aot-size-report")]
+    pub size_report: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AotFormat {
+    /// Assembly and a C runtime, linked by `cc`.
+    Asm,
+    /// A static executable written directly, without `cc` nor libc.
+    Elf,
+    /// A relocatable object exporting the program as a C function.
+    Obj,
+    /// A static library exporting the program as a C function.
+    Lib,
+    /// Portable C source, compiled by `cc`. Works on any target.
+    C,
+    /// A Rust module exporting the program as a function. Works on any
+    /// target.
+    Rust,
+    /// LLVM IR and a C runtime, to be built with LLVM tools and `cc`.
+    Llvm,
+    /// A WebAssembly module importing input and output from the host. Works
+    /// on any target.
+    Wasm,
+}
+
+impl From<AotFormat> for aot::Format {
+    fn from(format: AotFormat) -> Self {
+        match format {
+            AotFormat::Asm => Self::Assembly,
+            AotFormat::Elf => Self::Elf,
+            AotFormat::Obj => Self::Object,
+            AotFormat::Lib => Self::Archive,
+            AotFormat::C => Self::C,
+            AotFormat::Rust => Self::Rust,
+            AotFormat::Llvm => Self::Llvm,
+            AotFormat::Wasm => Self::Wasm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct IrArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// How the IR is printed.
+    #[arg(
+        short = 'f',
+        long = "format",
+        value_enum,
+        default_value_t = IrFormat::Text
+    )]
+    pub format: IrFormat,
+    /// Optimization level: 0 keeps the IR as parsed, 1 removes loops that
+    /// never run and instructions undone by the next one.
+    #[arg(
+        short = 'O',
+        long = "opt-level",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0 ..= 1)
+    )]
+    pub opt_level: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IrFormat {
+    /// Instructions and jump labels, one per line.
+    Text,
+    /// Instructions, with loops as indented blocks.
+    Tree,
+    /// Brainfuck source equivalent to the IR.
+    Brainfuck,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Fail if there are warnings.
+    #[arg(short = 'D', long = "deny-warnings")]
+    pub deny_warnings: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct DebugArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Print the state of the machine whenever an instruction written at the
+    /// given source line, or line and column, as in LINE[:COLUMN], is about
+    /// to run. May be repeated.
+    #[arg(short = 'B', long = "break", value_parser = parse_breakpoint)]
+    pub breakpoints: Vec<Breakpoint>,
+    /// Print the state of the machine before every instruction.
+    #[arg(long = "trace")]
+    pub trace: bool,
+    /// Number of cells printed at each side of the current one.
+    #[arg(long = "tape-window", default_value_t = 4)]
+    pub tape_window: usize,
+    /// Fail before running more than the given number of instructions.
+    #[arg(long = "max-steps")]
+    pub max_steps: Option<u64>,
+    #[command(flatten)]
+    pub io: IoArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReplArgs {
+    /// Number of cells printed at each side of the current one after each
+    /// line.
+    #[arg(long = "tape-window", default_value_t = 4)]
+    pub tape_window: usize,
+    /// Stop a line before it runs more than the given number of
+    /// instructions. The tape is kept as it was then.
+    #[arg(long = "max-steps")]
+    pub max_steps: Option<u64>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    /// Backends compared, separated by commas. Defaults to every backend
+    /// supported by the target platform.
+    #[arg(short = 'b', long = "backends", value_enum, value_delimiter = ',')]
+    pub backends: Vec<BenchBackend>,
+    /// Number of times the program is run on each backend.
+    #[arg(
+        short = 'n',
+        long = "runs",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1 ..)
+    )]
+    pub runs: u32,
+    /// Read the input of every run from the given file. Runs get no input
+    /// otherwise.
+    #[arg(short = 'i', long = "input")]
+    pub input: Option<PathBuf>,
+    /// Give the text as the input of every run.
+    #[arg(
+        long = "input-string",
+        value_name = "TEXT",
+        conflicts_with = "input"
+    )]
+    pub input_string: Option<String>,
+    /// How the results are printed.
+    #[arg(
+        short = 'f',
+        long = "format",
+        value_enum,
+        default_value_t = BenchFormat::Text
+    )]
+    pub format: BenchFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BenchBackend {
+    /// The interpreter, which also counts the instructions executed.
+    Interpreter,
+    /// The interpreter compiling loops that run often Just-In-Time (JIT).
+    Tiered,
+    /// The whole program compiled Just-In-Time (JIT).
+    Jit,
+    /// An executable compiled Ahead-Of-Time (AOT), as by `run --backend aot`.
+    Aot,
+}
+
+impl BenchBackend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Interpreter => "interpreter",
+            Self::Tiered => "tiered",
+            Self::Jit => "jit",
+            Self::Aot => "aot",
+        }
+    }
+
+    pub fn engine_backend(self) -> engine::Backend {
+        match self {
+            Self::Interpreter => engine::Backend::Interpreter,
+            Self::Tiered => engine::Backend::Tiered,
+            Self::Jit => engine::Backend::Jit,
+            Self::Aot => engine::Backend::Aot,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BenchFormat {
+    /// A table.
+    Text,
+    /// A JSON object, for tracking results over time.
+    Json,
+}
+
+/// A source line, and optionally a column, where the debugger stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub line: u64,
+    pub column: Option<u64>,
+}
+
+pub fn parse_breakpoint(text: &str) -> Result<Breakpoint, String> {
+    let parse = |number: &str| {
+        number.parse::<u64>().map_err(|error| format!("{}: {}", number, error))
+    };
+    match text.split_once(':') {
+        Some((line, column)) => {
+            Ok(Breakpoint { line: parse(line)?, column: Some(parse(column)?) })
+        },
+        None => Ok(Breakpoint { line: parse(text)?, column: None }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, BenchBackend, Breakpoint, Cli, Command, IrFormat};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    /// Parses the arguments after `catbf`, returning the command run and the
+    /// replacement of the deprecated form used, if any.
+    fn parse(args: &[&str]) -> (Command, Option<&'static str>) {
+        let cli = Cli::try_parse_from(["catbf"].iter().chain(args)).unwrap();
+        match cli.command {
+            Some(command) => (command, None),
+            None => {
+                let deprecation = cli.legacy.deprecation();
+                (cli.legacy.into_command(), deprecation)
+            },
+        }
+    }
+
+    #[test]
+    fn parses_run() {
+        let (command, deprecation) = parse(&[
+            "run",
+            "--backend",
+            "tiered",
+            "--max-steps",
+            "10",
+            "--input-string",
+            "hi",
+            "-o",
+            "out.txt",
+            "prog.bf",
+        ]);
+        let Command::Run(args) = command else { panic!("{:?}", command) };
+        assert_eq!(deprecation, None);
+        assert_eq!(args.source.paths, [PathBuf::from("prog.bf")]);
+        assert_eq!(args.backend, Backend::Tiered);
+        assert_eq!(args.max_steps, Some(10));
+        assert_eq!(args.io.input_string.as_deref(), Some("hi"));
+        assert_eq!(args.io.output, Some(PathBuf::from("out.txt")));
+        assert!(!args.io.reads_stdin());
+    }
+
+    #[test]
+    fn parses_build_directory() {
+        let (command, _) = parse(&["build", "prog.bf"]);
+        let Command::Build(args) = command else { panic!("{:?}", command) };
+        assert_eq!(args.out_dir, PathBuf::from("build"));
+        for flag in ["-o", "--out-dir", "--output"] {
+            let (command, _) = parse(&["build", flag, "out", "prog.bf"]);
+            let Command::Build(args) = command else { panic!("{:?}", command) };
+            assert_eq!(args.out_dir, PathBuf::from("out"));
+        }
+    }
+
+    #[test]
+    fn parses_other_subcommands() {
+        let (command, _) =
+            parse(&["ir", "--opt-level", "1", "--format", "tree", "-e", "+"]);
+        let Command::Ir(args) = command else { panic!("{:?}", command) };
+        assert_eq!((args.opt_level, args.format), (1, IrFormat::Tree));
+        assert_eq!(args.source.eval.as_deref(), Some("+"));
+
+        let (command, _) =
+            parse(&["debug", "--break", "3", "--break", "4:2", "prog.bf"]);
+        let Command::Debug(args) = command else { panic!("{:?}", command) };
+        assert_eq!(
+            args.breakpoints,
+            [
+                Breakpoint { line: 3, column: None },
+                Breakpoint { line: 4, column: Some(2) }
+            ]
+        );
+
+        let (command, _) = parse(&["bench", "-b", "tiered,jit", "prog.bf"]);
+        let Command::Bench(args) = command else { panic!("{:?}", command) };
+        assert_eq!(args.backends, [BenchBackend::Tiered, BenchBackend::Jit]);
+
+        assert!(matches!(parse(&["check", "prog.bf"]).0, Command::Check(_)));
+        assert!(matches!(parse(&["repl"]).0, Command::Repl(_)));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for args in [
+            &["run"][..],
+            &["run", "-e", "+", "prog.bf"],
+            &["run", "--backend", "fast", "prog.bf"],
+            &["bench", "--runs", "0", "prog.bf"],
+            &["debug", "--break", "x", "prog.bf"],
+            &["-j", "-J", "prog.bf"],
+            &["run", "-j", "prog.bf"],
+        ] {
+            let result = Cli::try_parse_from(["catbf"].iter().chain(args));
+            assert!(result.is_err(), "{:?} accepted", args);
+        }
+    }
+
+    #[test]
+    fn path_alone_runs_without_warning() {
+        let (command, deprecation) = parse(&["prog.bf"]);
+        let Command::Run(args) = command else { panic!("{:?}", command) };
+        assert_eq!(deprecation, None);
+        assert_eq!(args.source.paths, [PathBuf::from("prog.bf")]);
+        assert_eq!(args.backend, Backend::Interpreter);
+    }
+
+    #[test]
+    fn legacy_flags_become_subcommands_with_warning() {
+        let (command, deprecation) = parse(&["-p", "prog.bf"]);
+        assert!(matches!(command, Command::Ir(_)), "{:?}", command);
+        assert_eq!(deprecation, Some("catbf ir"));
+
+        let (command, deprecation) =
+            parse(&["-o", "out", "--format", "elf", "prog.bf"]);
+        let Command::Build(args) = command else { panic!("{:?}", command) };
+        assert_eq!(deprecation, Some("catbf build"));
+        assert_eq!(args.out_dir, PathBuf::from("out"));
+        assert_eq!(args.source.paths, [PathBuf::from("prog.bf")]);
+
+        for (flag, backend) in [
+            ("-j", Backend::Jit),
+            ("-J", Backend::ForceJit),
+            ("-t", Backend::Tiered),
+        ] {
+            let (command, deprecation) = parse(&[flag, "prog.bf"]);
+            let Command::Run(args) = command else { panic!("{:?}", command) };
+            assert_eq!(deprecation, Some("catbf run --backend"));
+            assert_eq!(args.backend, backend);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Differences between the expected output of a program and the actual one.
+
+use std::{fmt::Write as _, path::Path};
+
+/// Describes how the actual output differs from the expected one, showing
+/// the lines between the common start and the common end of both.
+pub fn diff(path: &Path, expected: &[u8], actual: &[u8]) -> String {
+    let expected: Vec<_> =
+        expected.split_inclusive(|&byte| byte == b'\n').collect();
+    let actual: Vec<_> =
+        actual.split_inclusive(|&byte| byte == b'\n').collect();
+    let prefix = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    let suffix = expected[prefix ..]
+        .iter()
+        .rev()
+        .zip(actual[prefix ..].iter().rev())
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+
+    let mut diff = String::new();
+    writeln!(diff, "--- {}", path.display()).ok();
+    writeln!(diff, "+++ output").ok();
+    writeln!(diff, "@@ line {} @@", prefix + 1).ok();
+    let removed = &expected[prefix .. expected.len() - suffix];
+    let added = &actual[prefix .. actual.len() - suffix];
+    for (sign, lines) in [('-', removed), ('+', added)] {
+        for line in lines {
+            // Bytes that are not printable ASCII are escaped.
+            match line.strip_suffix(b"\n") {
+                Some(text) => {
+                    writeln!(diff, "{}{}", sign, text.escape_ascii()).ok()
+                },
+                None => writeln!(
+                    diff,
+                    "{}{}\n\\ No newline at end",
+                    sign,
+                    line.escape_ascii()
+                )
+                .ok(),
+            };
+        }
+    }
+    diff
+}
@@ -0,0 +1,346 @@
+mod bench;
+mod cli;
+mod diff;
+mod repl;
+
+use crate::{
+    bench::bench,
+    cli::{
+        BuildArgs, CheckArgs, Cli, Command, DebugArgs, IoArgs, IrArgs,
+        IrFormat, RunArgs, SourceArgs,
+    },
+    diff::diff,
+    repl::repl,
+};
+use anyhow::{anyhow, bail};
+use catbf::{
+    compiler::{aot, jit},
+    engine::{EngineBuilder, Limits},
+    interpreter::{Interface, Machine, Tape},
+    ir::{Instruction, Node},
+};
+use clap::Parser;
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    process,
+};
+
+fn open_input(io_args: &IoArgs) -> anyhow::Result<Box<dyn Read + Send + Sync>> {
+    Ok(match (&io_args.input, &io_args.input_string) {
+        (Some(path), _) => Box::new(BufReader::new(
+            File::open(path)
+                .map_err(|error| anyhow!("{}: {}", path.display(), error))?,
+        )),
+        (None, Some(text)) => Box::new(io::Cursor::new(text.clone())),
+        (None, None) => Box::new(io::stdin()),
+    })
+}
+
+/// Output of a program, written to a file or stdout, and captured when it is
+/// to be compared with the expected one.
+struct Output {
+    writer: Option<Box<dyn Write + Send + Sync>>,
+    captured: Option<Vec<u8>>,
+}
+
+impl Output {
+    fn open(io_args: &IoArgs) -> anyhow::Result<Self> {
+        let writer: Option<Box<dyn Write + Send + Sync>> =
+            match (&io_args.output, &io_args.expect_output) {
+                (Some(path), _) => {
+                    Some(Box::new(BufWriter::new(File::create(path).map_err(
+                        |error| anyhow!("{}: {}", path.display(), error),
+                    )?)))
+                },
+                (None, Some(_)) => None,
+                (None, None) => Some(Box::new(io::stdout())),
+            };
+        let captured = io_args.expect_output.as_ref().map(|_| Vec::new());
+        Ok(Self { writer, captured })
+    }
+
+    /// Flushes the output and compares it with the expected one, if any,
+    /// printing a diff and failing when they differ.
+    fn finish(&mut self, io_args: &IoArgs) -> anyhow::Result<()> {
+        self.flush()?;
+        let (Some(path), Some(actual)) =
+            (&io_args.expect_output, &self.captured)
+        else {
+            return Ok(());
+        };
+        let expected = fs::read(path)
+            .map_err(|error| anyhow!("{}: {}", path.display(), error))?;
+        if expected != *actual {
+            eprint!("{}", diff(path, &expected, actual));
+            bail!("output differs from {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = match &mut self.writer {
+            Some(writer) => writer.write(buf)?,
+            None => buf.len(),
+        };
+        if let Some(captured) = &mut self.captured {
+            captured.extend_from_slice(&buf[.. count]);
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn run(args: RunArgs) -> anyhow::Result<()> {
+    let program = args.source.parse()?;
+    let mut input = open_input(&args.io)?;
+    let mut output = Output::open(&args.io)?;
+
+    let backend = args.backend.engine_backend();
+    let options = jit::Options {
+        perf_map: args.jit.perf_map,
+        gdb_jit: args.jit.gdb_jit,
+        mapping_mode: if args.jit.jit_dual_mapping {
+            jit::MappingMode::DualMapping
+        } else {
+            jit::MappingMode::Protect
+        },
+        source_name: args.source.single_file_name(),
+        ..jit::Options::default()
+    };
+    let mut builder = EngineBuilder::new(backend)
+        .with_hot_loop_threshold(args.jit.hot_loop_threshold)
+        .with_jit_options(options)
+        .with_inherited_stdin(args.io.reads_stdin());
+    if let Some(max_steps) = args.max_steps {
+        builder = builder.with_max_steps(max_steps);
+    }
+    if let Some(directory) = args.jit.jit_cache {
+        builder = builder.with_jit_cache(jit::Cache::new(directory));
+    }
+    let stats = builder.build(&program)?.run(&mut *input, &mut output)?;
+
+    let result = output.finish(&args.io);
+    if args.stats {
+        eprintln!("{}", stats);
+    }
+    result
+}
+
+fn build(args: BuildArgs) -> anyhow::Result<()> {
+    let program = args.source.parse()?;
+    let options = aot::Options {
+        format: args.aot.format.into(),
+        symbol_prefix: Some(args.aot.symbol_prefix),
+        cc: args.aot.cc,
+        remove_intermediates: args.aot.remove_intermediates,
+        rust_crate: args.aot.rust_crate,
+        wasm_text: args.aot.wat,
+        source_name: args
+            .aot
+            .debug_info
+            .then(|| args.source.single_file_name())
+            .flatten(),
+        target: Some(args.aot.target),
+        target_dir: args.aot.target_dir,
+    };
+    if args.aot.size_report {
+        println!("{}", aot::compare_sizes(&program, args.out_dir, &options)?);
+    } else {
+        aot::compile_with(&program, args.out_dir, &options)?;
+    }
+    Ok(())
+}
+
+fn print_ir(args: IrArgs) -> anyhow::Result<()> {
+    let mut program = args.source.parse()?;
+    if args.opt_level >= 1 {
+        program = program.optimized()?;
+    }
+    match args.format {
+        IrFormat::Text => print!("{}", program),
+        IrFormat::Tree => {
+            let mut tree = String::new();
+            write_tree(&mut tree, &program.structured()?, 0);
+            print!("{}", tree);
+        },
+        IrFormat::Brainfuck => {
+            let source: String = program
+                .code
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Halt => None,
+                    Instruction::Inc => Some('+'),
+                    Instruction::Dec => Some('-'),
+                    Instruction::Next => Some('>'),
+                    Instruction::Prev => Some('<'),
+                    Instruction::Get => Some(','),
+                    Instruction::Put => Some('.'),
+                    Instruction::Jz(_) => Some('['),
+                    Instruction::Jnz(_) => Some(']'),
+                })
+                .collect();
+            println!("{}", source);
+        },
+    }
+    Ok(())
+}
+
+fn write_tree(tree: &mut String, nodes: &[Node], depth: usize) {
+    for node in nodes {
+        let indent = "    ".repeat(depth);
+        match node {
+            Node::Instruction { instruction, .. } => {
+                writeln!(tree, "{}{}", indent, instruction).ok();
+            },
+            Node::Loop { body, .. } => {
+                writeln!(tree, "{}loop", indent).ok();
+                write_tree(tree, body, depth + 1);
+                writeln!(tree, "{}end", indent).ok();
+            },
+        }
+    }
+}
+
+fn check(args: CheckArgs) -> anyhow::Result<()> {
+    let program = args.source.parse()?;
+    let lints = program.lint()?;
+    for lint in &lints {
+        match program.location(lint.ip()) {
+            Some(location) => eprintln!(
+                "{}: warning: {}",
+                args.source.describe(location),
+                lint
+            ),
+            None => eprintln!("warning: {}", lint),
+        }
+    }
+    if args.deny_warnings && !lints.is_empty() {
+        bail!("{} warning(s) denied", lints.len());
+    }
+    Ok(())
+}
+
+fn debug(args: DebugArgs) -> anyhow::Result<()> {
+    let program = args.source.parse()?;
+    let input = open_input(&args.io)?;
+    let mut output = Output::open(&args.io)?;
+
+    let tape = Tape::new();
+    let interface = Interface::from_std(input, &mut output);
+    let mut machine = Machine::new(program, tape, interface);
+    let limits = Limits { max_steps: args.max_steps, ..Limits::default() };
+    let mut steps = 0;
+    loop {
+        let location = machine.program().location(machine.ip());
+        let at_breakpoint = location.is_some_and(|location| {
+            args.breakpoints.iter().any(|breakpoint| {
+                breakpoint.line == location.line
+                    && breakpoint
+                        .column
+                        .is_none_or(|column| column == location.column)
+            })
+        });
+        if args.trace || at_breakpoint {
+            print_state(&machine, &args.source, steps, args.tape_window);
+        }
+        limits.check(steps, machine.is_halting(), machine.tape())?;
+        if !machine.step()? {
+            break;
+        }
+        steps += 1;
+    }
+    eprintln!("halted after {} steps", steps);
+    print_state(&machine, &args.source, steps, args.tape_window);
+
+    drop(machine);
+    output.finish(&args.io)
+}
+
+/// Prints the next instruction and the cells around the current one.
+fn print_state<I, O>(
+    machine: &Machine<I, O>,
+    source: &SourceArgs,
+    steps: u64,
+    tape_window: usize,
+) where
+    I: catbf::io::Input,
+    O: catbf::io::Output,
+{
+    let program = machine.program();
+    // Once halted, the machine is past the final `Halt`.
+    let ip = machine.ip().min(program.code.len().saturating_sub(1));
+    let instruction =
+        program.code.get(ip).copied().unwrap_or(Instruction::Halt);
+    let location = match program.location(ip) {
+        Some(location) => source.describe(location),
+        None => "an unknown location".to_owned(),
+    };
+
+    let cells =
+        describe_cells(machine.tape(), window(machine.tape(), tape_window));
+    eprintln!("step {}: `{}` at {}, {}", steps, instruction, location, cells);
+}
+
+/// Cells at each side of the current one, up to the given number.
+fn window(tape: &Tape, tape_window: usize) -> Range<usize> {
+    let cursor = tape.cursor();
+    cursor.saturating_sub(tape_window) .. cursor + tape_window + 1
+}
+
+/// Describes the cells in the given range, marking the current one.
+fn describe_cells(tape: &Tape, range: Range<usize>) -> String {
+    let end = range.end.min(tape.cells().len());
+    let start = range.start.min(end);
+    let mut cells = format!("cells from {}:", start);
+    for (i, cell) in tape.cells()[start .. end].iter().enumerate() {
+        if start + i == tape.cursor() {
+            write!(cells, " [{}]", cell).ok();
+        } else {
+            write!(cells, " {}", cell).ok();
+        }
+    }
+    cells
+}
+
+fn try_main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let command = match cli.command {
+        Some(command) => command,
+        None => {
+            if let Some(replacement) = cli.legacy.deprecation() {
+                eprintln!(
+                    "warning: modes selected by flags are deprecated, use `{}`",
+                    replacement
+                );
+            }
+            cli.legacy.into_command()
+        },
+    };
+    match command {
+        Command::Run(args) => run(args),
+        Command::Build(args) => build(args),
+        Command::Ir(args) => print_ir(args),
+        Command::Check(args) => check(args),
+        Command::Debug(args) => debug(args),
+        Command::Repl(args) => repl(args),
+        Command::Bench(args) => bench(args),
+    }
+}
+
+fn main() {
+    if let Err(error) = try_main() {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
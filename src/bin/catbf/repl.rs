@@ -0,0 +1,316 @@
+//! Interactive mode, running each line entered on a tape kept between lines.
+
+use crate::{cli::ReplArgs, describe_cells, window};
+use anyhow::{anyhow, bail};
+use catbf::{
+    engine::Limits,
+    interpreter::{Interface, Machine, Tape},
+    ir::{ParseError, Program},
+    source::Source,
+};
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    mem,
+    ops::Range,
+    path::Path,
+};
+
+/// Commands of the REPL.
+const REPL_HELP: &str = "\
+:reset          clear the tape and forget the lines run
+:tape [A..B]    print the cells from A up to B, or around the current one
+:ir             print the IR of the lines run since the last reset
+:load FILE      run the code in the file
+:save FILE      write the lines run since the last reset to the file
+:help           print this message
+:quit           leave, as does the end of input
+";
+
+/// State of the REPL kept between lines.
+struct Repl {
+    args: ReplArgs,
+    tape: Tape,
+    /// Code of the lines run successfully since the last reset.
+    history: Vec<u8>,
+    /// Lines entered while a loop is open, run once it is closed.
+    pending: Vec<u8>,
+    output: ReplOutput,
+}
+
+impl Repl {
+    fn new(args: ReplArgs) -> Self {
+        Self {
+            args,
+            tape: Tape::new(),
+            history: Vec::new(),
+            pending: Vec::new(),
+            output: ReplOutput { stdout: io::stdout(), at_line_start: true },
+        }
+    }
+
+    /// Handles a line entered, returning whether the REPL goes on.
+    fn handle(&mut self, line: &str) -> anyhow::Result<bool> {
+        if let Some(command) = line.trim().strip_prefix(':') {
+            return self.command(command);
+        }
+
+        self.pending.extend_from_slice(line.as_bytes());
+        match Program::parse(Source::new(&self.pending[..])) {
+            // Waits for continuation lines closing the loop.
+            Err(ParseError::UnmatchedLoopOpen(_)) => (),
+            Err(error) => {
+                self.pending.clear();
+                Err(error)?;
+            },
+            Ok(program) => {
+                let code = mem::take(&mut self.pending);
+                self.run(program, &code)?;
+            },
+        }
+        Ok(true)
+    }
+
+    fn command(&mut self, command: &str) -> anyhow::Result<bool> {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+        let file = || {
+            if argument.is_empty() {
+                bail!("`:{}` expects a file", name);
+            }
+            Ok(Path::new(argument))
+        };
+        match name {
+            "reset" => {
+                self.tape = Tape::new();
+                self.history.clear();
+                self.pending.clear();
+                self.print_tape(None);
+            },
+            "tape" if argument.is_empty() => self.print_tape(None),
+            "tape" => self.print_tape(Some(parse_range(argument)?)),
+            "ir" => {
+                print!("{}", Program::parse(Source::new(&self.history[..]))?)
+            },
+            "load" => {
+                let path = file()?;
+                let code = fs::read(path).map_err(|error| {
+                    anyhow!("{}: {}", path.display(), error)
+                })?;
+                let program = Program::parse(Source::new(&code[..])).map_err(
+                    |error| anyhow!("{}: {}", path.display(), error),
+                )?;
+                self.run(program, &code)?;
+            },
+            "save" => {
+                let path = file()?;
+                fs::write(path, &self.history).map_err(|error| {
+                    anyhow!("{}: {}", path.display(), error)
+                })?;
+            },
+            "help" => print!("{}", REPL_HELP),
+            "quit" => return Ok(false),
+            _ => bail!(
+                "unknown command `:{}`, enter `:help` for the commands",
+                name
+            ),
+        }
+        Ok(true)
+    }
+
+    /// Runs a program on the tape, remembering its code if it succeeds.
+    fn run(&mut self, program: Program, code: &[u8]) -> anyhow::Result<()> {
+        let tape = mem::take(&mut self.tape);
+        let interface = Interface::from_std(io::stdin(), &mut self.output);
+        let mut machine = Machine::new(program, tape, interface);
+        let limits =
+            Limits { max_steps: self.args.max_steps, ..Limits::default() };
+        let mut steps = 0;
+        let mut result = Ok(());
+        loop {
+            if let Err(error) =
+                limits.check(steps, machine.is_halting(), machine.tape())
+            {
+                result = Err(error.into());
+                break;
+            }
+            match machine.step() {
+                Ok(true) => steps += 1,
+                Ok(false) => break,
+                Err(error) => {
+                    result = Err(error.into());
+                    break;
+                },
+            }
+        }
+        self.tape = machine.into_tape();
+
+        self.output.end_line()?;
+        if result.is_ok() {
+            self.history.extend_from_slice(code);
+        }
+        self.print_tape(None);
+        result
+    }
+
+    /// Prints the cursor and the cells in the range, or around the current
+    /// one.
+    fn print_tape(&self, range: Option<Range<usize>>) {
+        let range =
+            range.unwrap_or_else(|| window(&self.tape, self.args.tape_window));
+        println!(
+            "cursor {}, {}",
+            self.tape.cursor(),
+            describe_cells(&self.tape, range)
+        );
+    }
+}
+
+/// Output of programs run by the REPL, which keeps track of whether a line
+/// was left unfinished.
+struct ReplOutput {
+    stdout: io::Stdout,
+    at_line_start: bool,
+}
+
+impl ReplOutput {
+    /// Finishes the line written by a program, if any, so that the REPL
+    /// prints on a line of its own.
+    fn end_line(&mut self) -> io::Result<()> {
+        if !self.at_line_start {
+            self.write_all(b"\n")?;
+        }
+        self.flush()
+    }
+}
+
+impl Write for ReplOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.stdout.write(buf)?;
+        if let Some(&last) = buf[.. count].last() {
+            self.at_line_start = last == b'\n';
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+fn parse_range(text: &str) -> anyhow::Result<Range<usize>> {
+    let parse = |number: &str| {
+        number
+            .trim()
+            .parse::<usize>()
+            .map_err(|error| anyhow!("{}: {}", number, error))
+    };
+    let Some((start, end)) = text.split_once("..") else {
+        bail!("expected a range START..END, found `{}`", text);
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        bail!("the range {}..{} ends before it starts", start, end);
+    }
+    Ok(start .. end)
+}
+
+pub fn repl(args: ReplArgs) -> anyhow::Result<()> {
+    let stdin = io::stdin();
+    // Prompts are left out when lines come from a file or a pipe.
+    let interactive = stdin.is_terminal();
+    let mut repl = Repl::new(args);
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("{}", if repl.pending.is_empty() { "bf> " } else { "... " });
+            io::stdout().flush()?;
+        }
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            break;
+        }
+        match repl.handle(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, Repl};
+    use crate::cli::ReplArgs;
+
+    fn repl(max_steps: Option<u64>) -> Repl {
+        Repl::new(ReplArgs { tape_window: 2, max_steps })
+    }
+
+    #[test]
+    fn unbalanced_lines_wait_for_continuation() {
+        let mut repl = repl(None);
+        assert!(repl.handle("+++[>++\n").unwrap());
+        assert_eq!(repl.pending, b"+++[>++\n");
+        assert!(repl.history.is_empty());
+        assert_eq!(repl.tape.cells()[.. 2], [0, 0]);
+
+        assert!(repl.handle("[-]+<-]\n").unwrap());
+        assert!(repl.pending.is_empty());
+        assert_eq!(repl.history, b"+++[>++\n[-]+<-]\n");
+        assert_eq!(repl.tape.cells()[.. 2], [0, 1]);
+    }
+
+    #[test]
+    fn state_is_carried_between_lines() {
+        let mut repl = repl(None);
+        repl.handle("+++>").unwrap();
+        repl.handle("++").unwrap();
+        repl.handle("[<+>-]<").unwrap();
+        assert_eq!(repl.tape.cells()[.. 2], [5, 0]);
+        assert_eq!(repl.tape.cursor(), 0);
+        assert_eq!(repl.history, b"+++>++[<+>-]<");
+
+        repl.handle(":reset").unwrap();
+        assert_eq!(repl.tape.cells()[0], 0);
+        assert!(repl.history.is_empty());
+    }
+
+    #[test]
+    fn failed_lines_are_forgotten() {
+        let mut repl = repl(Some(3));
+        assert!(repl.handle("]").is_err());
+        assert!(repl.pending.is_empty());
+
+        repl.handle("++").unwrap();
+        // Stopped by the step limit, with the tape kept as it was then.
+        assert!(repl.handle("+>++").is_err());
+        assert_eq!(repl.tape.cells()[.. 2], [3, 1]);
+        assert_eq!(repl.history, b"++");
+    }
+
+    #[test]
+    fn handles_commands() {
+        let mut repl = repl(None);
+        assert!(repl.handle(":tape 0..3").unwrap());
+        // Bounds past the tape print the cells that exist.
+        assert!(repl.handle(":tape 8190..9000").unwrap());
+        assert!(repl.handle(":tape 3..1").is_err());
+        assert!(repl.handle(":load").is_err());
+        assert!(repl.handle(":frobnicate").is_err());
+        assert!(!repl.handle(" :quit\n").unwrap());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("2..5").unwrap(), 2 .. 5);
+        assert_eq!(parse_range(" 1 .. 3 ").unwrap(), 1 .. 3);
+        assert_eq!(parse_range("4..4").unwrap(), 4 .. 4);
+        for text in ["5..2", "3", "a..2", "1..b", "-1..2", "1..2..3", ".."] {
+            assert!(parse_range(text).is_err(), "{} accepted", text);
+        }
+    }
+}
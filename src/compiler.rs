@@ -1,2 +1,8 @@
 pub mod aot;
+pub mod c;
+pub mod llvm;
+pub mod rust;
+pub mod wasm;
 pub mod jit;
+mod elf;
+mod dwarf;
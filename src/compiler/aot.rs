@@ -1,15 +1,107 @@
-use crate::ir::{Instruction, Program};
+use crate::{
+    compiler::{c, jit, llvm, rust, wasm},
+    ir::{Instruction, Program, StructureError},
+};
 use std::{
+    env,
+    fmt::{self, Write as _},
     fs,
     io::{self, Write},
+    os::unix::fs::PermissionsExt,
     path::PathBuf,
     process::{Command, Stdio},
 };
 use thiserror::Error;
 
+mod elf;
+mod freestanding;
+mod library;
+mod target;
+
+pub use self::{
+    library::DEFAULT_SYMBOL_PREFIX,
+    target::{Target, BUILTIN_TARGETS, DEFAULT_TARGET},
+};
+
 pub const TARGET_SUPPORTED: bool =
     cfg!(all(target_os = "linux", target_arch = "x86_64"));
 
+/// Kind of artifacts produced by Ahead-Of-Time compilation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Format {
+    /// Assembly and a C runtime, linked into the executable `prog` by `cc`.
+    #[default]
+    Assembly,
+    /// A static executable `prog` written directly, which needs neither a C
+    /// toolchain to be built nor libc to run.
+    Elf,
+    /// A relocatable object `prog.o` exporting the program as the C function
+    /// `<prefix>main`, and its header `prog.h`.
+    Object,
+    /// Like `Object`, but the object is in the static archive `libprog.a`.
+    Archive,
+    /// Portable C source `prog.c`, compiled into the executable `prog` by
+    /// `cc`. Supported on every target.
+    C,
+    /// Rust module `prog.rs` exporting the program as the function `run`.
+    /// Supported on every target.
+    Rust,
+    /// LLVM IR `prog.ll` defining `catbf_main`, and the C runtime
+    /// `runtime.c` it calls, to be built with LLVM tools and `cc`.
+    Llvm,
+    /// WebAssembly module `prog.wasm` exporting the program as `main`, with
+    /// input and output imported from the host. Supported on every target.
+    Wasm,
+}
+
+impl Format {
+    /// Whether artifacts of this format can be produced on any target.
+    pub fn is_portable(self) -> bool {
+        matches!(self, Self::C | Self::Rust | Self::Llvm | Self::Wasm)
+    }
+}
+
+/// Options of Ahead-Of-Time compilation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    pub format: Format,
+    /// Prefix of the symbols of objects and archives, so that several
+    /// programs can be linked together. Defaults to `DEFAULT_SYMBOL_PREFIX`.
+    pub symbol_prefix: Option<String>,
+    /// C compiler linking assembly, possibly followed by arguments. Defaults
+    /// to `$CC`, then `cc`. Flags in `$CFLAGS` are always passed.
+    pub cc: Option<String>,
+    /// Removes the sources given to the C compiler once `prog` is
+    /// successfully linked.
+    pub remove_intermediates: bool,
+    /// Places the Rust module in a crate scaffold instead: `Cargo.toml`,
+    /// `src/main.rs` and `src/prog.rs`, building the executable `prog`.
+    pub rust_crate: bool,
+    /// Also writes the WebAssembly module in the text format, `prog.wat`.
+    pub wasm_text: bool,
+    /// Name of the source file. When given, assembly and ELF executables
+    /// carry line information mapping their code back to it, for debuggers.
+    pub source_name: Option<String>,
+    /// Built-in target of assembly, one of `BUILTIN_TARGETS`. Defaults to
+    /// `DEFAULT_TARGET`.
+    pub target: Option<String>,
+    /// Directory of a target of assembly outside of the compiler, taking
+    /// precedence over `target`. It may be for any platform, provided `cc`
+    /// builds for it.
+    pub target_dir: Option<PathBuf>,
+}
+
+impl Options {
+    /// Loads the target of assembly given by these options.
+    pub fn load_target(&self) -> Result<Target, Error> {
+        match (&self.target_dir, &self.target) {
+            (Some(directory), _) => Target::load(directory),
+            (None, Some(name)) => Target::builtin(name),
+            (None, None) => Target::builtin(DEFAULT_TARGET),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("target is unsupported for Ahead-Of-Time compilation")]
@@ -18,53 +110,253 @@ pub enum Error {
     BadLabelIndex(usize),
     #[error("{}: {}", .0.display(), .1)]
     Io(PathBuf, io::Error),
+    #[error("{}", .0)]
+    CodeGen(#[from] jit::Error),
+    #[error("{}", .0)]
+    Structure(#[from] StructureError),
+    #[error("unknown target {:?}, expected one of {}", .0, BUILTIN_TARGETS.join(", "))]
+    UnknownTarget(String),
+    #[error("missing target file {}", .0)]
+    MissingTargetFile(String),
+    #[error("{}:{}: {}", .manifest, .line, .message)]
+    BadManifest { manifest: String, line: usize, message: String },
+    #[error("unknown placeholder {{{}}} in {}", .placeholder, .snippet)]
+    UnknownPlaceholder { snippet: String, placeholder: String },
+    #[error("symbol prefix {:?} is not a valid C identifier", .0)]
+    BadSymbolPrefix(String),
+    #[error("could not run C compiler `{}`: {}", .0, .1)]
+    CompilerNotRun(String, io::Error),
+    #[error(
+        "linking failed with {}: `{}`\n{}",
+        match .status {
+            Some(code) => format!("exit code {}", code),
+            None => "a signal".to_owned(),
+        },
+        .command,
+        .stderr
+    )]
+    LinkFailed { command: String, status: Option<i32>, stderr: String },
 }
 
 pub fn compile<P>(program: &Program, directory: P) -> Result<(), Error>
+where
+    P: Into<PathBuf>,
+{
+    compile_with(program, directory, &Options::default())
+}
+
+/// Compiles an executable `prog` into the directory, as ELF where the target
+/// is supported and built from C by `cc` elsewhere.
+pub fn compile_executable<P>(
+    program: &Program,
+    directory: P,
+) -> Result<(), Error>
+where
+    P: Into<PathBuf>,
+{
+    let format = if TARGET_SUPPORTED { Format::Elf } else { Format::C };
+    compile_with(program, directory, &Options { format, ..Options::default() })
+}
+
+pub fn compile_with<P>(
+    program: &Program,
+    directory: P,
+    options: &Options,
+) -> Result<(), Error>
 where
     P: Into<PathBuf>,
 {
     let mut path = directory.into();
 
-    if !TARGET_SUPPORTED {
+    // Targets of assembly in a directory may be for any platform.
+    let external_target =
+        options.format == Format::Assembly && options.target_dir.is_some();
+    if !TARGET_SUPPORTED && !options.format.is_portable() && !external_target {
         Err(Error::UnsupportedTarget)?;
     }
 
     fs::create_dir_all(&path)
         .map_err(|error| Error::Io(path.clone(), error))?;
 
-    generate_runtime_source(&mut path)?;
+    match options.format {
+        Format::Assembly => {
+            let target = options.load_target()?;
+
+            generate_runtime_source(&target, &mut path)?;
+
+            generate_prog_asm(
+                program,
+                &target,
+                options.source_name.as_deref(),
+                false,
+                &mut path,
+            )?;
+
+            let mut sources: Vec<_> = target.runtime().collect();
+            sources.push("prog.s");
+            link(&mut path, &sources, target.cflags(), options)?;
+
+            if options.remove_intermediates {
+                remove_intermediates(&mut path, &sources)?;
+            }
+        },
+        Format::C => {
+            generate_prog_c(program, &mut path)?;
+
+            let sources = ["prog.c"];
+            link(&mut path, &sources, &[], options)?;
+
+            if options.remove_intermediates {
+                remove_intermediates(&mut path, &sources)?;
+            }
+        },
+        Format::Rust => generate_prog_rust(program, options, &mut path)?,
+        Format::Wasm => generate_prog_wasm(program, options, &mut path)?,
+        Format::Llvm => {
+            // The C runtime is portable, any platform with `cc` runs it.
+            let target = Target::builtin(DEFAULT_TARGET)?;
+            generate_runtime_source(&target, &mut path)?;
+
+            generate_prog_llvm(program, &mut path)?;
+        },
+        Format::Elf => generate_prog_elf(
+            program,
+            options.source_name.as_deref(),
+            &mut path,
+        )?,
+        Format::Object | Format::Archive => {
+            let prefix = options
+                .symbol_prefix
+                .as_deref()
+                .unwrap_or(DEFAULT_SYMBOL_PREFIX);
+            generate_prog_library(program, prefix, options.format, &mut path)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Builds the program as assembly twice, first with the tape growth expanded
+/// at every instruction, as earlier versions did, then with shared slow paths,
+/// and reports the sizes of `prog.s` and `prog` of each. The artifacts left in
+/// the directory are the ones of the latter. The format in `options` is
+/// ignored.
+pub fn compare_sizes<P>(
+    program: &Program,
+    directory: P,
+    options: &Options,
+) -> Result<SizeComparison, Error>
+where
+    P: Into<PathBuf>,
+{
+    let mut path = directory.into();
+
+    let target = options.load_target()?;
+    if !TARGET_SUPPORTED && target.is_builtin() {
+        Err(Error::UnsupportedTarget)?;
+    }
 
-    generate_prog_asm(program, &mut path)?;
+    fs::create_dir_all(&path)
+        .map_err(|error| Error::Io(path.clone(), error))?;
 
-    link(&mut path)?;
+    generate_runtime_source(&target, &mut path)?;
+
+    let mut sources: Vec<_> = target.runtime().collect();
+    sources.push("prog.s");
+    let mut sizes = [Sizes::default(); 2];
+    for (inline_growth, sizes) in [true, false].into_iter().zip(&mut sizes) {
+        generate_prog_asm(
+            program,
+            &target,
+            options.source_name.as_deref(),
+            inline_growth,
+            &mut path,
+        )?;
+        link(&mut path, &sources, target.cflags(), options)?;
+        sizes.assembly = file_size(&mut path, "prog.s")?;
+        sizes.binary = file_size(&mut path, "prog")?;
+    }
 
-    Ok(())
+    if options.remove_intermediates {
+        remove_intermediates(&mut path, &sources)?;
+    }
+
+    let [inline, shared] = sizes;
+    Ok(SizeComparison { inline, shared })
 }
 
-fn link(path: &mut PathBuf) -> Result<(), Error> {
-    let mut command = Command::new("cc");
-    path.push("runtime.c");
-    command.arg(&path);
-    path.pop();
-    path.push("prog.s");
-    command.arg(&path);
+/// Sizes in bytes of the artifacts of assembly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Sizes {
+    /// Size of `prog.s`.
+    pub assembly: u64,
+    /// Size of the linked `prog`.
+    pub binary: u64,
+}
+
+/// Sizes of assembly before and after sharing the tape growth out of line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SizeComparison {
+    /// With the tape growth expanded at every `>`, `<` and `,`.
+    pub inline: Sizes,
+    /// With the tape growth in shared slow paths.
+    pub shared: Sizes,
+}
+
+impl fmt::Display for SizeComparison {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        let change = |before: u64, after: u64| {
+            (after as f64 - before as f64) / before.max(1) as f64 * 100.0
+        };
+        writeln!(fmtr, "{:<8} {:>12} {:>12}", "", "prog.s", "prog")?;
+        for (name, sizes) in [("inline", self.inline), ("shared", self.shared)]
+        {
+            writeln!(
+                fmtr,
+                "{:<8} {:>12} {:>12}",
+                name, sizes.assembly, sizes.binary
+            )?;
+        }
+        write!(
+            fmtr,
+            "{:<8} {:>+11.1}% {:>+11.1}%",
+            "change",
+            change(self.inline.assembly, self.shared.assembly),
+            change(self.inline.binary, self.shared.binary)
+        )
+    }
+}
+
+fn file_size(path: &mut PathBuf, name: &str) -> Result<u64, Error> {
+    path.push(name);
+    let size = fs::metadata(&path)
+        .map_err(|error| Error::Io(path.clone(), error))?
+        .len();
     path.pop();
-    command.arg("-o");
+    Ok(size)
+}
+
+fn generate_prog_elf(
+    program: &Program,
+    source_name: Option<&str>,
+    path: &mut PathBuf,
+) -> Result<(), Error> {
     path.push("prog");
-    command.arg(&path);
+
+    fs::write(&path, freestanding::executable(program, source_name)?)
+        .map_err(|error| Error::Io(path.clone(), error))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        .map_err(|error| Error::Io(path.clone(), error))?;
+
     path.pop();
-    command.stdout(Stdio::inherit());
-    let mut spawned =
-        command.spawn().map_err(|error| Error::Io(path.clone(), error))?;
-    spawned.wait().map_err(|error| Error::Io(path.clone(), error))?;
+
     Ok(())
 }
 
-fn generate_runtime_source(path: &mut PathBuf) -> Result<(), Error> {
-    path.push("runtime.c");
+fn generate_prog_c(program: &Program, path: &mut PathBuf) -> Result<(), Error> {
+    path.push("prog.c");
 
-    fs::write(&path, include_str!("../../resources/x86_64/linux/runtime.c"))
+    fs::write(&path, c::generate(program)?)
         .map_err(|error| Error::Io(path.clone(), error))?;
 
     path.pop();
@@ -72,119 +364,398 @@ fn generate_runtime_source(path: &mut PathBuf) -> Result<(), Error> {
     Ok(())
 }
 
-fn generate_prog_asm(
+fn generate_prog_llvm(
     program: &Program,
     path: &mut PathBuf,
 ) -> Result<(), Error> {
-    path.push("prog.s");
+    path.push("prog.ll");
 
-    let mut prog_file = fs::File::create(&path)
+    fs::write(&path, llvm::generate(program)?)
         .map_err(|error| Error::Io(path.clone(), error))?;
 
-    prog_file
-        .write_all(include_bytes!("../../resources/x86_64/linux/preamble.s"))
-        .map_err(|error| Error::Io(path.clone(), error))?;
+    path.pop();
+
+    Ok(())
+}
+
+fn generate_prog_wasm(
+    program: &Program,
+    options: &Options,
+    path: &mut PathBuf,
+) -> Result<(), Error> {
+    let module = wasm::generate(program)?;
 
-    prog_file
-        .write_all(include_bytes!("../../resources/x86_64/linux/enter.s"))
+    path.push("prog.wasm");
+    fs::write(&path, module.encode())
         .map_err(|error| Error::Io(path.clone(), error))?;
+    path.pop();
 
-    for (i, instruction) in program.code.iter().copied().enumerate() {
-        write!(prog_file, ".label_{}:\n", i)
+    if options.wasm_text {
+        path.push("prog.wat");
+        fs::write(&path, module.to_string())
             .map_err(|error| Error::Io(path.clone(), error))?;
+        path.pop();
+    }
 
-        match instruction {
-            Instruction::Halt => {
-                prog_file
-                    .write_all(include_bytes!(
-                        "../../resources/x86_64/linux/halt.s"
-                    ))
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+    Ok(())
+}
 
-            Instruction::Inc => {
-                prog_file
-                    .write_all(include_bytes!(
-                        "../../resources/x86_64/linux/inc.s"
-                    ))
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+fn generate_prog_rust(
+    program: &Program,
+    options: &Options,
+    path: &mut PathBuf,
+) -> Result<(), Error> {
+    let source = rust::generate(program)?;
 
-            Instruction::Dec => {
-                prog_file
-                    .write_all(include_bytes!(
-                        "../../resources/x86_64/linux/dec.s"
-                    ))
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+    if options.rust_crate {
+        path.push("Cargo.toml");
+        fs::write(&path, rust::manifest("prog"))
+            .map_err(|error| Error::Io(path.clone(), error))?;
+        path.pop();
 
-            Instruction::Next => {
-                let label = format!(".growed_next_{}", i);
-                let content =
-                    include_str!("../../resources/x86_64/linux/next.s")
-                        .replace(".growed_next", &label);
-                prog_file
-                    .write_all(content.as_bytes())
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+        path.push("src");
+        fs::create_dir_all(&path)
+            .map_err(|error| Error::Io(path.clone(), error))?;
 
-            Instruction::Prev => {
-                let label = format!(".growed_prev_{}", i);
-                let content =
-                    include_str!("../../resources/x86_64/linux/prev.s")
-                        .replace(".growed_prev", &label);
-                prog_file
-                    .write_all(content.as_bytes())
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+        path.push("main.rs");
+        fs::write(&path, rust::main_source())
+            .map_err(|error| Error::Io(path.clone(), error))?;
+        path.pop();
+    }
 
-            Instruction::Get => {
-                let label = format!(".get_growed_next_{}", i);
-                let content =
-                    include_str!("../../resources/x86_64/linux/get.s")
-                        .replace(".get_growed_next", &label);
-                prog_file
-                    .write_all(content.as_bytes())
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+    path.push("prog.rs");
+    fs::write(&path, source).map_err(|error| Error::Io(path.clone(), error))?;
+    path.pop();
 
-            Instruction::Put => {
-                prog_file
-                    .write_all(include_bytes!(
-                        "../../resources/x86_64/linux/put.s"
-                    ))
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+    if options.rust_crate {
+        path.pop();
+    }
 
-            Instruction::Jz(to_i) => {
-                let label = format!(".label_{}", to_i);
-                let content = include_str!("../../resources/x86_64/linux/jz.s")
-                    .replace(".jz_label", &label);
-                prog_file
-                    .write_all(content.as_bytes())
-                    .map_err(|error| Error::Io(path.clone(), error))?;
-            },
+    Ok(())
+}
+
+fn link(
+    path: &mut PathBuf,
+    sources: &[&str],
+    args: &[String],
+    options: &Options,
+) -> Result<(), Error> {
+    let cc = match &options.cc {
+        Some(cc) => cc.clone(),
+        None => env::var("CC")
+            .ok()
+            .filter(|cc| !cc.trim().is_empty())
+            .unwrap_or_else(|| "cc".to_owned()),
+    };
+    let cflags = env::var("CFLAGS").unwrap_or_default();
+    let mut words = cc.split_whitespace().chain(cflags.split_whitespace());
+    let program = words.next().unwrap_or("cc");
+
+    let mut command = Command::new(program);
+    command.args(words);
+    command.args(args);
+    for source in sources {
+        path.push(source);
+        command.arg(&path);
+        path.pop();
+    }
+    let prog_path = path.join("prog");
+    command.arg("-o");
+    command.arg(&prog_path);
+
+    // A failed link must not leave a previous `prog` behind.
+    match fs::remove_file(&prog_path) {
+        Ok(()) => (),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => Err(Error::Io(prog_path, error))?,
+    }
+
+    let command_line = command_line(&command);
+    command.stdout(Stdio::inherit());
+    let output = command
+        .output()
+        .map_err(|error| Error::CompilerNotRun(command_line.clone(), error))?;
+    if !output.status.success() {
+        Err(Error::LinkFailed {
+            command: command_line,
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr)
+                .trim_end()
+                .to_owned(),
+        })?;
+    }
+    // Warnings are still shown when linking succeeds.
+    io::stderr().write_all(&output.stderr).ok();
+
+    Ok(())
+}
+
+fn command_line(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().into_owned();
+    for arg in command.get_args() {
+        write!(line, " {}", arg.to_string_lossy()).ok();
+    }
+    line
+}
+
+fn remove_intermediates(
+    path: &mut PathBuf,
+    names: &[&str],
+) -> Result<(), Error> {
+    for name in names {
+        path.push(name);
+        fs::remove_file(&path)
+            .map_err(|error| Error::Io(path.clone(), error))?;
+        path.pop();
+    }
+    Ok(())
+}
+
+fn generate_prog_library(
+    program: &Program,
+    prefix: &str,
+    format: Format,
+    path: &mut PathBuf,
+) -> Result<(), Error> {
+    let object = library::object(program, prefix)?;
+
+    if format == Format::Archive {
+        path.push("libprog.a");
+        fs::write(&path, library::archive("prog.o", &object, prefix))
+            .map_err(|error| Error::Io(path.clone(), error))?;
+    } else {
+        path.push("prog.o");
+        fs::write(&path, object)
+            .map_err(|error| Error::Io(path.clone(), error))?;
+    }
+    path.pop();
+
+    path.push("prog.h");
+    fs::write(&path, library::header(prefix))
+        .map_err(|error| Error::Io(path.clone(), error))?;
+    path.pop();
+
+    Ok(())
+}
+
+fn generate_runtime_source(
+    target: &Target,
+    path: &mut PathBuf,
+) -> Result<(), Error> {
+    for name in target.runtime() {
+        path.push(name);
+        fs::write(&path, target.render_runtime(name))
+            .map_err(|error| Error::Io(path.clone(), error))?;
+        path.pop();
+    }
+
+    Ok(())
+}
+
+/// Generates `prog.s` from the snippets of the target. Unless `inline_growth`
+/// is set, runs of instructions are merged, and the tape growth is moved out
+/// of line, into slow paths that call shared subroutines, keeping only a
+/// comparison and a jump inline.
+fn generate_prog_asm(
+    program: &Program,
+    target: &Target,
+    source_name: Option<&str>,
+    inline_growth: bool,
+    path: &mut PathBuf,
+) -> Result<(), Error> {
+    let mut asm = String::new();
+    let mut slow_paths = String::new();
+
+    asm.push_str(&target.render("preamble", &[]));
+    asm.push_str(&target.render("enter", &[]));
+
+    if let Some(source_name) = source_name {
+        let file = escape_asm_string(source_name);
+        asm.push_str(&target.render("file", &[("file", &file)]));
+    }
+
+    let mut i = 0;
+    while i < program.code.len() {
+        let instruction = program.code[i];
+        let mut count = 1;
+        if !inline_growth
+            && matches!(
+                instruction,
+                Instruction::Inc
+                    | Instruction::Dec
+                    | Instruction::Next
+                    | Instruction::Prev
+            )
+        {
+            // Jumps never target the middle of a run, they land right after
+            // another jump.
+            while program.code.get(i + count) == Some(&instruction) {
+                count += 1;
+            }
+        }
 
-            Instruction::Jnz(to_i) => {
-                let label = format!(".label_{}", to_i);
-                let content =
-                    include_str!("../../resources/x86_64/linux/jnz.s")
-                        .replace(".jnz_label", &label);
-                prog_file
-                    .write_all(content.as_bytes())
-                    .map_err(|error| Error::Io(path.clone(), error))?;
+        asm.push_str(&target.render("label", &[("id", &i)]));
+
+        if let (Some(_), Some(location)) = (source_name, program.location(i)) {
+            asm.push_str(&target.render(
+                "loc",
+                &[("line", &location.line), ("column", &location.column)],
+            ));
+        }
+
+        let (role, slow_role) = match instruction {
+            Instruction::Halt => ("halt", None),
+            Instruction::Inc if count == 1 => ("inc", None),
+            Instruction::Inc => ("add", None),
+            Instruction::Dec if count == 1 => ("dec", None),
+            Instruction::Dec => ("sub", None),
+            Instruction::Next if inline_growth => ("next", None),
+            Instruction::Next => ("next_fast", Some("next_slow")),
+            Instruction::Prev if inline_growth => ("prev", None),
+            Instruction::Prev => ("prev_fast", Some("prev_slow")),
+            Instruction::Get if inline_growth => ("get", None),
+            Instruction::Get => ("get_fast", Some("get_slow")),
+            Instruction::Put => ("put", None),
+            Instruction::Jz(_) => ("jz", None),
+            Instruction::Jnz(_) => ("jnz", None),
+        };
+        let jump_to = match instruction {
+            Instruction::Jz(to_i) | Instruction::Jnz(to_i) => to_i,
+            _ => 0,
+        };
+        let cell_count = count as u8;
+        // Snippets only use the placeholders of their role.
+        let placeholders: [(&str, &dyn fmt::Display); 3] = [
+            ("id", &i),
+            // Cells wrap around, so only the count modulo 256 matters to them.
+            match instruction {
+                Instruction::Inc | Instruction::Dec => ("count", &cell_count),
+                _ => ("count", &count),
             },
+            ("target", &jump_to),
+        ];
+        asm.push_str(&target.render(role, &placeholders));
+        if let Some(slow_role) = slow_role {
+            slow_paths.push_str(&target.render(slow_role, &placeholders));
         }
+
+        i += count;
     }
 
-    write!(prog_file, ".label_{}:\n", program.code.len())
-        .map_err(|error| Error::Io(path.clone(), error))?;
+    let end = program.code.len();
+    asm.push_str(&target.render("label", &[("id", &end)]));
+    asm.push_str(&target.render("leave", &[]));
 
-    prog_file
-        .write_all(include_bytes!("../../resources/x86_64/linux/leave.s"))
-        .map_err(|error| Error::Io(path.clone(), error))?;
+    if !slow_paths.is_empty() {
+        asm.push_str(&slow_paths);
+        asm.push_str(&target.render("grow", &[]));
+    }
 
+    path.push("prog.s");
+    fs::write(&path, asm).map_err(|error| Error::Io(path.clone(), error))?;
     path.pop();
 
     Ok(())
 }
+
+/// Escapes a string to be quoted in assembly.
+fn escape_asm_string(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for ch in string.chars() {
+        match ch {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            },
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{link, Error, Options};
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf, process};
+
+    /// A fresh directory holding a fake C compiler running the given script.
+    fn fake_cc(name: &str, script: &str) -> (PathBuf, Options) {
+        let directory = std::env::temp_dir().join(format!(
+            "catbf-link-{}-{}",
+            name,
+            process::id()
+        ));
+        fs::remove_dir_all(&directory).ok();
+        fs::create_dir_all(&directory).unwrap();
+        let cc = directory.join("cc");
+        fs::write(&cc, format!("#!/bin/sh\n{}", script)).unwrap();
+        fs::set_permissions(&cc, fs::Permissions::from_mode(0o755)).unwrap();
+        let options = Options {
+            cc: Some(cc.display().to_string()),
+            ..Options::default()
+        };
+        (directory, options)
+    }
+
+    #[test]
+    fn link_succeeds() {
+        let (directory, options) = fake_cc(
+            "success",
+            r#"
+            while [ $# -gt 0 ]; do
+                if [ "$1" = -o ]; then shift; echo linked > "$1"; fi
+                shift
+            done
+            "#,
+        );
+        let mut path = directory.clone();
+        link(&mut path, &["prog.s"], &[], &options).unwrap();
+        assert_eq!(path, directory);
+        let prog = fs::read_to_string(directory.join("prog"));
+        fs::remove_dir_all(&directory).ok();
+        assert_eq!(prog.unwrap(), "linked\n");
+    }
+
+    #[test]
+    fn link_keeps_the_path_when_prog_cannot_be_removed() {
+        let (directory, options) = fake_cc("unremovable", "exit 0\n");
+        fs::create_dir(directory.join("prog")).unwrap();
+        let mut path = directory.clone();
+        let result = link(&mut path, &["prog.s"], &[], &options);
+        fs::remove_dir_all(&directory).ok();
+
+        let Err(Error::Io(prog, _)) = result else {
+            panic!("expected an I/O error, got {:?}", result);
+        };
+        assert_eq!(prog, directory.join("prog"));
+        assert_eq!(path, directory);
+    }
+
+    #[test]
+    fn link_fails() {
+        let (directory, options) = fake_cc(
+            "failure",
+            "echo 'undefined reference to main' >&2\nexit 3\n",
+        );
+        fs::write(directory.join("prog"), "stale").unwrap();
+        let mut path = directory.clone();
+        let result = link(&mut path, &["prog.s"], &[], &options);
+        let stale = directory.join("prog").exists();
+        fs::remove_dir_all(&directory).ok();
+
+        let Err(Error::LinkFailed { command, status, stderr }) = result else {
+            panic!("expected a link failure, got {:?}", result);
+        };
+        let cc = options.cc.unwrap();
+        assert!(command.starts_with(&cc));
+        assert!(
+            command.contains(&directory.join("prog.s").display().to_string())
+        );
+        assert!(command
+            .ends_with(&format!(" -o {}", directory.join("prog").display())));
+        assert_eq!(status, Some(3));
+        assert_eq!(stderr, "undefined reference to main");
+        assert!(!stale);
+    }
+}
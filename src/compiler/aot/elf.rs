@@ -0,0 +1,100 @@
+//! Layout of the code of ELF files compiled Ahead-Of-Time: runtime stubs
+//! followed by the program, shared by executables and relocatable objects.
+
+use crate::compiler::jit::Function;
+
+/// Alignment of each function in the code.
+pub const CODE_ALIGN: usize = 16;
+
+/// A function placed in the code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placed {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Code with the runtime stubs and the program placed in it.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub text: Vec<u8>,
+    /// Runtime stubs, in the order of `Function::ALL`.
+    pub stubs: Vec<Placed>,
+    pub main: Placed,
+}
+
+impl Layout {
+    /// Appends a stub for each runtime function, then the code of the
+    /// program, each aligned to `CODE_ALIGN` and named with the prefix. The
+    /// stub is given the offset where it is placed.
+    pub fn new<F>(
+        mut text: Vec<u8>,
+        prefix: &str,
+        code: &[u8],
+        mut stub: F,
+    ) -> Self
+    where
+        F: FnMut(Function, usize) -> Vec<u8>,
+    {
+        let mut stubs = Vec::with_capacity(Function::ALL.len());
+        for function in Function::ALL {
+            let offset = align(&mut text);
+            let code = stub(function, offset);
+            stubs.push(Placed {
+                name: format!("{}{}", prefix, function.name()),
+                offset,
+                size: code.len(),
+            });
+            text.extend_from_slice(&code);
+        }
+
+        let offset = align(&mut text);
+        let main = Placed {
+            name: format!("{}main", prefix),
+            offset,
+            size: code.len(),
+        };
+        text.extend_from_slice(code);
+        Self { text, stubs, main }
+    }
+}
+
+/// Pads the code with `int3` up to `CODE_ALIGN`, returning its new length.
+fn align(text: &mut Vec<u8>) -> usize {
+    text.resize(text.len().next_multiple_of(CODE_ALIGN), 0xcc);
+    text.len()
+}
+
+/// Reads the symbols of an ELF file written by `compiler::elf`.
+#[cfg(test)]
+pub fn read_symbols(file: &[u8]) -> Vec<(String, u8)> {
+    use crate::compiler::elf::SHT_SYMTAB;
+
+    let u32_at = |pos: usize| {
+        u32::from_le_bytes(file[pos .. pos + 4].try_into().unwrap())
+    };
+    let u64_at = |pos: usize| {
+        u64::from_le_bytes(file[pos .. pos + 8].try_into().unwrap()) as usize
+    };
+    let section_headers = u64_at(0x28);
+    let sections = u16::from_le_bytes([file[0x3c], file[0x3d]]) as usize;
+    let header = |index: usize| section_headers + index * 64;
+    let symtab = (0 .. sections)
+        .map(header)
+        .find(|&header| u32_at(header + 4) == SHT_SYMTAB)
+        .expect("no symbol table");
+    let strtab = header(u32_at(symtab + 40) as usize);
+    let (strings, symbols) = (u64_at(strtab + 24), u64_at(symtab + 24));
+
+    (symbols .. symbols + u64_at(symtab + 32))
+        .step_by(24)
+        .skip(1)
+        .map(|symbol| {
+            let start = strings + u32_at(symbol) as usize;
+            let len =
+                file[start ..].iter().position(|&byte| byte == 0).unwrap();
+            let name = String::from_utf8(file[start .. start + len].to_vec());
+            (name.unwrap(), file[symbol + 4] >> 4)
+        })
+        .collect()
+}
@@ -0,0 +1,243 @@
+//! Static executables independent of libc: machine code generated by the JIT
+//! compiler, linked against a tiny runtime doing raw Linux system calls.
+
+use super::{
+    elf::{Layout, Placed},
+    Error,
+};
+use crate::{
+    compiler::{
+        elf::{self, Object, Section},
+        jit::{self, DebugInfo, Function, TAPE_CHUNK_SIZE},
+    },
+    ir::Program,
+};
+
+/// Address where the code is loaded. Its offset in the file is the same
+/// modulo the page size.
+const TEXT_ADDRESS: u64 = 0x401000;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Offset in the entry point of the 32-bit displacement of the call to the
+/// program, and the offset it is relative to.
+const ENTRY_CALL_DISPLACEMENT: usize = 3;
+const ENTRY_CALL_END: usize = 7;
+
+/// Generates the contents of a static executable running the program, with
+/// line information mapping the code back to the source file, if its name is
+/// given.
+pub fn executable(
+    program: &Program,
+    source_name: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+    let artifact = jit::generate(program, &jit::Options::default())?;
+
+    let entry = entry();
+    let entry_size = entry.len();
+    let Layout { mut text, stubs, main } =
+        Layout::new(entry, "catbf_", &artifact.code, |function, _| {
+            runtime_stub(function)
+        });
+    let main_offset = main.offset;
+
+    let displacement = (main_offset - ENTRY_CALL_END) as u32;
+    text[ENTRY_CALL_DISPLACEMENT .. ENTRY_CALL_END]
+        .copy_from_slice(&displacement.to_le_bytes());
+    for relocation in &artifact.relocations {
+        let offset = main_offset + relocation.offset;
+        let address =
+            TEXT_ADDRESS + stubs[relocation.function as usize].offset as u64;
+        text[offset .. offset + 8].copy_from_slice(&address.to_le_bytes());
+    }
+
+    let mut object = Object::new(elf::ET_EXEC);
+    let mut section = Section::new(".text", elf::SHT_PROGBITS, text);
+    section.flags = elf::SHF_ALLOC | elf::SHF_EXECINSTR;
+    section.addr = TEXT_ADDRESS;
+    section.align = PAGE_SIZE;
+    let text_index = object.add_section(section);
+    object.add_segment(text_index, elf::PF_R | elf::PF_X);
+    object.set_entry(TEXT_ADDRESS);
+    if let Some(source_name) = source_name {
+        let debug_info = DebugInfo::collect(
+            &artifact.ir_offsets,
+            artifact.code.len(),
+            program,
+        );
+        debug_info.add_line_info(
+            &mut object,
+            source_name,
+            TEXT_ADDRESS + main_offset as u64,
+            artifact.code.len(),
+        );
+    }
+    let start =
+        Placed { name: "_start".to_owned(), offset: 0, size: entry_size };
+    for placed in [start].into_iter().chain(stubs).chain([main]) {
+        object.add_symbol(elf::Symbol {
+            name: placed.name,
+            value: TEXT_ADDRESS + placed.offset as u64,
+            size: placed.size as u64,
+            section: text_index,
+            bind: elf::STB_GLOBAL,
+            kind: elf::STT_FUNC,
+        });
+    }
+
+    Ok(object.finish())
+}
+
+/// Calls the program with a null interface and exits with status 1 if it
+/// failed, 0 otherwise.
+fn entry() -> Vec<u8> {
+    vec![
+        0x31, 0xff, // xor edi, edi
+        0xe8, 0, 0, 0, 0, // call catbf_main
+        0x0f, 0xbe, 0xf8, // movsx edi, al
+        0xf7, 0xdf, // neg edi
+        0xb8, 0xe7, 0, 0, 0, // mov eax, SYS_exit_group
+        0x0f, 0x05, // syscall
+    ]
+}
+
+/// Machine code of the freestanding version of a runtime function. Input and
+/// output use the standard file descriptors, ignoring the interface, and the
+/// tape is an anonymous mapping grown with `mremap`.
+fn runtime_stub(function: Function) -> Vec<u8> {
+    let chunk = (TAPE_CHUNK_SIZE as u32).to_le_bytes();
+    let chunk_minus_1 = (TAPE_CHUNK_SIZE as u32 - 1).to_le_bytes();
+    match function {
+        Function::CreateTape => [
+            &[0x31, 0xff][..], // xor edi, edi
+            &[0xbe],           // mov esi, TAPE_CHUNK_SIZE
+            &chunk,
+            &[0xba, 0x03, 0, 0, 0], // mov edx, PROT_READ | PROT_WRITE
+            // mov r10d, MAP_PRIVATE | MAP_ANONYMOUS
+            &[0x41, 0xba, 0x22, 0, 0, 0],
+            &[0x49, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff], // mov r8, -1
+            &[0x45, 0x31, 0xc9],                         // xor r9d, r9d
+            &[0xb8, 0x09, 0, 0, 0],                      // mov eax, SYS_mmap
+            &[0x0f, 0x05],                               // syscall
+            &[0x48, 0x3d, 0x01, 0xf0, 0xff, 0xff],       // cmp rax, -4095
+            &[0x73, 0x01],                               // jae .failure
+            &[0xc3],                                     // ret
+            // .failure:
+            &[0x31, 0xc0], // xor eax, eax
+            &[0xc3],       // ret
+        ]
+        .concat(),
+
+        // The kernel unmaps the tape on exit.
+        Function::DestroyTape => vec![0xc3],
+
+        Function::GrowNext => [
+            &[0x48, 0x8d, 0x96][..], // lea rdx, [rsi + TAPE_CHUNK_SIZE]
+            &chunk,
+            &[0x41, 0xba, 0x01, 0, 0, 0], // mov r10d, MREMAP_MAYMOVE
+            &[0xb8, 0x19, 0, 0, 0],       // mov eax, SYS_mremap
+            &[0x0f, 0x05],                // syscall
+            &[0x48, 0x3d, 0x01, 0xf0, 0xff, 0xff], // cmp rax, -4095
+            &[0x73, 0x01],                // jae .failure
+            &[0xc3],                      // ret
+            &[0x31, 0xc0],                // .failure: xor eax, eax
+            &[0xc3],                      // ret
+        ]
+        .concat(),
+
+        Function::GrowPrev => [
+            &[0x49, 0x89, 0xf1][..], // mov r9, rsi
+            &[0x48, 0x8d, 0x96],     // lea rdx, [rsi + TAPE_CHUNK_SIZE]
+            &chunk,
+            &[0x41, 0xba, 0x01, 0, 0, 0], // mov r10d, MREMAP_MAYMOVE
+            &[0xb8, 0x19, 0, 0, 0],       // mov eax, SYS_mremap
+            &[0x0f, 0x05],                // syscall
+            &[0x48, 0x3d, 0x01, 0xf0, 0xff, 0xff], // cmp rax, -4095
+            &[0x73, 0x27],                // jae .failure
+            &[0x48, 0x89, 0xc2],          // mov rdx, rax
+            &[0x4a, 0x8d, 0x74, 0x08, 0xff], // lea rsi, [rax + r9 - 1]
+            // lea rdi, [rax + r9 + TAPE_CHUNK_SIZE - 1]
+            &[0x4a, 0x8d, 0xbc, 0x08],
+            &chunk_minus_1,
+            &[0x4c, 0x89, 0xc9], // mov rcx, r9
+            &[0xfd],             // std
+            &[0xf3, 0xa4],       // rep movsb
+            &[0xfc],             // cld
+            &[0x48, 0x89, 0xd7], // mov rdi, rdx
+            &[0xb9],             // mov ecx, TAPE_CHUNK_SIZE
+            &chunk,
+            &[0x31, 0xc0],       // xor eax, eax
+            &[0xf3, 0xaa],       // rep stosb
+            &[0x48, 0x89, 0xd0], // mov rax, rdx
+            &[0xc3],             // ret
+            &[0x31, 0xc0],       // .failure: xor eax, eax
+            &[0xc3],             // ret
+        ]
+        .concat(),
+
+        Function::Get => vec![
+            0x50, // push rax
+            0x48, 0x89, 0xe6, // mov rsi, rsp
+            0x31, 0xff, // .retry: xor edi, edi
+            0xba, 0x01, 0, 0, 0, // mov edx, 1
+            0x31, 0xc0, // xor eax, eax
+            0x0f, 0x05, // syscall
+            0x48, 0x83, 0xf8, 0xfc, // cmp rax, -EINTR
+            0x74, 0xef, // je .retry
+            0x48, 0x85, 0xc0, // test rax, rax
+            0x78, 0x0c, // js .failure
+            0x74, 0x08, // je .done
+            0xb8, 0x00, 0x01, 0, 0, // mov eax, 0x100
+            0x8a, 0x04, 0x24, // mov al, [rsp]
+            0x5a, // .done: pop rdx
+            0xc3, // ret
+            0x5a, // .failure: pop rdx
+            0xb8, 0xff, 0xff, 0xff, 0xff, // mov eax, -1
+            0xc3, // ret
+        ],
+
+        Function::Put => vec![
+            0x56, // push rsi
+            0x48, 0x89, 0xe6, // mov rsi, rsp
+            0xbf, 0x01, 0, 0, 0, // .retry: mov edi, 1
+            0xba, 0x01, 0, 0, 0, // mov edx, 1
+            0xb8, 0x01, 0, 0, 0, // mov eax, SYS_write
+            0x0f, 0x05, // syscall
+            0x48, 0x83, 0xf8, 0xfc, // cmp rax, -EINTR
+            0x74, 0xe9, // je .retry
+            0x5e, // pop rsi
+            0x48, 0xff, 0xc8, // dec rax
+            0x48, 0xc1, 0xf8, 0x3f, // sar rax, 63
+            0xc3, // ret
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::executable;
+    use crate::compiler::{aot::elf::read_symbols, elf};
+
+    #[test]
+    fn executable_has_runtime_symbols() {
+        let executable = executable(&",[.,]".parse().unwrap(), None).unwrap();
+        let symbols = read_symbols(&executable);
+        for name in [
+            "_start",
+            "catbf_main",
+            "catbf_create_tape",
+            "catbf_destroy_tape",
+            "catbf_grow_next",
+            "catbf_grow_prev",
+            "catbf_get",
+            "catbf_put",
+        ] {
+            assert!(
+                symbols.contains(&(name.to_owned(), elf::STB_GLOBAL)),
+                "{} missing from {:?}",
+                name,
+                symbols
+            );
+        }
+    }
+}
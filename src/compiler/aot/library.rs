@@ -0,0 +1,341 @@
+//! Relocatable objects and static archives exporting the program as a C
+//! function, `<prefix>main`, to be linked into other programs. Input and
+//! output go through callbacks of the caller, described in the generated
+//! header, and the tape is allocated with the C library.
+
+use super::{
+    elf::{Layout, CODE_ALIGN},
+    Error,
+};
+use crate::{
+    compiler::{
+        elf::{self, Object, Section},
+        jit::{self, CallKind, Function, TAPE_CHUNK_SIZE},
+    },
+    ir::Program,
+};
+
+/// Prefix of symbols used when none is given.
+pub const DEFAULT_SYMBOL_PREFIX: &str = "catbf_";
+
+/// Machine code of a runtime function, with calls to C library functions.
+#[derive(Debug, Clone, Default)]
+struct Stub {
+    code: Vec<u8>,
+    /// Offset of the 32-bit displacement and name of each called function.
+    calls: Vec<(usize, &'static str)>,
+}
+
+impl Stub {
+    fn write(mut self, bytes: &[u8]) -> Self {
+        self.code.extend_from_slice(bytes);
+        self
+    }
+
+    /// Writes a `call` or `jmp` with a 32-bit displacement to an external
+    /// function.
+    fn call(mut self, opcode: u8, function: &'static str) -> Self {
+        self.code.push(opcode);
+        self.calls.push((self.code.len(), function));
+        self.code.extend_from_slice(&[0; 4]);
+        self
+    }
+}
+
+/// Checks that the prefix can start a C identifier.
+pub fn validate_prefix(prefix: &str) -> Result<(), Error> {
+    let valid = !prefix.starts_with(|ch: char| ch.is_ascii_digit())
+        && prefix.chars().all(|ch| ch == '_' || ch.is_ascii_alphanumeric());
+    if !valid {
+        Err(Error::BadSymbolPrefix(prefix.to_owned()))?;
+    }
+    Ok(())
+}
+
+/// Generates a relocatable object exporting the program as `<prefix>main`.
+pub fn object(program: &Program, prefix: &str) -> Result<Vec<u8>, Error> {
+    validate_prefix(prefix)?;
+    let artifact = jit::generate_with_calls(
+        program,
+        &jit::Options::default(),
+        CallKind::Relative,
+    )?;
+
+    let mut relocations = Vec::new();
+    let layout =
+        Layout::new(Vec::new(), prefix, &artifact.code, |function, at| {
+            let stub = runtime_stub(function);
+            for (offset, symbol) in stub.calls {
+                relocations.push(elf::Relocation {
+                    offset: (at + offset) as u64,
+                    symbol: symbol.to_owned(),
+                    kind: elf::R_X86_64_PLT32,
+                    addend: -4,
+                });
+            }
+            stub.code
+        });
+    let Layout { mut text, stubs, main } = layout;
+    for relocation in &artifact.relocations {
+        let offset = main.offset + relocation.offset;
+        let target = stubs[relocation.function as usize].offset;
+        let displacement = target.wrapping_sub(offset + 4) as u32;
+        text[offset .. offset + 4].copy_from_slice(&displacement.to_le_bytes());
+    }
+
+    let mut object = Object::new(elf::ET_REL);
+    let mut section = Section::new(".text", elf::SHT_PROGBITS, text);
+    section.flags = elf::SHF_ALLOC | elf::SHF_EXECINSTR;
+    section.align = CODE_ALIGN as u64;
+    let text_index = object.add_section(section);
+    // Tells linkers the code does not need an executable stack.
+    object.add_section(Section::new(
+        ".note.GNU-stack",
+        elf::SHT_PROGBITS,
+        Vec::new(),
+    ));
+    let symbols = stubs
+        .into_iter()
+        .map(|stub| (stub, elf::STB_LOCAL))
+        .chain([(main, elf::STB_GLOBAL)]);
+    for (placed, bind) in symbols {
+        object.add_symbol(elf::Symbol {
+            name: placed.name,
+            value: placed.offset as u64,
+            size: placed.size as u64,
+            section: text_index,
+            bind,
+            kind: elf::STT_FUNC,
+        });
+    }
+    for name in ["calloc", "free", "realloc", "memmove", "memset"] {
+        object.add_symbol(elf::Symbol {
+            name: name.to_owned(),
+            value: 0,
+            size: 0,
+            section: elf::SHN_UNDEF,
+            bind: elf::STB_GLOBAL,
+            kind: elf::STT_NOTYPE,
+        });
+    }
+    object.add_relocations(text_index, relocations);
+
+    Ok(object.finish())
+}
+
+/// Machine code of a runtime function. Input and output tail-call the
+/// callbacks of the interface with its data, and the tape is allocated with
+/// the C library.
+fn runtime_stub(function: Function) -> Stub {
+    let chunk = (TAPE_CHUNK_SIZE as u32).to_le_bytes();
+    match function {
+        Function::CreateTape => Stub::default()
+            .write(&[0xbf]) // mov edi, TAPE_CHUNK_SIZE
+            .write(&chunk)
+            .write(&[0xbe, 0x01, 0, 0, 0]) // mov esi, 1
+            .call(0xe9, "calloc"), // jmp calloc
+
+        Function::DestroyTape => {
+            Stub::default().call(0xe9, "free") // jmp free
+        },
+
+        Function::GrowNext => Stub::default()
+            .write(&[0x53]) // push rbx
+            .write(&[0x41, 0x54]) // push r12
+            .write(&[0x48, 0x83, 0xec, 0x08]) // sub rsp, 8
+            .write(&[0x48, 0x89, 0xf3]) // mov rbx, rsi
+            .write(&[0x48, 0x8d, 0xb6]) // lea rsi, [rsi + TAPE_CHUNK_SIZE]
+            .write(&chunk)
+            .call(0xe8, "realloc") // call realloc
+            .write(&[0x48, 0x85, 0xc0]) // test rax, rax
+            .write(&[0x74, 0x16]) // je .done
+            .write(&[0x49, 0x89, 0xc4]) // mov r12, rax
+            .write(&[0x48, 0x8d, 0x3c, 0x18]) // lea rdi, [rax + rbx]
+            .write(&[0x31, 0xf6]) // xor esi, esi
+            .write(&[0xba]) // mov edx, TAPE_CHUNK_SIZE
+            .write(&chunk)
+            .call(0xe8, "memset") // call memset
+            .write(&[0x4c, 0x89, 0xe0]) // mov rax, r12
+            .write(&[0x48, 0x83, 0xc4, 0x08]) // .done: add rsp, 8
+            .write(&[0x41, 0x5c]) // pop r12
+            .write(&[0x5b]) // pop rbx
+            .write(&[0xc3]), // ret
+
+        Function::GrowPrev => Stub::default()
+            .write(&[0x53]) // push rbx
+            .write(&[0x41, 0x54]) // push r12
+            .write(&[0x48, 0x83, 0xec, 0x08]) // sub rsp, 8
+            .write(&[0x48, 0x89, 0xf3]) // mov rbx, rsi
+            .write(&[0x48, 0x8d, 0xb6]) // lea rsi, [rsi + TAPE_CHUNK_SIZE]
+            .write(&chunk)
+            .call(0xe8, "realloc") // call realloc
+            .write(&[0x48, 0x85, 0xc0]) // test rax, rax
+            .write(&[0x74, 0x27]) // je .done
+            .write(&[0x49, 0x89, 0xc4]) // mov r12, rax
+            .write(&[0x48, 0x8d, 0xb8]) // lea rdi, [rax + TAPE_CHUNK_SIZE]
+            .write(&chunk)
+            .write(&[0x48, 0x89, 0xc6]) // mov rsi, rax
+            .write(&[0x48, 0x89, 0xda]) // mov rdx, rbx
+            .call(0xe8, "memmove") // call memmove
+            .write(&[0x4c, 0x89, 0xe7]) // mov rdi, r12
+            .write(&[0x31, 0xf6]) // xor esi, esi
+            .write(&[0xba]) // mov edx, TAPE_CHUNK_SIZE
+            .write(&chunk)
+            .call(0xe8, "memset") // call memset
+            .write(&[0x4c, 0x89, 0xe0]) // mov rax, r12
+            .write(&[0x48, 0x83, 0xc4, 0x08]) // .done: add rsp, 8
+            .write(&[0x41, 0x5c]) // pop r12
+            .write(&[0x5b]) // pop rbx
+            .write(&[0xc3]), // ret
+
+        Function::Get => Stub::default()
+            .write(&[0x48, 0x8b, 0x47, 0x08]) // mov rax, [rdi + 8]
+            .write(&[0x48, 0x8b, 0x3f]) // mov rdi, [rdi]
+            .write(&[0xff, 0xe0]), // jmp rax
+
+        Function::Put => Stub::default()
+            .write(&[0x48, 0x8b, 0x47, 0x10]) // mov rax, [rdi + 16]
+            .write(&[0x48, 0x8b, 0x3f]) // mov rdi, [rdi]
+            .write(&[0xff, 0xe0]), // jmp rax
+    }
+}
+
+/// Generates a static archive holding the given object, with an index of the
+/// symbol `<prefix>main` so linkers find it.
+pub fn archive(object_name: &str, object: &[u8], prefix: &str) -> Vec<u8> {
+    let symbol = format!("{}main", prefix);
+
+    let mut index = Vec::new();
+    index.extend_from_slice(&1u32.to_be_bytes());
+    // Patched once the offset of the object member is known.
+    index.extend_from_slice(&0u32.to_be_bytes());
+    index.extend_from_slice(symbol.as_bytes());
+    index.push(0);
+    let object_offset = 8 + 60 + index.len().next_multiple_of(2);
+    index[4 .. 8].copy_from_slice(&(object_offset as u32).to_be_bytes());
+
+    let mut buf = b"!<arch>\n".to_vec();
+    push_archive_member(&mut buf, "/", &index);
+    push_archive_member(&mut buf, &format!("{}/", object_name), object);
+    buf
+}
+
+fn push_archive_member(buf: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        name,
+        0,
+        0,
+        0,
+        644,
+        data.len()
+    );
+    buf.extend_from_slice(header.as_bytes());
+    buf.extend_from_slice(data);
+    if !data.len().is_multiple_of(2) {
+        buf.push(b'\n');
+    }
+}
+
+/// Generates a C header declaring `<prefix>main` and the interface it takes.
+pub fn header(prefix: &str) -> String {
+    let guard = format!("{}MAIN_H", prefix.to_ascii_uppercase());
+    format!(
+        r#"/* Generated by catbf {version}. */
+#ifndef {guard}
+#define {guard}
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {{
+#endif
+
+#ifndef CATBF_INTERFACE_DEFINED
+#define CATBF_INTERFACE_DEFINED
+
+/* Input and output of compiled Brainfuck programs. */
+struct catbf_interface {{
+    /* Passed as is to the callbacks. */
+    void *data;
+    /*
+     * Reads a byte, returning (1 << 8) | byte, 0 at the end of the input, or
+     * a negative number on error.
+     */
+    int16_t (*get)(void *data);
+    /* Writes a byte, returning 0, or a negative number on error. */
+    int8_t (*put)(void *data, uint8_t byte);
+}};
+
+#endif
+
+/*
+ * Runs the program, returning 0, or a negative number if a callback or memory
+ * allocation failed. Safe to call concurrently with different interfaces.
+ */
+int8_t {prefix}main(struct catbf_interface *interface);
+
+#ifdef __cplusplus
+}}
+#endif
+
+#endif
+"#,
+        version = env!("CARGO_PKG_VERSION"),
+        guard = guard,
+        prefix = prefix,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{archive, object, validate_prefix};
+    use crate::compiler::{aot::elf::read_symbols, elf};
+
+    #[test]
+    fn validates_prefixes() {
+        for prefix in ["", "catbf_", "_", "bf2_", "MyProgram"] {
+            assert!(validate_prefix(prefix).is_ok(), "{:?} rejected", prefix);
+        }
+        for prefix in ["2bf_", "bf-", "bf.", "bf ", "é_"] {
+            assert!(validate_prefix(prefix).is_err(), "{:?} accepted", prefix);
+            assert!(object(&"+.".parse().unwrap(), prefix).is_err());
+        }
+    }
+
+    #[test]
+    fn object_symbols_are_prefixed() {
+        let object = object(&",[.,]".parse().unwrap(), "hello_").unwrap();
+        let symbols = read_symbols(&object);
+        for (name, bind) in [
+            ("hello_main", elf::STB_GLOBAL),
+            ("hello_create_tape", elf::STB_LOCAL),
+            ("hello_get", elf::STB_LOCAL),
+            ("hello_put", elf::STB_LOCAL),
+            ("calloc", elf::STB_GLOBAL),
+        ] {
+            assert!(
+                symbols.contains(&(name.to_owned(), bind)),
+                "{} missing from {:?}",
+                name,
+                symbols
+            );
+        }
+        assert!(!symbols.iter().any(|(name, _)| name.starts_with("catbf_")));
+    }
+
+    #[test]
+    fn archive_indexes_the_prefixed_main() {
+        let object = object(&"+.".parse().unwrap(), "hello_").unwrap();
+        let archive = archive("prog.o", &object, "hello_");
+        assert!(archive.starts_with(b"!<arch>\n/               "));
+        // The index has one symbol, the offset of its member, and its name.
+        let index = &archive[8 + 60 ..];
+        assert_eq!(index[.. 4], 1u32.to_be_bytes());
+        assert_eq!(&index[8 .. 19], b"hello_main\0");
+        let member = u32::from_be_bytes(index[4 .. 8].try_into().unwrap());
+        let member = &archive[member as usize ..];
+        assert!(member.starts_with(b"prog.o/ "));
+        assert_eq!(member[60 ..][.. object.len()], object[..]);
+    }
+}
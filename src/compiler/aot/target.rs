@@ -0,0 +1,472 @@
+//! Descriptions of the targets of assembly: a directory of snippets, one per
+//! role, and a manifest `target.manifest` declaring constants, runtime files
+//! and flags of the C compiler. Snippets and runtime files may use constants
+//! and, for snippets, the placeholders of their role, written as `{name}`.
+//!
+//! Manifest lines are blank, comments starting with `#`, or one of:
+//!
+//! - `inherit = <name>`: takes whatever is not given from a built-in target;
+//! - `const <NAME> = <value>`: declares a constant;
+//! - `runtime = <file>`: adds a runtime file, replacing inherited ones;
+//! - `cflags = <flags>`: flags of the C compiler, replacing inherited ones.
+
+use super::Error;
+use std::{collections::HashMap, fmt, fs, io, path::PathBuf};
+
+/// Target used when none is given.
+pub const DEFAULT_TARGET: &str = "x86_64-linux";
+
+/// Names of the targets built into the compiler.
+pub const BUILTIN_TARGETS: &[&str] =
+    &["x86_64-linux", "x86_64-linux-intel", "x86_64-linux-freestanding"];
+
+/// Name of the manifest in a target directory.
+const MANIFEST: &str = "target.manifest";
+
+/// Extension of snippet files.
+const SNIPPET_EXTENSION: &str = "s";
+
+/// Roles of snippets, with the placeholders each one may use.
+const SNIPPETS: &[(&str, &[&str])] = &[
+    ("preamble", &[]),
+    ("enter", &[]),
+    ("leave", &[]),
+    ("file", &["file"]),
+    ("label", &["id"]),
+    ("loc", &["line", "column"]),
+    ("halt", &[]),
+    ("inc", &[]),
+    ("dec", &[]),
+    ("add", &["count"]),
+    ("sub", &["count"]),
+    ("next", &["id"]),
+    ("prev", &["id"]),
+    ("get", &["id"]),
+    ("next_fast", &["id", "count"]),
+    ("next_slow", &["id", "count"]),
+    ("prev_fast", &["id", "count"]),
+    ("prev_slow", &["id", "count"]),
+    ("get_fast", &["id"]),
+    ("get_slow", &["id"]),
+    ("put", &[]),
+    ("jz", &["target"]),
+    ("jnz", &["target"]),
+    ("grow", &[]),
+];
+
+macro_rules! builtin_files {
+    ($dir:literal: $($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!(
+            "../../../resources/x86_64/",
+            $dir,
+            "/",
+            $name
+        )))),*]
+    };
+}
+
+/// Files of each built-in target.
+const BUILTIN_FILES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "x86_64-linux",
+        builtin_files!("linux":
+            "target.manifest", "runtime.c", "preamble.s", "enter.s",
+            "leave.s", "file.s", "label.s", "loc.s", "halt.s", "inc.s",
+            "dec.s", "add.s", "sub.s", "next.s", "prev.s", "get.s",
+            "next_fast.s", "next_slow.s", "prev_fast.s", "prev_slow.s",
+            "get_fast.s", "get_slow.s", "put.s", "jz.s", "jnz.s", "grow.s",
+        ),
+    ),
+    (
+        "x86_64-linux-intel",
+        builtin_files!("linux-intel":
+            "target.manifest", "preamble.s", "enter.s", "leave.s", "inc.s",
+            "dec.s", "add.s", "sub.s", "next.s", "prev.s", "get.s",
+            "next_fast.s", "next_slow.s", "prev_fast.s", "prev_slow.s",
+            "get_fast.s", "put.s", "jz.s", "jnz.s", "grow.s",
+        ),
+    ),
+    (
+        "x86_64-linux-freestanding",
+        builtin_files!("linux-freestanding": "target.manifest", "runtime.s"),
+    ),
+];
+
+/// Where the files of a target are read from.
+#[derive(Debug, Clone, Copy)]
+enum Location<'dir> {
+    Builtin(&'static [(&'static str, &'static str)]),
+    Directory(&'dir PathBuf),
+}
+
+impl<'dir> Location<'dir> {
+    fn read(&self, name: &str) -> Result<Option<String>, Error> {
+        match self {
+            Self::Builtin(files) => Ok(files
+                .iter()
+                .find(|(file_name, _)| *file_name == name)
+                .map(|(_, contents)| (*contents).to_owned())),
+            Self::Directory(directory) => {
+                let path = directory.join(name);
+                match fs::read_to_string(&path) {
+                    Ok(contents) => Ok(Some(contents)),
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                        Ok(None)
+                    },
+                    Err(error) => Err(Error::Io(path, error)),
+                }
+            },
+        }
+    }
+
+    fn describe(&self, target: &str, name: &str) -> String {
+        match self {
+            Self::Builtin(_) => format!("{}/{}", target, name),
+            Self::Directory(directory) => {
+                directory.join(name).display().to_string()
+            },
+        }
+    }
+}
+
+/// A target of assembly, with its snippets and runtime loaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    name: String,
+    builtin: bool,
+    constants: Vec<(String, String)>,
+    runtime: Vec<String>,
+    cflags: Vec<String>,
+    files: HashMap<String, String>,
+}
+
+impl Target {
+    /// Loads a target built into the compiler, one of `BUILTIN_TARGETS`.
+    pub fn builtin(name: &str) -> Result<Self, Error> {
+        let (name, files) = BUILTIN_FILES
+            .iter()
+            .find(|(target_name, _)| *target_name == name)
+            .ok_or_else(|| Error::UnknownTarget(name.to_owned()))?;
+        let mut target = Self::load_from(name, Location::Builtin(files))?;
+        target.builtin = true;
+        Ok(target)
+    }
+
+    /// Loads a target from a directory of snippets with a manifest.
+    pub fn load<P>(directory: P) -> Result<Self, Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let directory = directory.into();
+        let name = directory.display().to_string();
+        Self::load_from(&name, Location::Directory(&directory))
+    }
+
+    /// Name of the target, or the path of its directory.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is one of `BUILTIN_TARGETS`, which build for x86-64
+    /// Linux only.
+    pub fn is_builtin(&self) -> bool {
+        self.builtin
+    }
+
+    /// Names of the runtime files compiled along with `prog.s`.
+    pub fn runtime(&self) -> impl Iterator<Item = &str> {
+        self.runtime.iter().map(String::as_str)
+    }
+
+    /// Flags given to the C compiler.
+    pub fn cflags(&self) -> &[String] {
+        &self.cflags
+    }
+
+    /// Contents of a runtime file, with constants replaced.
+    pub(super) fn render_runtime(&self, name: &str) -> String {
+        self.substitute(&self.files[name], &[])
+    }
+
+    /// Contents of the snippet of the given role, with constants and
+    /// placeholders replaced.
+    pub(super) fn render(
+        &self,
+        role: &str,
+        placeholders: &[(&str, &dyn fmt::Display)],
+    ) -> String {
+        let file_name = format!("{}.{}", role, SNIPPET_EXTENSION);
+        self.substitute(&self.files[&file_name], placeholders)
+    }
+
+    fn substitute(
+        &self,
+        template: &str,
+        placeholders: &[(&str, &dyn fmt::Display)],
+    ) -> String {
+        let mut rendered = template.to_owned();
+        // Constants go first, values of placeholders such as the source name
+        // must be left as they are.
+        for (name, value) in &self.constants {
+            rendered = rendered.replace(&format!("{{{}}}", name), value);
+        }
+        for (name, value) in placeholders {
+            rendered =
+                rendered.replace(&format!("{{{}}}", name), &value.to_string());
+        }
+        rendered
+    }
+
+    fn load_from(name: &str, location: Location) -> Result<Self, Error> {
+        let manifest_name = location.describe(name, MANIFEST);
+        let manifest = location
+            .read(MANIFEST)?
+            .ok_or_else(|| Error::MissingTargetFile(manifest_name.clone()))?;
+
+        let mut target = Self { name: name.to_owned(), ..Self::default() };
+        let mut runtime = None;
+        let mut cflags = None;
+        let mut constants = Vec::new();
+
+        for (i, line) in manifest.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = |message: &str| Error::BadManifest {
+                manifest: manifest_name.clone(),
+                line: i + 1,
+                message: message.to_owned(),
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| bad_line("expected `key = value`"))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key.split_whitespace().collect::<Vec<_>>()[..] {
+                ["inherit"] => {
+                    let base = Self::builtin(value)?;
+                    target.constants = base.constants;
+                    target.runtime = base.runtime;
+                    target.cflags = base.cflags;
+                    target.files = base.files;
+                },
+                ["const", constant] if is_identifier(constant) => {
+                    constants.push((constant.to_owned(), value.to_owned()));
+                },
+                ["const", _] => Err(bad_line("bad constant name"))?,
+                ["runtime"] => {
+                    runtime.get_or_insert_with(Vec::new).push(value.to_owned())
+                },
+                ["cflags"] => {
+                    cflags = Some(
+                        value.split_whitespace().map(str::to_owned).collect(),
+                    );
+                },
+                _ => Err(bad_line("unknown key"))?,
+            }
+        }
+
+        for (constant, value) in constants {
+            target.constants.retain(|(other, _)| *other != constant);
+            target.constants.push((constant, value));
+        }
+        if let Some(runtime) = runtime {
+            target.runtime = runtime;
+        }
+        if let Some(cflags) = cflags {
+            target.cflags = cflags;
+        }
+
+        let snippet_names = SNIPPETS
+            .iter()
+            .map(|(role, _)| format!("{}.{}", role, SNIPPET_EXTENSION));
+        for file_name in snippet_names.chain(target.runtime.clone()) {
+            if let Some(contents) = location.read(&file_name)? {
+                target.files.insert(file_name.clone(), contents);
+            }
+            if !target.files.contains_key(&file_name) {
+                Err(Error::MissingTargetFile(
+                    location.describe(name, &file_name),
+                ))?;
+            }
+        }
+
+        for (role, allowed) in SNIPPETS {
+            let file_name = format!("{}.{}", role, SNIPPET_EXTENSION);
+            for placeholder in placeholders(&target.files[&file_name]) {
+                let known = allowed.contains(&placeholder)
+                    || target
+                        .constants
+                        .iter()
+                        .any(|(constant, _)| constant == placeholder);
+                if !known {
+                    Err(Error::UnknownPlaceholder {
+                        snippet: location.describe(name, &file_name),
+                        placeholder: placeholder.to_owned(),
+                    })?;
+                }
+            }
+        }
+
+        Ok(target)
+    }
+}
+
+/// Finds the names written as `{name}` in a snippet.
+fn placeholders(snippet: &str) -> impl Iterator<Item = &str> {
+    snippet.split('{').skip(1).filter_map(|piece| {
+        let (name, _) = piece.split_once('}')?;
+        Some(name).filter(|name| is_identifier(name))
+    })
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|ch: char| ch.is_ascii_digit())
+        && name.chars().all(|ch| ch == '_' || ch.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Target};
+    use std::{fs, path::PathBuf, process};
+
+    /// A fresh target directory holding the given files.
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "catbf-target-{}-{}",
+            name,
+            process::id()
+        ));
+        fs::remove_dir_all(&directory).ok();
+        fs::create_dir_all(&directory).unwrap();
+        for (name, contents) in files {
+            fs::write(directory.join(name), contents).unwrap();
+        }
+        directory
+    }
+
+    /// Loads a target from a fresh directory holding the given files.
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<Target, Error> {
+        let directory = directory(name, files);
+        let target = Target::load(&directory);
+        fs::remove_dir_all(&directory).ok();
+        target
+    }
+
+    #[test]
+    fn intel_inherits_missing_snippets() {
+        let linux = Target::builtin("x86_64-linux").unwrap();
+        let intel = Target::builtin("x86_64-linux-intel").unwrap();
+        assert!(intel.is_builtin());
+        assert_eq!(intel.runtime().collect::<Vec<_>>(), ["runtime.c"]);
+        let loc: &[(&str, &dyn std::fmt::Display)] =
+            &[("line", &3), ("column", &7)];
+        assert_eq!(intel.render("loc", loc), linux.render("loc", loc));
+        assert_ne!(intel.render("inc", &[]), linux.render("inc", &[]));
+        assert_eq!(
+            intel.render_runtime("runtime.c"),
+            linux.render_runtime("runtime.c")
+        );
+    }
+
+    #[test]
+    fn freestanding_replaces_runtime_and_flags() {
+        let linux = Target::builtin("x86_64-linux").unwrap();
+        let freestanding =
+            Target::builtin("x86_64-linux-freestanding").unwrap();
+        assert_eq!(freestanding.runtime().collect::<Vec<_>>(), ["runtime.s"]);
+        assert_eq!(freestanding.cflags(), ["-nostdlib", "-static"]);
+        assert_eq!(freestanding.render("inc", &[]), linux.render("inc", &[]));
+        assert!(freestanding
+            .render_runtime("runtime.s")
+            .contains(".set TAPE_CHUNK_SIZE, 8192"));
+    }
+
+    #[test]
+    fn constants_are_substituted() {
+        let target = load(
+            "constants",
+            &[
+                (
+                    "target.manifest",
+                    "inherit = x86_64-linux\nconst STEP = 42\nconst \
+                 TAPE_CHUNK_SIZE = 4096\n",
+                ),
+                ("inc.s", "    addb ${STEP}, 0(%r12, %r14)\n"),
+                (
+                    "runtime.c",
+                    "#define STEP {STEP}\n#define SIZE {TAPE_CHUNK_SIZE}\n",
+                ),
+            ],
+        )
+        .unwrap();
+        assert!(!target.is_builtin());
+        assert_eq!(target.render("inc", &[]), "    addb $42, 0(%r12, %r14)\n");
+        assert_eq!(
+            target.render_runtime("runtime.c"),
+            "#define STEP 42\n#define SIZE 4096\n"
+        );
+        assert!(target
+            .render("preamble", &[])
+            .contains(".set TAPE_CHUNK_SIZE, 4096"));
+    }
+
+    #[test]
+    fn unknown_placeholder_is_an_error() {
+        let error = load(
+            "placeholder",
+            &[
+                ("target.manifest", "inherit = x86_64-linux\n"),
+                ("inc.s", "    incb {offset}(%r12, %r14)\n"),
+            ],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                Error::UnknownPlaceholder { snippet, placeholder }
+                    if snippet.ends_with("inc.s") && placeholder == "offset"
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn missing_snippet_is_an_error() {
+        let error = load(
+            "missing",
+            &[
+                ("target.manifest", "const TAPE_CHUNK_SIZE = 8192\n"),
+                ("runtime.c", ""),
+            ],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&error, Error::MissingTargetFile(file) if file.ends_with(".s")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn bad_manifests_are_errors() {
+        for (name, manifest, expected_line) in [
+            ("syntax", "# comment\n\ninherit\n", 3),
+            ("key", "inherit = x86_64-linux\nlinker = ld\n", 2),
+            ("constant", "const 2X = 1\n", 1),
+        ] {
+            let error =
+                load(name, &[("target.manifest", manifest)]).unwrap_err();
+            assert!(
+                matches!(&error, Error::BadManifest { line, .. } if *line == expected_line),
+                "{:?}",
+                error
+            );
+        }
+        let error = load("inherit", &[("target.manifest", "inherit = x\n")])
+            .unwrap_err();
+        assert!(matches!(&error, Error::UnknownTarget(name) if name == "x"));
+    }
+}
@@ -0,0 +1,206 @@
+//! Translation of programs into portable C source code, buildable by any C89
+//! compiler. Loops become `while` statements, and the tape and input/output
+//! behave like those of the other backends. Cells are `unsigned char`, so
+//! they are 8-bit wherever bytes are.
+
+use crate::ir::{Instruction, Node, Program, StructureError};
+use std::fmt::Write;
+
+/// Definitions shared by all generated programs.
+const PRELUDE: &str = r#"#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define TAPE_CHUNK_SIZE 8192
+
+struct tape {
+    unsigned char *start;
+    size_t len;
+    size_t pos;
+};
+
+#define CELL (tape->start[tape->pos])
+"#;
+
+/// Helpers of the runtime, only included if used so compilers do not warn.
+const GROW_NEXT: &str = r#"
+/* Grows the tape by a chunk forwards. */
+static int grow_next(struct tape *tape)
+{
+    unsigned char *new_start = realloc(tape->start, tape->len + TAPE_CHUNK_SIZE);
+    if (new_start == NULL) {
+        return -1;
+    }
+    memset(new_start + tape->len, 0, TAPE_CHUNK_SIZE);
+    tape->start = new_start;
+    tape->len += TAPE_CHUNK_SIZE;
+    return 0;
+}
+"#;
+
+const GROW_PREV: &str = r#"
+/* Grows the tape by a chunk backwards, keeping the cursor on its cell. */
+static int grow_prev(struct tape *tape)
+{
+    unsigned char *new_start = realloc(tape->start, tape->len + TAPE_CHUNK_SIZE);
+    if (new_start == NULL) {
+        return -1;
+    }
+    memmove(new_start + TAPE_CHUNK_SIZE, new_start, tape->len);
+    memset(new_start, 0, TAPE_CHUNK_SIZE);
+    tape->start = new_start;
+    tape->len += TAPE_CHUNK_SIZE;
+    tape->pos += TAPE_CHUNK_SIZE;
+    return 0;
+}
+"#;
+
+const NEXT: &str = r#"
+static int next(struct tape *tape)
+{
+    tape->pos++;
+    if (tape->pos == tape->len) {
+        return grow_next(tape);
+    }
+    return 0;
+}
+"#;
+
+const PREV: &str = r#"
+static int prev(struct tape *tape)
+{
+    if (tape->pos == 0 && grow_prev(tape) < 0) {
+        return -1;
+    }
+    tape->pos--;
+    return 0;
+}
+"#;
+
+const GET: &str = r#"
+/*
+ * Writes whether a byte was read into the current cell, and the byte, zero
+ * on EOF, into the next one.
+ */
+static int get(struct tape *tape)
+{
+    int ch;
+    if (tape->pos + 1 == tape->len && grow_next(tape) < 0) {
+        return -1;
+    }
+    ch = getchar();
+    if (ch == EOF) {
+        if (ferror(stdin)) {
+            return -1;
+        }
+        tape->start[tape->pos] = 0;
+        tape->start[tape->pos + 1] = 0;
+    } else {
+        tape->start[tape->pos] = 1;
+        tape->start[tape->pos + 1] = (unsigned char) ch;
+    }
+    return 0;
+}
+"#;
+
+const PUT: &str = r#"
+static int put(struct tape *tape)
+{
+    if (putchar(CELL) == EOF) {
+        return -1;
+    }
+    return 0;
+}
+"#;
+
+const MAIN: &str = r#"
+int main(void)
+{
+    struct tape tape;
+    int status;
+    tape.start = calloc(TAPE_CHUNK_SIZE, sizeof(unsigned char));
+    if (tape.start == NULL) {
+        perror("catbf");
+        return 1;
+    }
+    tape.len = TAPE_CHUNK_SIZE;
+    tape.pos = 0;
+    status = run(&tape);
+    if (fflush(stdout) == EOF) {
+        status = -1;
+    }
+    free(tape.start);
+    if (status < 0) {
+        perror("stdio");
+        return 1;
+    }
+    return 0;
+}
+"#;
+
+/// Generates a complete C program with a `main` function running the given
+/// program on the standard input and output.
+pub fn generate(program: &Program) -> Result<String, StructureError> {
+    let nodes = program.structured()?;
+
+    let mut source = String::new();
+    source.push_str(concat!(
+        "/* Generated by catbf ",
+        env!("CARGO_PKG_VERSION"),
+        ". */\n"
+    ));
+    source.push_str(PRELUDE);
+    let uses = |instruction| program.code.contains(&instruction);
+    let helpers = [
+        (uses(Instruction::Next) || uses(Instruction::Get), GROW_NEXT),
+        (uses(Instruction::Prev), GROW_PREV),
+        (uses(Instruction::Next), NEXT),
+        (uses(Instruction::Prev), PREV),
+        (uses(Instruction::Get), GET),
+        (uses(Instruction::Put), PUT),
+    ];
+    for (used, helper) in helpers {
+        if used {
+            source.push_str(helper);
+        }
+    }
+    source.push_str("\nstatic int run(struct tape *tape)\n{\n");
+    write_block(&mut source, &nodes, 1);
+    let halts = matches!(
+        nodes.last(),
+        Some(Node::Instruction { instruction: Instruction::Halt, .. })
+    );
+    if !halts {
+        source.push_str("    return 0;\n");
+    }
+    source.push_str("}\n");
+    source.push_str(MAIN);
+    Ok(source)
+}
+
+fn write_block(source: &mut String, nodes: &[Node], depth: usize) {
+    for node in nodes {
+        let indent = "    ".repeat(depth);
+        match node {
+            Node::Instruction { instruction, .. } => {
+                let statement = match instruction {
+                    Instruction::Halt => "return 0;",
+                    Instruction::Inc => "CELL++;",
+                    Instruction::Dec => "CELL--;",
+                    Instruction::Next => "if (next(tape) < 0) return -1;",
+                    Instruction::Prev => "if (prev(tape) < 0) return -1;",
+                    Instruction::Get => "if (get(tape) < 0) return -1;",
+                    Instruction::Put => "if (put(tape) < 0) return -1;",
+                    // See `Program::structured`.
+                    Instruction::Jz(_) | Instruction::Jnz(_) => continue,
+                };
+                writeln!(source, "{}{}", indent, statement).ok();
+            },
+            Node::Loop { body, .. } => {
+                writeln!(source, "{}while (CELL) {{", indent).ok();
+                write_block(source, body, depth + 1);
+                writeln!(source, "{}}}", indent).ok();
+            },
+        }
+    }
+}
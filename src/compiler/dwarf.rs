@@ -0,0 +1,189 @@
+//! Minimal DWARF 4 writer: a single compilation unit with a line table mapping
+//! machine code back to Brainfuck source.
+
+use super::elf::{put_u16, put_u32, put_u64};
+
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA2: u8 = 0x05;
+const DW_FORM_DATA8: u8 = 0x07;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_FORM_SEC_OFFSET: u8 = 0x17;
+
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+/// A row of the line table: machine code starting at `address` comes from the
+/// given source line and column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineRow {
+    pub address: u64,
+    pub line: u64,
+    pub column: u64,
+}
+
+/// Encoded contents of the debug sections.
+#[derive(Debug, Clone)]
+pub struct DebugSections {
+    pub abbrev: Vec<u8>,
+    pub info: Vec<u8>,
+    pub line: Vec<u8>,
+}
+
+/// Describes code in `low_pc .. high_pc`, compiled from the source file
+/// `file_name`, with the given line table rows sorted by address.
+pub fn debug_sections(
+    file_name: &str,
+    comp_dir: &str,
+    low_pc: u64,
+    high_pc: u64,
+    rows: &[LineRow],
+) -> DebugSections {
+    DebugSections {
+        abbrev: abbrev(),
+        info: info(file_name, comp_dir, low_pc, high_pc),
+        line: line(file_name, high_pc, rows),
+    }
+}
+
+fn abbrev() -> Vec<u8> {
+    let mut buf = Vec::new();
+    put_uleb(&mut buf, 1);
+    put_uleb(&mut buf, DW_TAG_COMPILE_UNIT as u64);
+    buf.push(0);
+    for (attribute, form) in [
+        (DW_AT_PRODUCER, DW_FORM_STRING),
+        (DW_AT_LANGUAGE, DW_FORM_DATA2),
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_COMP_DIR, DW_FORM_STRING),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_DATA8),
+        (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+    ] {
+        put_uleb(&mut buf, attribute as u64);
+        put_uleb(&mut buf, form as u64);
+    }
+    buf.extend_from_slice(&[0, 0, 0]);
+    buf
+}
+
+fn info(file_name: &str, comp_dir: &str, low_pc: u64, high_pc: u64) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    put_u16(&mut buf, 4);
+    put_u32(&mut buf, 0);
+    buf.push(8);
+    put_uleb(&mut buf, 1);
+    put_str(&mut buf, concat!("catbf ", env!("CARGO_PKG_VERSION")));
+    put_u16(&mut buf, DW_LANG_MIPS_ASSEMBLER);
+    put_str(&mut buf, file_name);
+    put_str(&mut buf, comp_dir);
+    put_u64(&mut buf, low_pc);
+    put_u64(&mut buf, high_pc - low_pc);
+    put_u32(&mut buf, 0);
+    patch_unit_length(&mut buf);
+    buf
+}
+
+fn line(file_name: &str, high_pc: u64, rows: &[LineRow]) -> Vec<u8> {
+    // Minimum instruction length, maximum operations per instruction,
+    // default `is_stmt`, line base, line range and opcode base.
+    let mut header = vec![1, 1, 1, -5i8 as u8, 14, OPCODE_BASE];
+    header.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+    header.push(0);
+    put_str(&mut header, file_name);
+    header.extend_from_slice(&[0, 0, 0]);
+    header.push(0);
+
+    let mut buf = vec![0; 4];
+    put_u16(&mut buf, 4);
+    put_u32(&mut buf, header.len() as u32);
+    buf.extend_from_slice(&header);
+
+    let mut address = rows.first().map_or(high_pc, |row| row.address);
+    let mut line = 1;
+    let mut column = 0;
+    buf.push(0);
+    put_uleb(&mut buf, 9);
+    buf.push(DW_LNE_SET_ADDRESS);
+    put_u64(&mut buf, address);
+    for row in rows {
+        if row.address > address {
+            buf.push(DW_LNS_ADVANCE_PC);
+            put_uleb(&mut buf, row.address - address);
+            address = row.address;
+        }
+        if row.line != line {
+            buf.push(DW_LNS_ADVANCE_LINE);
+            put_sleb(&mut buf, row.line as i64 - line as i64);
+            line = row.line;
+        }
+        if row.column != column {
+            buf.push(DW_LNS_SET_COLUMN);
+            put_uleb(&mut buf, row.column);
+            column = row.column;
+        }
+        buf.push(DW_LNS_COPY);
+    }
+    if high_pc > address {
+        buf.push(DW_LNS_ADVANCE_PC);
+        put_uleb(&mut buf, high_pc - address);
+    }
+    buf.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+
+    patch_unit_length(&mut buf);
+    buf
+}
+
+fn patch_unit_length(buf: &mut [u8]) {
+    let length = (buf.len() - 4) as u32;
+    buf[.. 4].copy_from_slice(&length.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, string: &str) {
+    buf.extend_from_slice(string.as_bytes());
+    buf.push(0);
+}
+
+pub fn put_uleb(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+pub fn put_sleb(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_clear = byte & 0x40 == 0;
+        if (value == 0 && sign_clear) || (value == -1 && !sign_clear) {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,362 @@
+//! Minimal writer of little-endian ELF64 files for x86-64.
+
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_INFO_LINK: u64 = 0x40;
+
+/// Index of undefined symbols' section.
+pub const SHN_UNDEF: u16 = 0;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_FUNC: u8 = 2;
+
+pub const R_X86_64_PLT32: u32 = 4;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_R: u32 = 0x4;
+
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+/// A section to be written in the ELF file.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub align: u64,
+    /// Contents of the section. Ignored for `SHT_NOBITS`.
+    pub data: Vec<u8>,
+    /// Size of a `SHT_NOBITS` section, which has no data in the file.
+    pub nobits_size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub entsize: u64,
+}
+
+impl Section {
+    pub fn new(name: &str, kind: u32, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+            flags: 0,
+            addr: 0,
+            align: 1,
+            data,
+            nobits_size: 0,
+            link: 0,
+            info: 0,
+            entsize: 0,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        if self.kind == SHT_NOBITS {
+            self.nobits_size
+        } else {
+            self.data.len() as u64
+        }
+    }
+}
+
+/// A symbol to be written in the symbol table.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    /// Index of the section as returned by `Object::add_section`.
+    pub section: u16,
+    pub bind: u8,
+    pub kind: u8,
+}
+
+/// A relocation of a section's contents against a symbol.
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    /// Offset in the section of the relocated field.
+    pub offset: u64,
+    /// Name of a symbol added with `Object::add_symbol`.
+    pub symbol: String,
+    pub kind: u32,
+    pub addend: i64,
+}
+
+/// A loadable segment mapping a whole section at its address.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    section: u16,
+    flags: u32,
+}
+
+/// An ELF file being built.
+#[derive(Debug, Clone)]
+pub struct Object {
+    kind: u16,
+    entry: u64,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+    segments: Vec<Segment>,
+    /// Relocations of each section, by section index.
+    relocations: Vec<(u16, Vec<Relocation>)>,
+}
+
+impl Object {
+    pub fn new(kind: u16) -> Self {
+        Self {
+            kind,
+            entry: 0,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+            segments: Vec::new(),
+            relocations: Vec::new(),
+        }
+    }
+
+    /// Sets the address where execution starts.
+    pub fn set_entry(&mut self, entry: u64) {
+        self.entry = entry;
+    }
+
+    /// Maps the section with the given index into memory at its address,
+    /// with the given `PF_*` permissions. The address and the alignment of
+    /// the section must be multiples of the page size.
+    pub fn add_segment(&mut self, section: u16, flags: u32) {
+        self.segments.push(Segment { section, flags });
+    }
+
+    /// Adds a section, returning its index in the section header table.
+    pub fn add_section(&mut self, section: Section) -> u16 {
+        self.sections.push(section);
+        self.sections.len() as u16
+    }
+
+    pub fn add_symbol(&mut self, symbol: Symbol) {
+        self.symbols.push(symbol);
+    }
+
+    /// Adds relocations to the section with the given index.
+    pub fn add_relocations(
+        &mut self,
+        section: u16,
+        relocations: Vec<Relocation>,
+    ) {
+        self.relocations.push((section, relocations));
+    }
+
+    /// Lays out and encodes the whole file.
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.symbols.is_empty() {
+            self.push_symbol_table();
+        }
+        self.push_relocation_sections();
+        let shstrtab_index = self.sections.len() + 1;
+        let mut shstrtab = StringTable::new();
+        let mut names = Vec::with_capacity(self.sections.len() + 1);
+        for section in &self.sections {
+            names.push(shstrtab.insert(&section.name));
+        }
+        names.push(shstrtab.insert(".shstrtab"));
+        self.sections.push(Section::new(
+            ".shstrtab",
+            SHT_STRTAB,
+            shstrtab.into_bytes(),
+        ));
+
+        let phoff = if self.segments.is_empty() { 0 } else { HEADER_SIZE };
+        let mut buf =
+            vec![0; HEADER_SIZE + PROGRAM_HEADER_SIZE * self.segments.len()];
+        let mut offsets = Vec::with_capacity(self.sections.len());
+        for section in &self.sections {
+            if section.kind != SHT_NOBITS {
+                align(&mut buf, section.align);
+            }
+            offsets.push(buf.len() as u64);
+            if section.kind != SHT_NOBITS {
+                buf.extend_from_slice(&section.data);
+            }
+        }
+
+        align(&mut buf, 8);
+        let shoff = buf.len() as u64;
+        buf.extend_from_slice(&[0; SECTION_HEADER_SIZE]);
+        for (i, section) in self.sections.iter().enumerate() {
+            put_u32(&mut buf, names[i]);
+            put_u32(&mut buf, section.kind);
+            put_u64(&mut buf, section.flags);
+            put_u64(&mut buf, section.addr);
+            put_u64(&mut buf, offsets[i]);
+            put_u64(&mut buf, section.size());
+            put_u32(&mut buf, section.link);
+            put_u32(&mut buf, section.info);
+            put_u64(&mut buf, section.align);
+            put_u64(&mut buf, section.entsize);
+        }
+
+        let mut program_headers = Vec::new();
+        for segment in &self.segments {
+            let section = &self.sections[usize::from(segment.section) - 1];
+            let file_size =
+                if section.kind == SHT_NOBITS { 0 } else { section.size() };
+            put_u32(&mut program_headers, PT_LOAD);
+            put_u32(&mut program_headers, segment.flags);
+            put_u64(
+                &mut program_headers,
+                offsets[usize::from(segment.section) - 1],
+            );
+            put_u64(&mut program_headers, section.addr);
+            put_u64(&mut program_headers, section.addr);
+            put_u64(&mut program_headers, file_size);
+            put_u64(&mut program_headers, section.size());
+            put_u64(&mut program_headers, section.align);
+        }
+        buf[HEADER_SIZE .. HEADER_SIZE + program_headers.len()]
+            .copy_from_slice(&program_headers);
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        header.extend_from_slice(&[0; 8]);
+        put_u16(&mut header, self.kind);
+        put_u16(&mut header, EM_X86_64);
+        put_u32(&mut header, 1);
+        put_u64(&mut header, self.entry);
+        put_u64(&mut header, phoff as u64);
+        put_u64(&mut header, shoff);
+        put_u32(&mut header, 0);
+        put_u16(&mut header, HEADER_SIZE as u16);
+        put_u16(&mut header, PROGRAM_HEADER_SIZE as u16);
+        put_u16(&mut header, self.segments.len() as u16);
+        put_u16(&mut header, SECTION_HEADER_SIZE as u16);
+        put_u16(&mut header, self.sections.len() as u16 + 1);
+        put_u16(&mut header, shstrtab_index as u16);
+        buf[.. HEADER_SIZE].copy_from_slice(&header);
+
+        buf
+    }
+
+    fn push_symbol_table(&mut self) {
+        // Local symbols must precede global ones.
+        self.symbols.sort_by_key(|symbol| symbol.bind != STB_LOCAL);
+        let mut strtab = StringTable::new();
+        let mut symtab = vec![0; SYMBOL_SIZE];
+        for symbol in &self.symbols {
+            put_u32(&mut symtab, strtab.insert(&symbol.name));
+            symtab.push((symbol.bind << 4) | (symbol.kind & 0xf));
+            symtab.push(0);
+            put_u16(&mut symtab, symbol.section);
+            put_u64(&mut symtab, symbol.value);
+            put_u64(&mut symtab, symbol.size);
+        }
+        let first_global = self
+            .symbols
+            .iter()
+            .position(|symbol| symbol.bind != STB_LOCAL)
+            .unwrap_or(self.symbols.len());
+        let mut symtab_section = Section::new(".symtab", SHT_SYMTAB, symtab);
+        symtab_section.align = 8;
+        // The string table is pushed right after the symbol table, and the
+        // null symbol is not in `self.symbols`. Section indices are 1-based.
+        symtab_section.link = self.sections.len() as u32 + 2;
+        symtab_section.info = first_global as u32 + 1;
+        symtab_section.entsize = SYMBOL_SIZE as u64;
+        self.sections.push(symtab_section);
+        self.sections.push(Section::new(
+            ".strtab",
+            SHT_STRTAB,
+            strtab.into_bytes(),
+        ));
+    }
+
+    fn push_relocation_sections(&mut self) {
+        let symtab_index = self
+            .sections
+            .iter()
+            .position(|section| section.kind == SHT_SYMTAB)
+            .map_or(0, |i| i as u32 + 1);
+        for (section, relocations) in std::mem::take(&mut self.relocations) {
+            let mut rela = Vec::with_capacity(relocations.len() * RELA_SIZE);
+            for relocation in relocations {
+                // Symbols were sorted when the symbol table was pushed.
+                let symbol = self
+                    .symbols
+                    .iter()
+                    .position(|symbol| symbol.name == relocation.symbol)
+                    .map_or(0, |i| i as u64 + 1);
+                put_u64(&mut rela, relocation.offset);
+                put_u64(&mut rela, (symbol << 32) | u64::from(relocation.kind));
+                put_u64(&mut rela, relocation.addend as u64);
+            }
+            let name = format!(
+                ".rela{}",
+                self.sections[usize::from(section) - 1].name
+            );
+            let mut rela_section = Section::new(&name, SHT_RELA, rela);
+            rela_section.flags = SHF_INFO_LINK;
+            rela_section.align = 8;
+            rela_section.link = symtab_index;
+            rela_section.info = u32::from(section);
+            rela_section.entsize = RELA_SIZE as u64;
+            self.sections.push(rela_section);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+
+    fn insert(&mut self, string: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+fn align(buf: &mut Vec<u8>, alignment: u64) {
+    let alignment = alignment.max(1) as usize;
+    let padding = (alignment - buf.len() % alignment) % alignment;
+    buf.resize(buf.len() + padding, 0);
+}
+
+pub fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
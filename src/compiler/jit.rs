@@ -1,23 +1,52 @@
-use self::runtime::Interface;
-use crate::ir::{Instruction, Program};
+#[cfg(feature = "gdb-jit")]
+use self::gdb::GdbRegistration;
+use self::{memory::CodeMemory, runtime::Interface};
+pub use crate::interpreter::Status;
+use crate::{
+    ir::{Instruction, Program},
+    stats::Stats,
+};
 use std::{
     collections::{BTreeMap, HashMap},
     io,
     mem::transmute,
+    path::PathBuf,
     ptr,
+    time::Instant,
 };
 use thiserror::Error;
 
+pub use self::{
+    cache::Cache,
+    debug::Symbol,
+    memory::{CodeAllocator, MappingMode},
+};
+pub(crate) use self::{
+    debug::DebugInfo,
+    runtime::{Function, TAPE_CHUNK_SIZE},
+};
+
 mod runtime;
+mod debug;
+#[cfg(feature = "gdb-jit")]
+mod gdb;
+mod memory;
+mod cache;
 
 pub const TARGET_SUPPORTED: bool =
     cfg!(all(target_os = "linux", target_arch = "x86_64"));
 
+/// Version of the generated machine code, checked by the cache. Must be
+/// bumped on every change to the code emitted for a program.
+pub(crate) const CODEGEN_VERSION: u32 = 1;
+
 const PUSH_RBX: [u8; 1] = [0x53];
 const PUSH_R12: [u8; 2] = [0x41, 0x54];
 const PUSH_R13: [u8; 2] = [0x41, 0x55];
 const PUSH_R14: [u8; 2] = [0x41, 0x56];
+const PUSH_R15: [u8; 2] = [0x41, 0x57];
 
+const POP_R15: [u8; 2] = [0x41, 0x5f];
 const POP_R14: [u8; 2] = [0x41, 0x5e];
 const POP_R13: [u8; 2] = [0x41, 0x5d];
 const POP_R12: [u8; 2] = [0x41, 0x5c];
@@ -28,6 +57,19 @@ const MOV_R12_TO_RDI: [u8; 3] = [0x4c, 0x89, 0xe7];
 const MOV_R13_TO_RSI: [u8; 3] = [0x4c, 0x89, 0xee];
 const MOV_RAX_TO_R12: [u8; 3] = [0x49, 0x89, 0xc4];
 const MOV_RBX_TO_RDI: [u8; 3] = [0x48, 0x89, 0xdf];
+const MOV_RBX_TO_RDX: [u8; 3] = [0x48, 0x89, 0xda];
+const MOV_MEM_RBX_TO_R12: [u8; 3] = [0x4c, 0x8b, 0x23];
+const MOV_MEM_RBX_8_TO_R13: [u8; 4] = [0x4c, 0x8b, 0x6b, 0x08];
+const MOV_MEM_RBX_16_TO_R14: [u8; 4] = [0x4c, 0x8b, 0x73, 0x10];
+const MOV_R12_TO_MEM_RBX: [u8; 3] = [0x4c, 0x89, 0x23];
+const MOV_R13_TO_MEM_RBX_8: [u8; 4] = [0x4c, 0x89, 0x6b, 0x08];
+const MOV_R14_TO_MEM_RBX_16: [u8; 4] = [0x4c, 0x89, 0x73, 0x10];
+const MOV_MEM_RBX_24_TO_RAX: [u8; 4] = [0x48, 0x8b, 0x43, 0x18];
+const MOV_RAX_TO_MEM_RBX_24: [u8; 4] = [0x48, 0x89, 0x43, 0x18];
+const MOV_MEM_RBX_32_TO_AX: [u8; 4] = [0x66, 0x8b, 0x43, 0x20];
+const MOV_MEM_RBX_40_TO_RDI: [u8; 4] = [0x48, 0x8b, 0x7b, 0x28];
+const MOV_MEM_RBX_40_TO_RDX: [u8; 4] = [0x48, 0x8b, 0x53, 0x28];
+const LEA_RIP_REL32_TO_RAX: [u8; 3] = [0x48, 0x8d, 0x05];
 const MOV_AX_TO_SI: [u8; 3] = [0x66, 0x89, 0xc6];
 const MOV_AX_TO_MEM_R12_R14: [u8; 5] = [0x66, 0x43, 0x89, 0x04, 0x34];
 const MOV_R14B_TO_AL: [u8; 3] = [0x44, 0x88, 0xf0];
@@ -44,7 +86,9 @@ const JMP_REL32: [u8; 1] = [0xe9];
 const JE_JZ_REL32: [u8; 2] = [0x0f, 0x84];
 const JNE_JNZ_REL32: [u8; 2] = [0x0f, 0x85];
 const JS_REL32: [u8; 2] = [0x0f, 0x88];
+const JMP_ABS_RAX: [u8; 2] = [0xff, 0xe0];
 const CALL_ABS_RAX: [u8; 2] = [0xff, 0xd0];
+const CALL_REL32: [u8; 1] = [0xe8];
 
 const XOR_R14_TO_R14: [u8; 3] = [0x4d, 0x31, 0xf6];
 const XOR_EAX_TO_EAX: [u8; 2] = [0x31, 0xc0];
@@ -52,6 +96,7 @@ const XOR_R14B_TO_R14B: [u8; 3] = [0x45, 0x30, 0xf6];
 
 const MOV_IMM32_TO_R13: [u8; 3] = [0x49, 0xc7, 0xc5];
 const MOV_IMM8_TO_R14B: [u8; 2] = [0x41, 0xb6];
+const MOV_IMM8_TO_AL: [u8; 1] = [0xb0];
 const ADD_IMM32_TO_R13: [u8; 3] = [0x49, 0x81, 0xc5];
 const ADD_IMM32_TO_R14: [u8; 3] = [0x49, 0x81, 0xc6];
 
@@ -65,17 +110,237 @@ const DECB_MEM_R12_R14: [u8; 4] = [0x43, 0xfe, 0x0c, 0x34];
 
 const RET: [u8; 1] = [0xc3];
 
+/// Statuses returned by resumable code.
+const STATUS_HALTED: i8 = 0;
+const STATUS_NEEDS_INPUT: i8 = 1;
+
+/// Options of Just-In-Time compilation. Debugging and profiling support is
+/// disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Appends one symbol per loop of the generated code to a perf map, so
+    /// `perf` can attribute samples to them.
+    pub perf_map: bool,
+    /// Perf map to append to. Defaults to `/tmp/perf-<pid>.map`.
+    pub perf_map_path: Option<PathBuf>,
+    /// Registers an in-memory ELF object with symbols and source lines of the
+    /// generated code with the GDB JIT interface. Needs the `gdb-jit` feature.
+    pub gdb_jit: bool,
+    /// Name of the source file, used in the debug information.
+    pub source_name: Option<String>,
+    /// How executable memory for the generated code is mapped.
+    pub mapping_mode: MappingMode,
+    /// Generates code that returns to the caller whenever it needs input,
+    /// instead of reading it. Such code is run with `Executable::start` and
+    /// `Executable::resume` rather than `Executable::run`.
+    pub resumable: bool,
+}
+
 pub fn compile(program: &Program) -> Result<Executable, Error> {
+    compile_with(program, &Options::default())
+}
+
+pub fn compile_with(
+    program: &Program,
+    options: &Options,
+) -> Result<Executable, Error> {
+    let artifact = generate(program, options)?;
+    load(&artifact, program, options)
+}
+
+/// Generates position-independent machine code for the program.
+pub(crate) fn generate(
+    program: &Program,
+    options: &Options,
+) -> Result<Artifact, Error> {
+    generate_with_calls(program, options, CallKind::Absolute)
+}
+
+/// Generates machine code for the program, calling runtime functions the
+/// given way.
+pub(crate) fn generate_with_calls(
+    program: &Program,
+    options: &Options,
+    calls: CallKind,
+) -> Result<Artifact, Error> {
     if !TARGET_SUPPORTED {
         Err(Error::UnsupportedTarget)?;
     }
 
     let mut compiler = Compiler::new();
+    compiler.resumable = options.resumable;
+    compiler.calls = calls;
 
     compiler.first_pass(program);
     compiler.second_pass()?;
 
-    unsafe { Executable::new(&compiler.buf[..]) }
+    Ok(compiler.into_artifact(program.code.len()))
+}
+
+/// Relocates the generated code into executable memory.
+fn load(
+    artifact: &Artifact,
+    program: &Program,
+    options: &Options,
+) -> Result<Executable, Error> {
+    if !TARGET_SUPPORTED {
+        Err(Error::UnsupportedTarget)?;
+    }
+    if options.gdb_jit && !cfg!(feature = "gdb-jit") {
+        Err(Error::GdbJitDisabled)?;
+    }
+
+    let mut code = artifact.code.clone();
+    for relocation in &artifact.relocations {
+        let Some(bytes) =
+            code.get_mut(relocation.offset .. relocation.offset + 8)
+        else {
+            Err(Error::BadRelocation(relocation.offset))?
+        };
+        bytes.copy_from_slice(
+            &(relocation.function.address() as u64).to_le_bytes(),
+        );
+    }
+
+    let debug_info = if options.perf_map || options.gdb_jit {
+        DebugInfo::collect(&artifact.ir_offsets, code.len(), program)
+    } else {
+        DebugInfo::default()
+    };
+
+    let allocator = CodeAllocator::global(options.mapping_mode);
+    let mut executable = Executable::new(allocator.allocate(&code)?);
+    executable.resumable = options.resumable;
+    let address = executable.address();
+
+    if options.perf_map {
+        let path = options
+            .perf_map_path
+            .clone()
+            .unwrap_or_else(debug::default_perf_map_path);
+        debug::append_perf_map(&path, address, &debug_info.symbols)
+            .map_err(|error| Error::PerfMap(path, error))?;
+    }
+
+    #[cfg(feature = "gdb-jit")]
+    if options.gdb_jit {
+        let source_name = options.source_name.as_deref().unwrap_or("<source>");
+        let object = debug_info.elf_object(source_name, address, code.len());
+        executable.gdb_registration = Some(GdbRegistration::register(object));
+    }
+
+    executable.symbols = debug_info.symbols;
+
+    Ok(executable)
+}
+
+/// Addresses of the functions called by a fragment. Growth functions receive
+/// the fragment context as a third argument, and input/output functions
+/// receive it as the first one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FragmentRuntime {
+    pub grow_next: usize,
+    pub grow_prev: usize,
+    pub get: usize,
+    pub put: usize,
+}
+
+/// State of a tape shared between a fragment and its caller. Must be the
+/// first field of the context passed to a fragment.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FragmentTape {
+    pub start: *mut u8,
+    pub len: usize,
+    /// Must always be less than `len`.
+    pub cursor: usize,
+}
+
+/// A single loop of a program compiled to native code, running on a tape
+/// owned by the caller. Returns when the loop exits.
+#[derive(Debug)]
+pub(crate) struct Fragment {
+    memory: CodeMemory,
+}
+
+impl Fragment {
+    /// Runs the loop, returning a negative status on input/output errors.
+    ///
+    /// # Safety
+    ///
+    /// `context` must point to the `FragmentTape` at the start of the context
+    /// expected by the functions of the `FragmentRuntime` used to compile the
+    /// fragment, and describe a valid tape.
+    pub unsafe fn call(&self, context: *mut FragmentTape) -> i8 {
+        let main: unsafe extern "sysv64" fn(*mut FragmentTape) -> i8 =
+            transmute(self.memory.as_ptr());
+        main(context)
+    }
+}
+
+/// Compiles the loop whose `Jz` is at the given index of the program.
+pub(crate) fn compile_fragment(
+    program: &Program,
+    start: usize,
+    runtime: &FragmentRuntime,
+) -> Result<Fragment, Error> {
+    if !TARGET_SUPPORTED {
+        Err(Error::UnsupportedTarget)?;
+    }
+
+    let Some(Instruction::Jz(end)) = program.code.get(start).copied() else {
+        Err(Error::BadLabelIndex(start))?
+    };
+    let body = program.code.get(start + 1 .. end).unwrap_or_default();
+    let is_loop = body.last() == Some(&Instruction::Jnz(start + 1))
+        && !body.contains(&Instruction::Halt);
+    if !is_loop {
+        Err(Error::BadLoop(start, end))?;
+    }
+
+    let mut addresses = [0; Function::ALL.len()];
+    addresses[Function::GrowNext as usize] = runtime.grow_next;
+    addresses[Function::GrowPrev as usize] = runtime.grow_prev;
+    addresses[Function::Get as usize] = runtime.get;
+    addresses[Function::Put as usize] = runtime.put;
+
+    let mut compiler = Compiler::new();
+    compiler.addresses = addresses;
+    compiler.fragment_pass(program, start, end);
+    compiler.second_pass()?;
+
+    let allocator = CodeAllocator::global(MappingMode::default());
+    Ok(Fragment { memory: allocator.allocate(&compiler.buf)? })
+}
+
+/// How generated code calls runtime functions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum CallKind {
+    /// `movabs rax, address; call rax`, with a relocation to the 64-bit
+    /// address of the function.
+    #[default]
+    Absolute,
+    /// `call rel32`, with a relocation to the 32-bit distance from the end of
+    /// the instruction to the function, placed near the code.
+    Relative,
+}
+
+/// A reference to a runtime function in the generated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Relocation {
+    /// Offset of the immediate holding the address or distance, depending on
+    /// the `CallKind`.
+    pub offset: usize,
+    pub function: Function,
+}
+
+/// Generated machine code, before being loaded into executable memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Artifact {
+    pub code: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+    /// Offset in the code of each IR instruction, plus the end of the program.
+    pub ir_offsets: Vec<usize>,
 }
 
 #[derive(Debug, Error)]
@@ -88,58 +353,254 @@ pub enum Error {
     AllocError(io::Error),
     #[error("error setting permission for executable memory: {}", .0)]
     Permission(io::Error),
+    #[error("instructions {} to {} do not form a loop", .0, .1)]
+    BadLoop(usize, usize),
+    #[error("relocation at offset {} is out of bounds", .0)]
+    BadRelocation(usize),
+    #[error("error writing perf map {}: {}", .0.display(), .1)]
+    PerfMap(PathBuf, io::Error),
+    #[error("error accessing JIT cache {}: {}", .0.display(), .1)]
+    Cache(PathBuf, io::Error),
+    #[error("GDB JIT interface is disabled, enable the `gdb-jit` feature")]
+    GdbJitDisabled,
 }
 
+/// Compiled program in executable memory. Can be sent to and run from other
+/// threads.
 #[derive(Debug)]
 pub struct Executable {
-    buf: *mut libc::c_void,
+    memory: CodeMemory,
+    symbols: Vec<Symbol>,
+    #[cfg(feature = "gdb-jit")]
+    gdb_registration: Option<GdbRegistration>,
+    resumable: bool,
 }
 
 impl Executable {
-    unsafe fn new(buf: &[u8]) -> Result<Self, Error> {
-        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as libc::size_t;
-        let len = buf.len() as libc::size_t;
-        let ceiled_len = len + (page_size - len % page_size) % page_size;
-
-        let mut ptr = ptr::null_mut();
-        let code = libc::posix_memalign(&mut ptr, page_size, ceiled_len);
-        if code != 0 {
-            Err(Error::AllocError(io::Error::from_raw_os_error(code)))?;
+    fn new(memory: CodeMemory) -> Self {
+        Self {
+            memory,
+            symbols: Vec::new(),
+            #[cfg(feature = "gdb-jit")]
+            gdb_registration: None,
+            resumable: false,
         }
-        libc::memcpy(ptr, buf.as_ptr() as *const libc::c_void, len);
+    }
 
-        let protection = libc::PROT_EXEC | libc::PROT_READ;
-        if libc::mprotect(ptr, ceiled_len, protection) < 0 {
-            Err(Error::Permission(io::Error::last_os_error()))?;
-        }
+    /// Whether the code was compiled with `Options::resumable`.
+    pub fn is_resumable(&self) -> bool {
+        self.resumable
+    }
+
+    /// Address where the generated code starts.
+    pub fn address(&self) -> usize {
+        self.memory.as_ptr() as usize
+    }
 
-        Ok(Self { buf: ptr })
+    /// Symbols of the generated code, one per innermost loop. Only collected
+    /// when a perf map or GDB registration was requested.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
     }
 
-    pub fn run<R, W>(&self, input: R, output: W) -> io::Result<()>
+    /// The ELF object registered with the GDB JIT interface, if any.
+    pub fn debug_object(&self) -> Option<&[u8]> {
+        #[cfg(feature = "gdb-jit")]
+        if let Some(registration) = &self.gdb_registration {
+            return Some(registration.object());
+        }
+        None
+    }
+
+    /// Runs the program until it halts, returning the statistics of the run.
+    /// Only input, output, tape growths and time are counted.
+    pub fn run<R, W>(&self, input: R, output: W) -> io::Result<Stats>
     where
-        R: io::Read + Send + Sync + 'static,
-        W: io::Write + Send + Sync + 'static,
+        R: io::Read,
+        W: io::Write,
     {
+        if self.resumable {
+            Err(invalid_input("resumable code must be started, not run"))?;
+        }
+
         let mut interface = Interface::new(input, output);
 
+        let start = Instant::now();
         let status = unsafe {
-            let main: unsafe fn(*mut Interface) -> i8 = transmute(self.buf);
+            let main: unsafe extern "sysv64" fn(*mut Interface<'_>) -> i8 =
+                transmute(self.memory.as_ptr());
             main(&mut interface)
         };
+        interface.stats.time = start.elapsed();
 
         if status < 0 {
-            Err(io::Error::last_os_error())?;
+            Err(interface.failure())?;
         }
-        Ok(())
+        Ok(interface.stats)
+    }
+
+    /// Creates the state of a new run of resumable code.
+    pub fn continuation(&self) -> Result<Continuation, Error> {
+        let start = unsafe { runtime::create_tape() };
+        if start.is_null() {
+            Err(Error::AllocError(io::ErrorKind::OutOfMemory.into()))?;
+        }
+        let context = ResumeContext {
+            tape: FragmentTape { start, len: TAPE_CHUNK_SIZE, cursor: 0 },
+            resume_address: 0,
+            input: 0,
+            interface: ptr::null_mut(),
+        };
+        Ok(Continuation {
+            context,
+            address: self.address(),
+            status: None,
+            stats: Stats::default(),
+        })
+    }
+
+    /// Runs resumable code from its beginning until it halts or needs input,
+    /// writing output to the given writer.
+    pub fn start(
+        &self,
+        continuation: &mut Continuation,
+        output: &mut dyn io::Write,
+    ) -> io::Result<Status> {
+        if continuation.status.is_some() {
+            Err(invalid_input("continuation was already started"))?;
+        }
+        self.enter(continuation, output)
+    }
+
+    /// Continues code waiting for input with the given byte, or `None` for
+    /// EOF, until it halts or needs input again, writing output to the given
+    /// writer.
+    pub fn resume(
+        &self,
+        continuation: &mut Continuation,
+        input: Option<u8>,
+        output: &mut dyn io::Write,
+    ) -> io::Result<Status> {
+        if continuation.status != Some(Status::NeedsInput) {
+            Err(invalid_input("continuation is not waiting for input"))?;
+        }
+        // The flag goes to the current cell and the byte to the next one.
+        continuation.context.input = match input {
+            Some(byte) => u16::from_le_bytes([1, byte]),
+            None => 0,
+        };
+        if input.is_some() {
+            continuation.stats.bytes_read += 1;
+        }
+        self.enter(continuation, output)
+    }
+
+    fn enter(
+        &self,
+        continuation: &mut Continuation,
+        output: &mut dyn io::Write,
+    ) -> io::Result<Status> {
+        if !self.resumable {
+            Err(invalid_input("code was not compiled as resumable"))?;
+        }
+        if continuation.address != self.address() {
+            Err(invalid_input("continuation belongs to another executable"))?;
+        }
+
+        let mut interface = Interface::new(io::empty(), output);
+        interface.stats = continuation.stats;
+        continuation.context.interface = ptr::from_mut(&mut interface).cast();
+        let start = Instant::now();
+        let status = unsafe {
+            let main: unsafe extern "sysv64" fn(*mut ResumeContext) -> i8 =
+                transmute(self.memory.as_ptr());
+            main(&mut continuation.context)
+        };
+        interface.stats.time += start.elapsed();
+        continuation.context.interface = ptr::null_mut();
+        continuation.stats = interface.stats;
+
+        let status = match status {
+            STATUS_HALTED => Status::Halted,
+            STATUS_NEEDS_INPUT => Status::NeedsInput,
+            _ => Err(interface.failure())?,
+        };
+        continuation.status = Some(status);
+        Ok(status)
     }
 }
 
+#[cfg(feature = "gdb-jit")]
 impl Drop for Executable {
     fn drop(&mut self) {
-        unsafe {
-            libc::free(self.buf);
-        }
+        // Unregisters from GDB before the code is unmapped.
+        self.gdb_registration = None;
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Context passed to resumable code. Its layout is relied upon by the
+/// generated code.
+#[repr(C)]
+#[derive(Debug)]
+struct ResumeContext {
+    tape: FragmentTape,
+    /// Where to continue the code, or zero to start from the beginning.
+    resume_address: usize,
+    /// Cells written by the suspended input instruction.
+    input: u16,
+    /// Borrowed for the duration of a call, null otherwise.
+    interface: *mut Interface<'static>,
+}
+
+/// State of a run of resumable code: the tape, the cursor and where the code
+/// stopped. Created by `Executable::continuation`, and only usable with the
+/// executable that created it.
+#[derive(Debug)]
+pub struct Continuation {
+    context: ResumeContext,
+    /// Address of the executable this continuation belongs to.
+    address: usize,
+    /// Status of the last return to the caller, `None` if not started yet.
+    status: Option<Status>,
+    stats: Stats,
+}
+
+// The tape is owned by the continuation, and the interface is only set while
+// it is running.
+unsafe impl Send for Continuation {}
+unsafe impl Sync for Continuation {}
+
+impl Continuation {
+    /// Status of the last return to the caller, `None` if not started yet.
+    pub fn status(&self) -> Option<Status> {
+        self.status
+    }
+
+    /// Cells of the tape.
+    pub fn tape(&self) -> &[u8] {
+        let tape = &self.context.tape;
+        unsafe { std::slice::from_raw_parts(tape.start, tape.len) }
+    }
+
+    /// Position of the cursor in the tape.
+    pub fn cursor(&self) -> usize {
+        self.context.tape.cursor
+    }
+
+    /// Statistics of the run so far. Only input, output, tape growths and
+    /// time are counted.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+impl Drop for Continuation {
+    fn drop(&mut self) {
+        unsafe { runtime::destroy_tape(self.context.tape.start) };
     }
 }
 
@@ -148,6 +609,12 @@ struct Compiler {
     buf: Vec<u8>,
     placeholders: BTreeMap<usize, (usize, usize)>,
     labels: HashMap<(usize, usize), usize>,
+    relocations: Vec<Relocation>,
+    /// Address of each runtime function, indexed by `Function`.
+    addresses: [usize; Function::ALL.len()],
+    /// Whether to generate code that suspends on input.
+    resumable: bool,
+    calls: CallKind,
 }
 
 impl Compiler {
@@ -156,12 +623,27 @@ impl Compiler {
             buf: Vec::new(),
             placeholders: BTreeMap::new(),
             labels: HashMap::new(),
+            relocations: Vec::new(),
+            addresses: Function::ALL.map(Function::address),
+            resumable: false,
+            calls: CallKind::Absolute,
         }
     }
 
+    pub fn into_artifact(self, last_ir_label: usize) -> Artifact {
+        let ir_offsets = (0 ..= last_ir_label)
+            .map(|ir_label| self.labels[&(ir_label, 0)])
+            .collect();
+        Artifact { code: self.buf, relocations: self.relocations, ir_offsets }
+    }
+
     pub fn first_pass(&mut self, program: &Program) {
         let last_ir_label = program.code.len();
-        self.write_enter(last_ir_label);
+        if self.resumable {
+            self.write_resumable_enter();
+        } else {
+            self.write_enter(last_ir_label);
+        }
 
         for (ir_label, instr) in program.code.iter().enumerate() {
             self.def_main_label(ir_label);
@@ -169,7 +651,36 @@ impl Compiler {
         }
 
         self.def_main_label(last_ir_label);
-        self.write_leave(last_ir_label);
+        if self.resumable {
+            // The tape outlives the call, stored in the context like the one
+            // of a fragment.
+            self.write_fragment_leave(last_ir_label);
+        } else {
+            self.write_leave(last_ir_label);
+        }
+    }
+
+    /// Compiles only the instructions in `start .. end`, exiting through the
+    /// label `end`.
+    pub fn fragment_pass(
+        &mut self,
+        program: &Program,
+        start: usize,
+        end: usize,
+    ) {
+        self.write_fragment_enter();
+
+        for (ir_label, instr) in program.code[start .. end]
+            .iter()
+            .enumerate()
+            .map(|(i, instr)| (start + i, instr))
+        {
+            self.def_main_label(ir_label);
+            self.handle_instruction(ir_label, *instr, end);
+        }
+
+        self.def_main_label(end);
+        self.write_fragment_leave(end);
     }
 
     pub fn second_pass(&mut self) -> Result<(), Error> {
@@ -230,20 +741,36 @@ impl Compiler {
         self.write(0u32.to_le_bytes());
     }
 
-    pub fn call_absolute(&mut self, func_ptr: *const u8) {
-        self.write(MOVABS_TO_RAX);
-        self.write((func_ptr as usize as u64).to_le_bytes());
-        self.write(CALL_ABS_RAX);
+    pub fn call_runtime(&mut self, function: Function) {
+        match self.calls {
+            CallKind::Absolute => {
+                self.write(MOVABS_TO_RAX);
+                self.relocations
+                    .push(Relocation { offset: self.buf.len(), function });
+                self.write(
+                    (self.addresses[function as usize] as u64).to_le_bytes(),
+                );
+                self.write(CALL_ABS_RAX);
+            },
+            CallKind::Relative => {
+                self.write(CALL_REL32);
+                self.relocations
+                    .push(Relocation { offset: self.buf.len(), function });
+                self.write(0u32.to_le_bytes());
+            },
+        }
     }
 
     pub fn write_enter(&mut self, last_ir_label: usize) {
+        // Five pushes keep the stack aligned to 16 bytes at calls.
+        self.write(PUSH_R15);
         self.write(PUSH_R14);
         self.write(PUSH_R13);
         self.write(PUSH_R12);
         self.write(PUSH_RBX);
         self.write(MOV_RDI_TO_RBX);
         self.write(XOR_R14_TO_R14);
-        self.call_absolute(runtime::create_tape as *const u8);
+        self.call_runtime(Function::CreateTape);
         self.write(TEST_RAX_WITH_RAX);
         self.write(JE_JZ_REL32);
         self.make_placeholder(last_ir_label, 1);
@@ -261,12 +788,55 @@ impl Compiler {
         self.write((-1i8).to_le_bytes());
         self.def_label(ir_label, 2);
         self.write(MOV_R12_TO_RDI);
-        self.call_absolute(runtime::destroy_tape as *const u8);
+        self.call_runtime(Function::DestroyTape);
         self.write(MOV_R14B_TO_AL);
         self.write(POP_RBX);
         self.write(POP_R12);
         self.write(POP_R13);
         self.write(POP_R14);
+        self.write(POP_R15);
+        self.write(RET);
+    }
+
+    pub fn write_fragment_enter(&mut self) {
+        self.write(PUSH_R15);
+        self.write(PUSH_R14);
+        self.write(PUSH_R13);
+        self.write(PUSH_R12);
+        self.write(PUSH_RBX);
+        self.write(MOV_RDI_TO_RBX);
+        self.write(MOV_MEM_RBX_TO_R12);
+        self.write(MOV_MEM_RBX_8_TO_R13);
+        self.write(MOV_MEM_RBX_16_TO_R14);
+    }
+
+    /// Loads the tape from the context, and jumps to where the code was
+    /// suspended, if it was.
+    pub fn write_resumable_enter(&mut self) {
+        self.write_fragment_enter();
+        self.write(MOV_MEM_RBX_24_TO_RAX);
+        self.write(TEST_RAX_WITH_RAX);
+        self.write(JE_JZ_REL32);
+        self.make_placeholder(0, 0);
+        self.write(JMP_ABS_RAX);
+    }
+
+    pub fn write_fragment_leave(&mut self, ir_label: usize) {
+        self.write(XOR_EAX_TO_EAX);
+        self.write(JMP_REL32);
+        self.make_placeholder(ir_label, 2);
+        self.def_label(ir_label, 1);
+        self.write(MOV_IMM8_TO_AL);
+        self.write((-1i8).to_le_bytes());
+        self.def_label(ir_label, 2);
+        self.write(MOV_R12_TO_MEM_RBX);
+        self.write(MOV_R13_TO_MEM_RBX_8);
+        self.write(MOV_R14_TO_MEM_RBX_16);
+        self.write(POP_RBX);
+        self.write(POP_R12);
+        self.write(POP_R13);
+        self.write(POP_R14);
+        self.write(POP_R15);
         self.write(RET);
     }
 
@@ -279,12 +849,24 @@ impl Compiler {
     }
 
     pub fn write_next(&mut self, ir_label: usize, last_ir_label: usize) {
+        self.write(INC_R14);
+        self.write_grow_next_if_end(ir_label, last_ir_label);
+    }
+
+    /// Grows the tape forwards if the cursor is at its end, keeping the
+    /// cursor in bounds.
+    pub fn write_grow_next_if_end(
+        &mut self,
+        ir_label: usize,
+        last_ir_label: usize,
+    ) {
         self.write(CMP_R14_WITH_R13);
         self.write(JNE_JNZ_REL32);
         self.make_placeholder(ir_label, 1);
         self.write(MOV_R12_TO_RDI);
         self.write(MOV_R13_TO_RSI);
-        self.call_absolute(runtime::grow_next as *const u8);
+        self.write_interface_to_rdx();
+        self.call_runtime(Function::GrowNext);
         self.write(TEST_RAX_WITH_RAX);
         self.write(JE_JZ_REL32);
         self.make_placeholder(last_ir_label, 1);
@@ -292,7 +874,6 @@ impl Compiler {
         self.write(ADD_IMM32_TO_R13);
         self.write((runtime::TAPE_CHUNK_SIZE as u32).to_le_bytes());
         self.def_label(ir_label, 1);
-        self.write(INC_R14);
     }
 
     pub fn write_prev(&mut self, ir_label: usize, last_ir_label: usize) {
@@ -301,7 +882,8 @@ impl Compiler {
         self.make_placeholder(ir_label, 1);
         self.write(MOV_R12_TO_RDI);
         self.write(MOV_R13_TO_RSI);
-        self.call_absolute(runtime::grow_prev as *const u8);
+        self.write_interface_to_rdx();
+        self.call_runtime(Function::GrowPrev);
         self.write(TEST_RAX_WITH_RAX);
         self.write(JE_JZ_REL32);
         self.make_placeholder(last_ir_label, 1);
@@ -314,33 +896,42 @@ impl Compiler {
         self.write(DEC_R14);
     }
 
+    /// Passes the interface to a growth function, which counts growths in it.
+    /// Resumable code keeps the interface in its context.
+    pub fn write_interface_to_rdx(&mut self) {
+        if self.resumable {
+            self.write(MOV_MEM_RBX_40_TO_RDX);
+        } else {
+            self.write(MOV_RBX_TO_RDX);
+        }
+    }
+
     pub fn write_put(&mut self, last_ir_label: usize) {
-        self.write(MOV_RBX_TO_RDI);
+        if self.resumable {
+            self.write(MOV_MEM_RBX_40_TO_RDI);
+        } else {
+            self.write(MOV_RBX_TO_RDI);
+        }
         self.write(XOR_EAX_TO_EAX);
         self.write(MOV_MEM_R12_R14_TO_AL);
         self.write(MOV_AX_TO_SI);
-        self.call_absolute(runtime::put as *const u8);
+        self.call_runtime(Function::Put);
         self.write(TEST_AL_WITH_AL);
         self.write(JS_REL32);
         self.make_placeholder(last_ir_label, 1);
     }
 
     pub fn write_get(&mut self, ir_label: usize, last_ir_label: usize) {
-        self.write(CMP_R14_WITH_R13);
-        self.write(JNE_JNZ_REL32);
-        self.make_placeholder(ir_label, 1);
-        self.write(MOV_R12_TO_RDI);
-        self.write(MOV_R13_TO_RSI);
-        self.call_absolute(runtime::grow_next as *const u8);
-        self.write(TEST_RAX_WITH_RAX);
-        self.write(JE_JZ_REL32);
-        self.make_placeholder(last_ir_label, 1);
-        self.write(MOV_RAX_TO_R12);
-        self.write(ADD_IMM32_TO_R13);
-        self.write((runtime::TAPE_CHUNK_SIZE as u32).to_le_bytes());
-        self.def_label(ir_label, 1);
+        // Input is written to two cells, so the next one must exist too.
+        self.write(INC_R14);
+        self.write_grow_next_if_end(ir_label, last_ir_label);
+        self.write(DEC_R14);
+        if self.resumable {
+            self.write_suspend(ir_label, last_ir_label);
+            return;
+        }
         self.write(MOV_RBX_TO_RDI);
-        self.call_absolute(runtime::get as *const u8);
+        self.call_runtime(Function::Get);
         self.write(TEST_AX_WITH_AX);
         self.write(JS_REL32);
         self.make_placeholder(last_ir_label, 1);
@@ -349,6 +940,21 @@ impl Compiler {
         self.write(MOV_AX_TO_MEM_R12_R14);
     }
 
+    /// Returns to the caller asking for input, which is written to the tape
+    /// once resumed.
+    pub fn write_suspend(&mut self, ir_label: usize, last_ir_label: usize) {
+        self.write(LEA_RIP_REL32_TO_RAX);
+        self.make_placeholder(ir_label, 3);
+        self.write(MOV_RAX_TO_MEM_RBX_24);
+        self.write(MOV_IMM8_TO_AL);
+        self.write(STATUS_NEEDS_INPUT.to_le_bytes());
+        self.write(JMP_REL32);
+        self.make_placeholder(last_ir_label, 2);
+        self.def_label(ir_label, 3);
+        self.write(MOV_MEM_RBX_32_TO_AX);
+        self.write(MOV_AX_TO_MEM_R12_R14);
+    }
+
     pub fn write_halt(&mut self, last_ir_label: usize) {
         self.write(JMP_REL32);
         self.make_placeholder(last_ir_label, 0);
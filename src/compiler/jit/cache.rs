@@ -0,0 +1,473 @@
+//! Persistent on-disk cache of Just-In-Time compiled code.
+//!
+//! Entries hold position-independent machine code together with a relocation
+//! table of the runtime function addresses, which are patched when an entry
+//! is loaded. Entries are named by a hash of the program, and hold the whole
+//! program so that programs with the same hash are told apart. Entries written
+//! by a different version of catbf or of the code generator, or against a
+//! different runtime layout, are ignored and overwritten.
+
+use super::{
+    generate, load,
+    runtime::{self, Function},
+    Artifact, Error, Executable, Options, Relocation, CODEGEN_VERSION,
+};
+use crate::ir::{Instruction, Program};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+const MAGIC: &[u8; 8] = b"CATBFJIT";
+
+/// Version of the entry format, bumped on every incompatible change.
+const FORMAT_VERSION: u32 = 4;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A directory of cached compiled programs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cache {
+    directory: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P>(directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self { directory: directory.into() }
+    }
+
+    /// `$XDG_CACHE_HOME/catbf`, or `$HOME/.cache/catbf`.
+    pub fn default_directory() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(base.join("catbf"))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Hash of the program and of the options that affect generated code.
+    pub fn key(program: &Program, options: &Options) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.write(&identity(program, options));
+        hasher.finish()
+    }
+
+    /// Path of the entry with the given key.
+    pub fn entry_path(&self, key: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.jit", key))
+    }
+
+    /// Loads the program from the cache, if a valid entry exists.
+    pub fn load(
+        &self,
+        program: &Program,
+        options: &Options,
+    ) -> Result<Option<Executable>, Error> {
+        let identity = identity(program, options);
+        let key = Self::key(program, options);
+        let path = self.entry_path(key);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(None)
+            },
+            Err(error) => Err(Error::Cache(path, error))?,
+        };
+        match decode(&bytes, key, &identity) {
+            Some(artifact) => Ok(Some(load(&artifact, program, options)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads the program from the cache, or compiles it and stores the result.
+    pub fn compile(
+        &self,
+        program: &Program,
+        options: &Options,
+    ) -> Result<Executable, Error> {
+        if let Some(executable) = self.load(program, options)? {
+            return Ok(executable);
+        }
+        let key = Self::key(program, options);
+        let artifact = generate(program, options)?;
+        self.store(key, &identity(program, options), &artifact)?;
+        load(&artifact, program, options)
+    }
+
+    fn store(
+        &self,
+        key: u64,
+        identity: &[u8],
+        artifact: &Artifact,
+    ) -> Result<(), Error> {
+        fs::create_dir_all(&self.directory)
+            .map_err(|error| Error::Cache(self.directory.clone(), error))?;
+        let path = self.entry_path(key);
+        // Writes and renames so concurrent readers never see partial entries.
+        let temp_path =
+            self.directory.join(format!("{:016x}.{}.tmp", key, process::id()));
+        fs::write(&temp_path, encode(artifact, key, identity))
+            .map_err(|error| Error::Cache(temp_path.clone(), error))?;
+        fs::rename(&temp_path, &path)
+            .map_err(|error| Error::Cache(path, error))?;
+        Ok(())
+    }
+}
+
+/// Encoding of the program and of the options that affect generated code.
+fn identity(program: &Program, options: &Options) -> Vec<u8> {
+    // Options ignored here do not change the generated code.
+    let Options {
+        perf_map: _,
+        perf_map_path: _,
+        gdb_jit: _,
+        source_name: _,
+        mapping_mode: _,
+        resumable,
+    } = options;
+
+    let mut buf = vec![u8::from(*resumable)];
+    buf.extend_from_slice(&(program.code.len() as u64).to_le_bytes());
+    for instruction in &program.code {
+        let (tag, label) = match *instruction {
+            Instruction::Halt => (0, 0),
+            Instruction::Inc => (1, 0),
+            Instruction::Dec => (2, 0),
+            Instruction::Next => (3, 0),
+            Instruction::Prev => (4, 0),
+            Instruction::Get => (5, 0),
+            Instruction::Put => (6, 0),
+            Instruction::Jz(label) => (7, label),
+            Instruction::Jnz(label) => (8, label),
+        };
+        buf.push(tag);
+        buf.extend_from_slice(&(label as u64).to_le_bytes());
+    }
+    buf
+}
+
+/// Hash of everything generated code depends on besides the program itself.
+fn runtime_layout() -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.write(env::consts::ARCH.as_bytes());
+    hasher.write(env::consts::OS.as_bytes());
+    hasher.write_u64(runtime::TAPE_CHUNK_SIZE as u64);
+    for function in Function::ALL {
+        hasher.write(function.name().as_bytes());
+    }
+    hasher.finish()
+}
+
+fn encode(artifact: &Artifact, key: u64, identity: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&CODEGEN_VERSION.to_le_bytes());
+    buf.extend_from_slice(&(VERSION.len() as u64).to_le_bytes());
+    buf.extend_from_slice(VERSION.as_bytes());
+    buf.extend_from_slice(&runtime_layout().to_le_bytes());
+    buf.extend_from_slice(&key.to_le_bytes());
+    buf.extend_from_slice(&(identity.len() as u64).to_le_bytes());
+    buf.extend_from_slice(identity);
+
+    buf.extend_from_slice(&(artifact.code.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&artifact.code);
+
+    buf.extend_from_slice(&(artifact.relocations.len() as u64).to_le_bytes());
+    for relocation in &artifact.relocations {
+        buf.extend_from_slice(&(relocation.offset as u64).to_le_bytes());
+        let index = Function::ALL
+            .iter()
+            .position(|function| *function == relocation.function)
+            .unwrap_or_default();
+        buf.push(index as u8);
+    }
+
+    buf.extend_from_slice(&(artifact.ir_offsets.len() as u64).to_le_bytes());
+    for offset in &artifact.ir_offsets {
+        buf.extend_from_slice(&(*offset as u64).to_le_bytes());
+    }
+
+    let mut hasher = Fnv::new();
+    hasher.write(&buf);
+    buf.extend_from_slice(&hasher.finish().to_le_bytes());
+    buf
+}
+
+/// Decodes an entry, returning `None` if it is corrupted, was not written by
+/// this very version and runtime, or holds another program.
+fn decode(bytes: &[u8], key: u64, identity: &[u8]) -> Option<Artifact> {
+    let (contents, checksum) = bytes.split_at(bytes.len().checked_sub(8)?);
+    let mut hasher = Fnv::new();
+    hasher.write(contents);
+    if hasher.finish().to_le_bytes() != checksum {
+        return None;
+    }
+
+    let mut reader = Reader { bytes: contents };
+    if reader.take(MAGIC.len())? != MAGIC
+        || reader.take(4)? != FORMAT_VERSION.to_le_bytes()
+        || reader.take(4)? != CODEGEN_VERSION.to_le_bytes()
+    {
+        return None;
+    }
+    let version_len = reader.len()?;
+    if reader.take(version_len)? != VERSION.as_bytes()
+        || reader.u64()? != runtime_layout()
+        || reader.u64()? != key
+    {
+        return None;
+    }
+    let identity_len = reader.len()?;
+    if reader.take(identity_len)? != identity {
+        return None;
+    }
+
+    let code_len = reader.len()?;
+    let code = reader.take(code_len)?.to_vec();
+
+    let relocation_count = reader.len()?;
+    let mut relocations = Vec::new();
+    for _ in 0 .. relocation_count {
+        let offset = reader.len()?;
+        let function = *Function::ALL.get(usize::from(reader.take(1)?[0]))?;
+        if offset.checked_add(8)? > code.len() {
+            return None;
+        }
+        relocations.push(Relocation { offset, function });
+    }
+
+    let offset_count = reader.len()?;
+    let mut ir_offsets = Vec::new();
+    for _ in 0 .. offset_count {
+        let offset = reader.len()?;
+        if offset > code.len() {
+            return None;
+        }
+        ir_offsets.push(offset);
+    }
+
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+
+    Some(Artifact { code, relocations, ir_offsets })
+}
+
+#[derive(Debug)]
+struct Reader<'bytes> {
+    bytes: &'bytes [u8],
+}
+
+impl<'bytes> Reader<'bytes> {
+    fn take(&mut self, count: usize) -> Option<&'bytes [u8]> {
+        if count > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+}
+
+/// 64-bit FNV-1a, used because its output is stable across Rust versions,
+/// unlike the standard library hashers.
+#[derive(Debug, Clone, Copy)]
+struct Fnv {
+    state: u64,
+}
+
+impl Fnv {
+    fn new() -> Self {
+        Self { state: 0xcbf29ce484222325 }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= u64::from(*byte);
+            self.state = self.state.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode, encode, identity, runtime_layout, Cache, Fnv, CODEGEN_VERSION,
+        FORMAT_VERSION, VERSION,
+    };
+    use crate::{
+        compiler::jit::{generate, Options, TARGET_SUPPORTED},
+        ir::Program,
+    };
+    use std::{env, fs, os::unix::fs::MetadataExt, process};
+
+    const KEY: u64 = 0x0123_4567_89ab_cdef;
+
+    /// Offsets of the fields in an entry.
+    const FORMAT_AT: usize = 8;
+    const CODEGEN_AT: usize = 12;
+    const VERSION_AT: usize = 24;
+
+    fn program() -> Program {
+        ",[>+<-]>.".parse().unwrap()
+    }
+
+    /// An entry of the program, and the identity it was stored with.
+    fn entry() -> (Vec<u8>, Vec<u8>) {
+        let options = Options::default();
+        let identity = identity(&program(), &options);
+        let artifact = generate(&program(), &options).unwrap();
+        (encode(&artifact, KEY, &identity), identity)
+    }
+
+    /// Recomputes the checksum of an edited entry.
+    fn reseal(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - 8);
+        let mut hasher = Fnv::new();
+        hasher.write(bytes);
+        bytes.extend_from_slice(&hasher.finish().to_le_bytes());
+    }
+
+    /// Decodes the entry after overwriting the bytes at the given offset.
+    fn decode_edited(at: usize, replacement: &[u8]) -> bool {
+        let (mut bytes, identity) = entry();
+        bytes[at .. at + replacement.len()].copy_from_slice(replacement);
+        reseal(&mut bytes);
+        decode(&bytes, KEY, &identity).is_some()
+    }
+
+    #[test]
+    fn decodes_encoded_entry() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        let options = Options::default();
+        let artifact = generate(&program(), &options).unwrap();
+        let (bytes, identity) = entry();
+        assert_eq!(decode(&bytes, KEY, &identity), Some(artifact));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        assert!(!decode_edited(FORMAT_AT, &(FORMAT_VERSION + 1).to_le_bytes()));
+        assert!(!decode_edited(
+            CODEGEN_AT,
+            &(CODEGEN_VERSION + 1).to_le_bytes()
+        ));
+        let mut version = VERSION.as_bytes().to_vec();
+        version[0] ^= 1;
+        assert!(!decode_edited(VERSION_AT, &version));
+    }
+
+    #[test]
+    fn rejects_other_runtime_layout() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        let at = VERSION_AT + VERSION.len();
+        assert!(decode_edited(at, &runtime_layout().to_le_bytes()));
+        assert!(!decode_edited(at, &(runtime_layout() ^ 1).to_le_bytes()));
+    }
+
+    #[test]
+    fn rejects_other_program() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        let (bytes, stored) = entry();
+        assert!(decode(&bytes, KEY ^ 1, &stored).is_none());
+        // Same key, as if the hashes of both programs collided.
+        let other =
+            identity(&",[>-<-]>.".parse().unwrap(), &Options::default());
+        assert!(decode(&bytes, KEY, &other).is_none());
+        let resumable = Options { resumable: true, ..Options::default() };
+        let other = identity(&program(), &resumable);
+        assert!(decode(&bytes, KEY, &other).is_none());
+    }
+
+    #[test]
+    fn rejects_corrupted_entry() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        let (bytes, identity) = entry();
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(decode(&flipped, KEY, &identity).is_none());
+        for len in [0, 7, 8, bytes.len() / 2, bytes.len() - 1] {
+            let mut truncated = bytes[.. len].to_vec();
+            if len >= 8 {
+                reseal(&mut truncated);
+            }
+            assert!(decode(&truncated, KEY, &identity).is_none(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_past_the_code() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        let options = Options::default();
+        let mut artifact = generate(&program(), &options).unwrap();
+        artifact.ir_offsets.push(artifact.code.len() + 1);
+        let identity = identity(&program(), &options);
+        let bytes = encode(&artifact, KEY, &identity);
+        assert!(decode(&bytes, KEY, &identity).is_none());
+    }
+
+    #[test]
+    fn compile_stores_then_loads() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        let directory =
+            env::temp_dir().join(format!("catbf-cache-{}", process::id()));
+        fs::remove_dir_all(&directory).ok();
+        let cache = Cache::new(&directory);
+        let options = Options::default();
+        let program = program();
+        assert!(cache.load(&program, &options).unwrap().is_none());
+
+        cache.compile(&program, &options).unwrap();
+        let path = cache.entry_path(Cache::key(&program, &options));
+        // Storing an entry again would replace the file.
+        let inode = fs::metadata(&path).unwrap().ino();
+
+        let executable = cache.compile(&program, &options).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+        let mut output = Vec::new();
+        executable.run(&[5][..], &mut output).unwrap();
+        // The flag of the read byte is added to it.
+        assert_eq!(output, [6]);
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,238 @@
+//! Debugging and profiling support for JIT compiled code: Linux perf maps and
+//! the ELF objects registered with GDB.
+
+use crate::{
+    compiler::{
+        dwarf::{self, LineRow},
+        elf::{self, Object, Section},
+    },
+    ir::{Instruction, Program},
+};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Name of the symbol covering code that is not inside of any loop.
+pub const MAIN_SYMBOL: &str = "catbf_main";
+
+/// A named range of the generated machine code.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol {
+    pub name: String,
+    /// Offset from the start of the generated code.
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Debug information collected while compiling.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DebugInfo {
+    pub symbols: Vec<Symbol>,
+    /// Line table rows, with addresses relative to the start of the code.
+    pub lines: Vec<LineRow>,
+}
+
+impl DebugInfo {
+    /// Splits the code into non-overlapping symbols, one per innermost loop
+    /// (loops are named after the location of their `[`), and maps each IR
+    /// instruction back to its source location.
+    pub fn collect(
+        ir_offsets: &[usize],
+        code_len: usize,
+        program: &Program,
+    ) -> Self {
+        let offset_of = |ir_label: usize| ir_offsets.get(ir_label).copied();
+
+        let mut owners = Vec::with_capacity(program.code.len());
+        let mut open_loops = Vec::new();
+        for (ir_label, instruction) in program.code.iter().enumerate() {
+            match instruction {
+                Instruction::Jz(_) => {
+                    open_loops.push(ir_label);
+                    owners.push(Some(ir_label));
+                },
+                Instruction::Jnz(_) => owners.push(open_loops.pop()),
+                _ => owners.push(open_loops.last().copied()),
+            }
+        }
+
+        let mut symbols: Vec<Symbol> = Vec::new();
+        let mut push_range = |name: String, start: usize, end: usize| {
+            if start >= end {
+                return;
+            }
+            match symbols.last_mut() {
+                Some(last) if last.name == name => {
+                    last.len = end - last.offset;
+                },
+                _ => symbols.push(Symbol {
+                    name,
+                    offset: start,
+                    len: end - start,
+                }),
+            }
+        };
+
+        let first_offset = offset_of(0).unwrap_or(code_len);
+        push_range(MAIN_SYMBOL.to_owned(), 0, first_offset);
+        for (ir_label, owner) in owners.iter().enumerate() {
+            let Some(start) = offset_of(ir_label) else { continue };
+            let end = offset_of(ir_label + 1).unwrap_or(code_len);
+            let name = match owner {
+                Some(loop_start) => loop_symbol_name(program, *loop_start),
+                None => MAIN_SYMBOL.to_owned(),
+            };
+            push_range(name, start, end);
+        }
+        let last_offset = offset_of(program.code.len()).unwrap_or(code_len);
+        push_range(MAIN_SYMBOL.to_owned(), last_offset, code_len);
+
+        let mut lines: Vec<LineRow> = Vec::new();
+        for ir_label in 0 .. program.code.len() {
+            let (Some(offset), Some(location)) =
+                (offset_of(ir_label), program.location(ir_label))
+            else {
+                continue;
+            };
+            let row = LineRow {
+                address: offset as u64,
+                line: location.line,
+                column: location.column,
+            };
+            match lines.last_mut() {
+                Some(last) if last.address == row.address => *last = row,
+                _ => lines.push(row),
+            }
+        }
+        if let Some(first) = lines.first_mut() {
+            first.address = 0;
+        }
+
+        Self { symbols, lines }
+    }
+
+    /// Builds an in-memory ELF object describing code loaded at `address`,
+    /// suitable for the GDB JIT interface.
+    #[cfg(feature = "gdb-jit")]
+    pub fn elf_object(
+        &self,
+        source_name: &str,
+        address: usize,
+        code_len: usize,
+    ) -> Vec<u8> {
+        let mut object = Object::new(elf::ET_REL);
+
+        let mut text = Section::new(".text", elf::SHT_NOBITS, Vec::new());
+        text.flags = elf::SHF_ALLOC | elf::SHF_EXECINSTR;
+        text.addr = address as u64;
+        text.align = 16;
+        text.nobits_size = code_len as u64;
+        let text_index = object.add_section(text);
+        self.add_line_info(&mut object, source_name, address as u64, code_len);
+
+        // The main symbol covers the whole code, loops are nested in it.
+        object.add_symbol(elf::Symbol {
+            name: MAIN_SYMBOL.to_owned(),
+            value: 0,
+            size: code_len as u64,
+            section: text_index,
+            bind: elf::STB_GLOBAL,
+            kind: elf::STT_FUNC,
+        });
+        for symbol in &self.symbols {
+            if symbol.name == MAIN_SYMBOL {
+                continue;
+            }
+            object.add_symbol(elf::Symbol {
+                name: symbol.name.clone(),
+                value: symbol.offset as u64,
+                size: symbol.len as u64,
+                section: text_index,
+                bind: elf::STB_LOCAL,
+                kind: elf::STT_FUNC,
+            });
+        }
+
+        object.finish()
+    }
+
+    /// Adds DWARF sections mapping the code, loaded at `low_pc`, back to the
+    /// source file.
+    pub fn add_line_info(
+        &self,
+        object: &mut Object,
+        source_name: &str,
+        low_pc: u64,
+        code_len: usize,
+    ) {
+        let high_pc = low_pc + code_len as u64;
+        let rows: Vec<_> = self
+            .lines
+            .iter()
+            .map(|row| LineRow { address: row.address + low_pc, ..*row })
+            .collect();
+        let comp_dir = std::env::current_dir()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let sections = dwarf::debug_sections(
+            source_name,
+            &comp_dir,
+            low_pc,
+            high_pc,
+            &rows,
+        );
+        object.add_section(Section::new(
+            ".debug_abbrev",
+            elf::SHT_PROGBITS,
+            sections.abbrev,
+        ));
+        object.add_section(Section::new(
+            ".debug_info",
+            elf::SHT_PROGBITS,
+            sections.info,
+        ));
+        object.add_section(Section::new(
+            ".debug_line",
+            elf::SHT_PROGBITS,
+            sections.line,
+        ));
+    }
+}
+
+fn loop_symbol_name(program: &Program, loop_start: usize) -> String {
+    match program.location(loop_start) {
+        Some(location) => {
+            format!("catbf_loop_{}_{}", location.line, location.column)
+        },
+        None => format!("catbf_loop_label_{}", loop_start),
+    }
+}
+
+/// Path of the perf map of the current process.
+pub fn default_perf_map_path() -> PathBuf {
+    PathBuf::from(format!("/tmp/perf-{}.map", process::id()))
+}
+
+/// Appends the given symbols of code loaded at `address` to a perf map, in the
+/// `START SIZE NAME` format expected by `perf`.
+pub fn append_perf_map(
+    path: &Path,
+    address: usize,
+    symbols: &[Symbol],
+) -> io::Result<()> {
+    let mut entries = String::new();
+    for symbol in symbols {
+        entries.push_str(&format!(
+            "{:x} {:x} {}\n",
+            address + symbol.offset,
+            symbol.len,
+            symbol.name
+        ));
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(entries.as_bytes())?;
+    Ok(())
+}
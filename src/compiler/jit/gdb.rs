@@ -0,0 +1,115 @@
+//! The GDB JIT compilation interface, through which GDB learns of the symbols
+//! and source lines of generated code. It defines the `__jit_debug_*` symbols
+//! GDB looks up by name, so only one copy may be linked into a process, which
+//! is why it is behind the `gdb-jit` feature.
+
+use std::{ptr, sync::Mutex};
+
+const JIT_NOACTION: u32 = 0;
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+#[repr(C)]
+#[derive(Debug)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+/// GDB sets a breakpoint in this function and reads `__jit_debug_descriptor`
+/// whenever it is called.
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn __jit_debug_register_code() {
+    // Keeps the function from being optimized away or merged.
+    std::hint::black_box(());
+}
+
+#[no_mangle]
+pub static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JIT_NOACTION,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+/// Serializes accesses to `__jit_debug_descriptor`.
+static DESCRIPTOR_LOCK: Mutex<()> = Mutex::new(());
+
+/// An ELF object registered with the GDB JIT interface, unregistered when
+/// dropped.
+#[derive(Debug)]
+pub(super) struct GdbRegistration {
+    entry: Box<JitCodeEntry>,
+    object: Box<[u8]>,
+}
+
+// The entry is only accessed through the descriptor, under `DESCRIPTOR_LOCK`.
+unsafe impl Send for GdbRegistration {}
+unsafe impl Sync for GdbRegistration {}
+
+impl GdbRegistration {
+    pub fn register(object: Vec<u8>) -> Self {
+        let object = object.into_boxed_slice();
+        let mut entry = Box::new(JitCodeEntry {
+            next_entry: ptr::null_mut(),
+            prev_entry: ptr::null_mut(),
+            symfile_addr: object.as_ptr(),
+            symfile_size: object.len() as u64,
+        });
+
+        let _guard =
+            DESCRIPTOR_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        unsafe {
+            let descriptor = &raw mut __jit_debug_descriptor;
+            let entry_ptr: *mut JitCodeEntry = &mut *entry;
+            entry.next_entry = (*descriptor).first_entry;
+            if let Some(next) = entry.next_entry.as_mut() {
+                next.prev_entry = entry_ptr;
+            }
+            (*descriptor).first_entry = entry_ptr;
+            (*descriptor).relevant_entry = entry_ptr;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+            __jit_debug_register_code();
+        }
+
+        Self { entry, object }
+    }
+
+    pub fn object(&self) -> &[u8] {
+        &self.object
+    }
+}
+
+impl Drop for GdbRegistration {
+    fn drop(&mut self) {
+        let _guard =
+            DESCRIPTOR_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        unsafe {
+            let descriptor = &raw mut __jit_debug_descriptor;
+            let entry_ptr: *mut JitCodeEntry = &mut *self.entry;
+            if let Some(prev) = self.entry.prev_entry.as_mut() {
+                prev.next_entry = self.entry.next_entry;
+            } else {
+                (*descriptor).first_entry = self.entry.next_entry;
+            }
+            if let Some(next) = self.entry.next_entry.as_mut() {
+                next.prev_entry = self.entry.prev_entry;
+            }
+            (*descriptor).relevant_entry = entry_ptr;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+        }
+    }
+}
@@ -0,0 +1,451 @@
+//! Allocation of executable memory for generated code.
+//!
+//! Memory is never writable and executable at the same time. Pages are either
+//! mapped writable while code is copied and only then made executable, or
+//! backed by an anonymous file mapped twice: once writable and once
+//! executable. Either way, small pieces of code share pages.
+
+use super::Error;
+use std::{
+    io, ptr,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// Alignment of each piece of code in a shared chunk.
+const CODE_ALIGN: usize = 16;
+
+/// Size of the chunks from which code is carved.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How executable memory is mapped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MappingMode {
+    /// Pages are mapped writable while code is copied and then switched to
+    /// read and execute. Adding code to a page already holding some replaces
+    /// it with an executable copy holding both, through `mremap`, so running
+    /// code never sees it writable.
+    #[default]
+    Protect,
+    /// Code is written through a writable view of a `memfd_create` file and
+    /// executed from a separate read and execute view of the same file, so
+    /// that pages are never remapped. Useful where changing the protection of
+    /// pages to executable is forbidden.
+    DualMapping,
+}
+
+/// A region of mapped memory, unmapped when the last piece of code using it
+/// is dropped.
+#[derive(Debug)]
+struct Mapping {
+    /// Executable view, or in protect mode, memory that is only executable
+    /// where code was placed.
+    exec: *mut u8,
+    /// Writable view of the same memory, only in dual-mapping mode.
+    write: *mut u8,
+    len: usize,
+}
+
+// The mapping is only written through `CodeAllocator`, while holding its lock,
+// and never at offsets already handed out.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    unsafe fn anonymous(len: usize) -> Result<Self, Error> {
+        let len = ceil_to_page(len);
+        let ptr = map_anonymous(len).map_err(Error::AllocError)?;
+        Ok(Self { exec: ptr, write: ptr::null_mut(), len })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    unsafe fn dual(_len: usize) -> Result<Self, Error> {
+        Err(Error::UnsupportedTarget)
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn dual(len: usize) -> Result<Self, Error> {
+        let len = ceil_to_page(len);
+        let fd = libc::memfd_create(c"catbf-jit".as_ptr(), libc::MFD_CLOEXEC);
+        if fd < 0 {
+            Err(Error::AllocError(io::Error::last_os_error()))?;
+        }
+        let result = Self::map_dual(fd, len);
+        libc::close(fd);
+        result
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn map_dual(fd: libc::c_int, len: usize) -> Result<Self, Error> {
+        if libc::ftruncate(fd, len as libc::off_t) < 0 {
+            Err(Error::AllocError(io::Error::last_os_error()))?;
+        }
+        let write =
+            map(len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd)
+                .map_err(Error::AllocError)?;
+        let exec = match map(
+            len,
+            libc::PROT_READ | libc::PROT_EXEC,
+            libc::MAP_SHARED,
+            fd,
+        ) {
+            Ok(exec) => exec,
+            Err(error) => {
+                libc::munmap(write as *mut libc::c_void, len);
+                Err(Error::Permission(error))?
+            },
+        };
+        Ok(Self { exec, write, len })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.exec as *mut libc::c_void, self.len);
+            if !self.write.is_null() {
+                libc::munmap(self.write as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
+
+/// Executable memory holding a single piece of generated code.
+#[derive(Debug)]
+pub struct CodeMemory {
+    mapping: Arc<Mapping>,
+    offset: usize,
+    len: usize,
+}
+
+impl CodeMemory {
+    /// Start of the executable code.
+    pub fn as_ptr(&self) -> *const u8 {
+        unsafe { self.mapping.exec.add(self.offset) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug)]
+struct Chunk {
+    mapping: Arc<Mapping>,
+    used: usize,
+}
+
+/// Allocator of executable memory.
+#[derive(Debug)]
+pub struct CodeAllocator {
+    mode: MappingMode,
+    current: Mutex<Option<Chunk>>,
+}
+
+impl CodeAllocator {
+    pub fn new(mode: MappingMode) -> Self {
+        Self { mode, current: Mutex::new(None) }
+    }
+
+    /// Process-wide allocator for the given mode.
+    pub fn global(mode: MappingMode) -> &'static Self {
+        static PROTECT: OnceLock<CodeAllocator> = OnceLock::new();
+        static DUAL_MAPPING: OnceLock<CodeAllocator> = OnceLock::new();
+        let cell = match mode {
+            MappingMode::Protect => &PROTECT,
+            MappingMode::DualMapping => &DUAL_MAPPING,
+        };
+        cell.get_or_init(|| Self::new(mode))
+    }
+
+    pub fn mode(&self) -> MappingMode {
+        self.mode
+    }
+
+    /// Copies the given code into executable memory.
+    pub fn allocate(&self, code: &[u8]) -> Result<CodeMemory, Error> {
+        // Big pieces of code get their own mapping instead of wasting most of
+        // a chunk.
+        if code.len() > CHUNK_SIZE / 2 {
+            let mapping = Arc::new(unsafe { self.map(code.len())? });
+            unsafe { self.place(&mapping, 0, code)? };
+            return Ok(CodeMemory { mapping, offset: 0, len: code.len() });
+        }
+
+        let mut current =
+            self.current.lock().unwrap_or_else(|error| error.into_inner());
+        let align = self.code_align();
+        let fits = current.as_ref().is_some_and(|chunk| {
+            ceil_to(chunk.used, align) + code.len().max(1) <= chunk.mapping.len
+        });
+        if !fits {
+            let mapping = Arc::new(unsafe { self.map(CHUNK_SIZE)? });
+            *current = Some(Chunk { mapping, used: 0 });
+        }
+        let chunk = current.as_mut().expect("chunk was just allocated");
+
+        let offset = ceil_to(chunk.used, align);
+        unsafe { self.place(&chunk.mapping, offset, code)? };
+        chunk.used = offset + code.len();
+
+        Ok(CodeMemory {
+            mapping: chunk.mapping.clone(),
+            offset,
+            len: code.len(),
+        })
+    }
+
+    /// Alignment of each piece of code in a chunk. Without `mremap`, code
+    /// cannot be added to pages already executable, so each piece of code
+    /// gets its own pages.
+    fn code_align(&self) -> usize {
+        if self.mode == MappingMode::Protect && !cfg!(target_os = "linux") {
+            page_size()
+        } else {
+            CODE_ALIGN
+        }
+    }
+
+    unsafe fn map(&self, len: usize) -> Result<Mapping, Error> {
+        match self.mode {
+            MappingMode::Protect => Mapping::anonymous(len),
+            MappingMode::DualMapping => Mapping::dual(len),
+        }
+    }
+
+    /// Copies code to the given offset of a mapping, where nothing was placed
+    /// yet, and makes it executable.
+    unsafe fn place(
+        &self,
+        mapping: &Mapping,
+        offset: usize,
+        code: &[u8],
+    ) -> Result<(), Error> {
+        match self.mode {
+            MappingMode::Protect => protect_code(mapping, offset, code),
+            MappingMode::DualMapping => {
+                write_code(mapping, offset, code);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Copies code into an anonymous mapping and makes its pages executable. If
+/// the first page already holds code, which may be running, the pages are
+/// replaced by executable copies with the code added, instead of being made
+/// writable again.
+unsafe fn protect_code(
+    mapping: &Mapping,
+    offset: usize,
+    code: &[u8],
+) -> Result<(), Error> {
+    let start = offset / page_size() * page_size();
+    let len = ceil_to_page(offset + code.len().max(1)) - start;
+    let pages = mapping.exec.add(start);
+    let protection = libc::PROT_READ | libc::PROT_EXEC;
+
+    // Pages are untouched when the code starts at the first of them.
+    if offset == start {
+        ptr::copy_nonoverlapping(code.as_ptr(), pages, code.len());
+        if libc::mprotect(pages as *mut libc::c_void, len, protection) < 0 {
+            Err(Error::Permission(io::Error::last_os_error()))?;
+        }
+        return Ok(());
+    }
+
+    let copy = map_anonymous(len).map_err(Error::AllocError)?;
+    ptr::copy_nonoverlapping(pages, copy, offset - start);
+    ptr::copy_nonoverlapping(
+        code.as_ptr(),
+        copy.add(offset - start),
+        code.len(),
+    );
+    if libc::mprotect(copy as *mut libc::c_void, len, protection) < 0 {
+        let error = io::Error::last_os_error();
+        libc::munmap(copy as *mut libc::c_void, len);
+        Err(Error::Permission(error))?;
+    }
+    if let Err(error) = replace_pages(copy, pages, len) {
+        libc::munmap(copy as *mut libc::c_void, len);
+        Err(Error::AllocError(error))?;
+    }
+    Ok(())
+}
+
+/// Moves the pages of a mapping over other pages at once.
+#[cfg(target_os = "linux")]
+unsafe fn replace_pages(
+    source: *mut u8,
+    target: *mut u8,
+    len: usize,
+) -> io::Result<()> {
+    let moved = libc::mremap(
+        source as *mut libc::c_void,
+        len,
+        len,
+        libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED,
+        target as *mut libc::c_void,
+    );
+    if moved == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Never called, as code is aligned to pages there.
+#[cfg(not(target_os = "linux"))]
+unsafe fn replace_pages(
+    _source: *mut u8,
+    _target: *mut u8,
+    _len: usize,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+unsafe fn write_code(mapping: &Mapping, offset: usize, code: &[u8]) {
+    ptr::copy_nonoverlapping(
+        code.as_ptr(),
+        mapping.write.add(offset),
+        code.len(),
+    );
+}
+
+unsafe fn map(
+    len: usize,
+    protection: libc::c_int,
+    flags: libc::c_int,
+    fd: libc::c_int,
+) -> io::Result<*mut u8> {
+    let ptr = libc::mmap(ptr::null_mut(), len, protection, flags, fd, 0);
+    if ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ptr as *mut u8)
+    }
+}
+
+unsafe fn map_anonymous(len: usize) -> io::Result<*mut u8> {
+    map(
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+    )
+}
+
+fn page_size() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+    *PAGE_SIZE
+        .get_or_init(|| unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize })
+}
+
+fn ceil_to_page(len: usize) -> usize {
+    ceil_to(len, page_size())
+}
+
+fn ceil_to(len: usize, alignment: usize) -> usize {
+    len.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        page_size, CodeAllocator, CodeMemory, MappingMode, CHUNK_SIZE,
+    };
+    use crate::compiler::jit::TARGET_SUPPORTED;
+    use std::{mem::transmute, sync::Arc, thread};
+
+    const MODES: [MappingMode; 2] =
+        [MappingMode::Protect, MappingMode::DualMapping];
+
+    /// `mov eax, value; ret`, after the given number of `nop`s.
+    fn code(value: u32, nops: usize) -> Vec<u8> {
+        let mut code = vec![0x90; nops];
+        code.push(0xb8);
+        code.extend_from_slice(&value.to_le_bytes());
+        code.push(0xc3);
+        code
+    }
+
+    fn call(memory: &CodeMemory) -> u32 {
+        unsafe {
+            let function: unsafe extern "sysv64" fn() -> u32 =
+                transmute(memory.as_ptr());
+            function()
+        }
+    }
+
+    #[test]
+    fn allocations_run() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        for mode in MODES {
+            let allocator = CodeAllocator::new(mode);
+            // Enough to fill more than one chunk, and a big piece on its own.
+            let memories: Vec<_> = (0 .. 2000)
+                .map(|i| {
+                    let memory = allocator.allocate(&code(i, 30)).unwrap();
+                    assert_eq!(call(&memory), i);
+                    memory
+                })
+                .collect();
+            let big = allocator.allocate(&code(7, CHUNK_SIZE)).unwrap();
+            assert_eq!(call(&big), 7);
+            for (i, memory) in (0 ..).zip(&memories) {
+                assert_eq!(call(memory), i, "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn small_allocations_share_pages() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        for mode in MODES {
+            let allocator = CodeAllocator::new(mode);
+            let first = allocator.allocate(&code(1, 0)).unwrap();
+            let second = allocator.allocate(&code(2, 0)).unwrap();
+            assert!(Arc::ptr_eq(&first.mapping, &second.mapping));
+            let page =
+                |memory: &CodeMemory| memory.as_ptr() as usize / page_size();
+            assert_eq!(page(&first), page(&second), "{:?}", mode);
+            assert_eq!((call(&first), call(&second)), (1, 2));
+        }
+    }
+
+    #[test]
+    fn allocations_from_other_threads_run() {
+        if !TARGET_SUPPORTED {
+            return;
+        }
+        for mode in MODES {
+            let allocator = CodeAllocator::new(mode);
+            let first = allocator.allocate(&code(1, 0)).unwrap();
+            let memories = thread::scope(|scope| {
+                // Code keeps running while pages holding it get more code.
+                let runner = scope.spawn(|| {
+                    for _ in 0 .. 100_000 {
+                        assert_eq!(call(&first), 1);
+                    }
+                });
+                let allocating = scope.spawn(|| {
+                    (2 .. 500)
+                        .map(|i| allocator.allocate(&code(i, 0)).unwrap())
+                        .collect::<Vec<_>>()
+                });
+                runner.join().unwrap();
+                allocating.join().unwrap()
+            });
+            for (i, memory) in (2 ..).zip(&memories) {
+                assert_eq!(call(memory), i, "{:?}", mode);
+            }
+        }
+    }
+}
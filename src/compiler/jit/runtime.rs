@@ -1,36 +1,110 @@
+use crate::stats::Stats;
 use std::{io, mem};
 
 pub const TAPE_CHUNK_SIZE: usize = 8192;
 
-pub struct Interface {
-    input: Box<dyn io::Read + Send + Sync + 'static>,
-    output: Box<dyn io::Write + Send + Sync + 'static>,
+/// A runtime function called by generated code through its absolute address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Function {
+    CreateTape,
+    DestroyTape,
+    GrowNext,
+    GrowPrev,
+    Get,
+    Put,
 }
 
-impl Interface {
+impl Function {
+    pub const ALL: [Self; 6] = [
+        Self::CreateTape,
+        Self::DestroyTape,
+        Self::GrowNext,
+        Self::GrowPrev,
+        Self::Get,
+        Self::Put,
+    ];
+
+    pub fn address(self) -> usize {
+        match self {
+            Self::CreateTape => create_tape as *const () as usize,
+            Self::DestroyTape => destroy_tape as *const () as usize,
+            Self::GrowNext => grow_next as *const () as usize,
+            Self::GrowPrev => grow_prev as *const () as usize,
+            Self::Get => get as *const () as usize,
+            Self::Put => put as *const () as usize,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::CreateTape => "create_tape",
+            Self::DestroyTape => "destroy_tape",
+            Self::GrowNext => "grow_next",
+            Self::GrowPrev => "grow_prev",
+            Self::Get => "get",
+            Self::Put => "put",
+        }
+    }
+}
+
+/// Input and output of a run, borrowed for its duration.
+pub struct Interface<'io> {
+    input: Box<dyn io::Read + 'io>,
+    output: Box<dyn io::Write + 'io>,
+    /// Counters kept by the runtime functions.
+    pub stats: Stats,
+    /// Why a runtime function failed, making the generated code return a
+    /// negative status.
+    error: Option<io::Error>,
+}
+
+impl<'io> Interface<'io> {
     pub fn new<R, W>(input: R, output: W) -> Self
     where
-        R: io::Read + Send + Sync + 'static,
-        W: io::Write + Send + Sync + 'static,
+        R: io::Read + 'io,
+        W: io::Write + 'io,
     {
-        Self { input: Box::new(input), output: Box::new(output) }
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            stats: Stats::default(),
+            error: None,
+        }
+    }
+
+    /// The error the generated code failed with.
+    pub fn failure(&mut self) -> io::Error {
+        self.error
+            .take()
+            .unwrap_or_else(|| io::Error::other("generated code failed"))
     }
 }
 
-pub unsafe extern "sysv64" fn put(interface: *mut Interface, ch: u8) -> i8 {
-    if (*interface).output.write_all(&[ch]).is_ok() {
-        0
-    } else {
-        -1
+pub unsafe extern "sysv64" fn put(interface: *mut Interface<'_>, ch: u8) -> i8 {
+    match (*interface).output.write_all(&[ch]) {
+        Ok(()) => {
+            (*interface).stats.bytes_written += 1;
+            0
+        },
+        Err(error) => {
+            (*interface).error = Some(error);
+            -1
+        },
     }
 }
 
-pub unsafe extern "sysv64" fn get(interface: *mut Interface) -> i16 {
+pub unsafe extern "sysv64" fn get(interface: *mut Interface<'_>) -> i16 {
     let mut buf = [0];
     match (*interface).input.read_exact(&mut buf) {
-        Ok(_) => (1 << 8) | (buf[0] as i16),
+        Ok(_) => {
+            (*interface).stats.bytes_read += 1;
+            (1 << 8) | (buf[0] as i16)
+        },
         Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => 0,
-        Err(_) => -1,
+        Err(error) => {
+            (*interface).error = Some(error);
+            -1
+        },
     }
 }
 
@@ -45,11 +119,13 @@ pub unsafe extern "sysv64" fn destroy_tape(tape: *mut u8) {
 pub unsafe extern "sysv64" fn grow_next(
     tape_start: *mut u8,
     tape_len: usize,
+    interface: *mut Interface<'_>,
 ) -> *mut u8 {
     let new_len = tape_len + TAPE_CHUNK_SIZE;
     let new_start =
         libc::realloc(tape_start as *mut libc::c_void, new_len) as *mut u8;
     if new_start.is_null() {
+        (*interface).error = Some(io::ErrorKind::OutOfMemory.into());
         return new_start;
     }
     libc::memset(
@@ -57,17 +133,20 @@ pub unsafe extern "sysv64" fn grow_next(
         0,
         TAPE_CHUNK_SIZE,
     );
+    (*interface).stats.tape_growths += 1;
     new_start
 }
 
 pub unsafe extern "sysv64" fn grow_prev(
     tape_start: *mut u8,
     tape_len: usize,
+    interface: *mut Interface<'_>,
 ) -> *mut u8 {
     let new_len = tape_len + TAPE_CHUNK_SIZE;
     let new_start =
         libc::realloc(tape_start as *mut libc::c_void, new_len) as *mut u8;
     if new_start.is_null() {
+        (*interface).error = Some(io::ErrorKind::OutOfMemory.into());
         return new_start;
     }
     libc::memmove(
@@ -76,5 +155,6 @@ pub unsafe extern "sysv64" fn grow_prev(
         tape_len,
     );
     libc::memset(new_start as *mut libc::c_void, 0, TAPE_CHUNK_SIZE);
+    (*interface).stats.tape_growths += 1;
     new_start
 }
@@ -0,0 +1,442 @@
+//! Translation of programs into textual LLVM IR, defining `catbf_main` against
+//! the same runtime as the assembly generated by AOT compilation. The tape is
+//! kept in SSA values, merged by phis where control flow joins, so LLVM can
+//! keep the cell pointer in a register. Pointers are opaque, as required by
+//! LLVM 15 and later.
+
+use crate::{
+    compiler::jit::TAPE_CHUNK_SIZE,
+    ir::{self, Instruction, Node, Program, Run, StructureError},
+};
+use std::fmt::Write;
+
+/// Declarations of the runtime, implemented by `runtime.c`.
+const DECLARATIONS: &str = r#"
+declare ptr @catbf_create_tape()
+declare void @catbf_destroy_tape(ptr)
+declare ptr @catbf_grow_next(ptr, i64)
+declare ptr @catbf_grow_prev(ptr, i64)
+declare signext i16 @catbf_get(ptr)
+declare signext i8 @catbf_put(ptr, i8 zeroext)
+"#;
+
+/// State of the tape, as the names of the values holding it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tape {
+    start: String,
+    len: String,
+    pos: String,
+    cell: String,
+}
+
+/// Where the phis of a loop header are, to be completed once the latch is
+/// known.
+#[derive(Debug, Clone)]
+struct LoopHeader {
+    line: usize,
+    entry_block: String,
+    entry: Tape,
+    phis: Tape,
+}
+
+#[derive(Debug, Default)]
+struct Builder {
+    lines: Vec<String>,
+    next_value: usize,
+    next_block: usize,
+    next_loop: usize,
+    /// Label of the block being written.
+    block: String,
+    /// Start of the tape at each branch to the failure block, and the block
+    /// it comes from.
+    failures: Vec<(String, String)>,
+}
+
+/// Generates an LLVM module defining `i8 @catbf_main(ptr %interface)`, which
+/// returns 0, or -1 if input, output or allocation failed.
+pub fn generate(program: &Program) -> Result<String, StructureError> {
+    let mut nodes = program.structured()?;
+    // The final halt is written along with the end of the function.
+    if let Some(Node::Instruction { instruction: Instruction::Halt, .. }) =
+        nodes.last()
+    {
+        nodes.pop();
+    }
+
+    let mut builder = Builder::default();
+    builder.emit("define i8 @catbf_main(ptr %interface) {");
+    builder.label("entry");
+    builder.emit("  %tape = call ptr @catbf_create_tape()");
+    builder.emit("  %no_tape = icmp eq ptr %tape, null");
+    builder.emit("  br i1 %no_tape, label %no_memory, label %start");
+    builder.label("no_memory");
+    builder.emit("  ret i8 -1");
+    builder.label("start");
+    let tape = Tape {
+        start: "%tape".to_owned(),
+        len: TAPE_CHUNK_SIZE.to_string(),
+        pos: "0".to_owned(),
+        cell: "%tape".to_owned(),
+    };
+    let tape = builder.block(&nodes, tape);
+    builder
+        .emit(format!("  call void @catbf_destroy_tape(ptr {})", tape.start));
+    builder.emit("  ret i8 0");
+    if !builder.failures.is_empty() {
+        builder.label("failure");
+        let incoming = builder
+            .failures
+            .iter()
+            .map(|(start, block)| format!("[ {}, %{} ]", start, block))
+            .collect::<Vec<_>>()
+            .join(", ");
+        builder.emit(format!("  %failed_tape = phi ptr {}", incoming));
+        builder.emit("  call void @catbf_destroy_tape(ptr %failed_tape)");
+        builder.emit("  ret i8 -1");
+    }
+    builder.emit("}");
+
+    let mut source = String::new();
+    source.push_str(concat!(
+        "; Generated by catbf ",
+        env!("CARGO_PKG_VERSION"),
+        ".\n"
+    ));
+    source.push_str(DECLARATIONS);
+    source.push('\n');
+    for line in &builder.lines {
+        source.push_str(line);
+        source.push('\n');
+    }
+    if builder.next_loop > 0 {
+        source.push('\n');
+    }
+    // Loop IDs only. `llvm.loop.mustprogress` must not be used, since
+    // programs may loop forever on purpose.
+    for id in 0 .. builder.next_loop {
+        writeln!(source, "!{} = distinct !{{!{}}}", id, id).ok();
+    }
+    Ok(source)
+}
+
+impl Builder {
+    fn emit(&mut self, line: impl Into<String>) {
+        self.lines.push(line.into());
+    }
+
+    fn label(&mut self, label: &str) {
+        self.emit(format!("{}:", label));
+        self.block = label.to_owned();
+    }
+
+    fn value(&mut self) -> String {
+        let value = format!("%v{}", self.next_value);
+        self.next_value += 1;
+        value
+    }
+
+    fn new_block(&mut self, kind: &str) -> String {
+        let block = format!("{}{}", kind, self.next_block);
+        self.next_block += 1;
+        block
+    }
+
+    /// Branches to the failure block if `condition` holds, continuing
+    /// otherwise in `next`.
+    fn branch_failure(&mut self, condition: &str, start: &str, next: &str) {
+        self.emit(format!(
+            "  br i1 {}, label %failure, label %{}",
+            condition, next
+        ));
+        self.failures.push((start.to_owned(), self.block.clone()));
+    }
+
+    /// Writes the code of a block. Runs of the same cell or cursor
+    /// instruction are merged, which keeps the number of basic blocks low.
+    fn block(&mut self, nodes: &[Node], mut tape: Tape) -> Tape {
+        for run in ir::runs(nodes) {
+            tape = match run {
+                Run::Instruction { instruction, count } => {
+                    self.instruction(instruction, count, tape)
+                },
+                Run::Loop { body } => self.loop_(body, tape),
+            };
+        }
+        tape
+    }
+
+    /// Writes the code of an instruction repeated `count` times.
+    fn instruction(
+        &mut self,
+        instruction: Instruction,
+        count: usize,
+        tape: Tape,
+    ) -> Tape {
+        match instruction {
+            Instruction::Halt => {
+                self.emit(format!(
+                    "  call void @catbf_destroy_tape(ptr {})",
+                    tape.start
+                ));
+                self.emit("  ret i8 0");
+                let block = self.new_block("halted");
+                self.label(&block);
+                tape
+            },
+            // Counts are taken modulo 256, see `ir::Run`.
+            Instruction::Inc => self.add(tape, count as u8),
+            Instruction::Dec => self.add(tape, (count as u8).wrapping_neg()),
+            Instruction::Next => {
+                let pos = self.value();
+                self.emit(format!(
+                    "  {} = add i64 {}, {}",
+                    pos, tape.pos, count
+                ));
+                let tape = self.grow(Tape { pos, ..tape }, false, count);
+                let pos = tape.pos.clone();
+                self.set_pos(tape, pos)
+            },
+            Instruction::Prev => {
+                let tape = self.grow(tape, true, count);
+                let pos = self.value();
+                self.emit(format!(
+                    "  {} = sub i64 {}, {}",
+                    pos, tape.pos, count
+                ));
+                self.set_pos(tape, pos)
+            },
+            Instruction::Get => {
+                // Both the current and the next cell must exist.
+                let next_pos = self.value();
+                self.emit(format!("  {} = add i64 {}, 1", next_pos, tape.pos));
+                let pos = tape.pos.clone();
+                let grown = self.grow(Tape { pos: next_pos, ..tape }, false, 1);
+                let tape = self.set_pos(grown, pos);
+                let result = self.value();
+                self.emit(format!(
+                    "  {} = call i16 @catbf_get(ptr %interface)",
+                    result
+                ));
+                let failed = self.value();
+                self.emit(format!("  {} = icmp slt i16 {}, 0", failed, result));
+                let read = self.new_block("read");
+                self.branch_failure(&failed, &tape.start, &read);
+                self.label(&read);
+                let shifted = self.value();
+                self.emit(format!("  {} = lshr i16 {}, 8", shifted, result));
+                let flag = self.value();
+                self.emit(format!("  {} = trunc i16 {} to i8", flag, shifted));
+                let byte = self.value();
+                self.emit(format!("  {} = trunc i16 {} to i8", byte, result));
+                self.emit(format!("  store i8 {}, ptr {}", flag, tape.cell));
+                let next_cell = self.value();
+                self.emit(format!(
+                    "  {} = getelementptr inbounds i8, ptr {}, i64 1",
+                    next_cell, tape.cell
+                ));
+                self.emit(format!("  store i8 {}, ptr {}", byte, next_cell));
+                tape
+            },
+            Instruction::Put => {
+                let byte = self.value();
+                self.emit(format!("  {} = load i8, ptr {}", byte, tape.cell));
+                let result = self.value();
+                self.emit(format!(
+                    "  {} = call i8 @catbf_put(ptr %interface, i8 {})",
+                    result, byte
+                ));
+                let failed = self.value();
+                self.emit(format!("  {} = icmp slt i8 {}, 0", failed, result));
+                let written = self.new_block("written");
+                self.branch_failure(&failed, &tape.start, &written);
+                self.label(&written);
+                tape
+            },
+            // See `Program::structured`.
+            Instruction::Jz(_) | Instruction::Jnz(_) => tape,
+        }
+    }
+
+    fn add(&mut self, tape: Tape, amount: u8) -> Tape {
+        let old = self.value();
+        self.emit(format!("  {} = load i8, ptr {}", old, tape.cell));
+        let new = self.value();
+        self.emit(format!("  {} = add i8 {}, {}", new, old, amount as i8));
+        self.emit(format!("  store i8 {}, ptr {}", new, tape.cell));
+        tape
+    }
+
+    fn set_pos(&mut self, mut tape: Tape, pos: String) -> Tape {
+        let cell = self.value();
+        self.emit(format!(
+            "  {} = getelementptr inbounds i8, ptr {}, i64 {}",
+            cell, tape.start, pos
+        ));
+        tape.pos = pos;
+        tape.cell = cell;
+        tape
+    }
+
+    /// Grows the tape by chunks until `count` cells fit before the position
+    /// when growing backwards, or until the position is in the tape
+    /// otherwise. Growing backwards shifts the position. The cell pointer
+    /// must then be computed again.
+    fn grow(&mut self, tape: Tape, backwards: bool, count: usize) -> Tape {
+        let (function, kind) = if backwards {
+            ("catbf_grow_prev", "prev")
+        } else {
+            ("catbf_grow_next", "next")
+        };
+        let check = |builder: &mut Self, pos: &str, len: &str| {
+            let condition = builder.value();
+            if backwards {
+                builder.emit(format!(
+                    "  {} = icmp ult i64 {}, {}",
+                    condition, pos, count
+                ));
+            } else {
+                builder.emit(format!(
+                    "  {} = icmp uge i64 {}, {}",
+                    condition, pos, len
+                ));
+            }
+            condition
+        };
+        let grow = self.new_block(&format!("grow_{}", kind));
+        let grown = self.new_block(&format!("grown_{}", kind));
+
+        let condition = check(self, &tape.pos, &tape.len);
+        self.emit(format!(
+            "  br i1 {}, label %{}, label %{}",
+            condition, grow, grown
+        ));
+        let from = self.block.clone();
+
+        self.label(&grow);
+        let grew = self.new_block(&format!("grew_{}", kind));
+        let start = self.value();
+        let len = self.value();
+        let pos = if backwards { self.value() } else { tape.pos.clone() };
+        let new_start = self.value();
+        let new_len = self.value();
+        let new_pos = if backwards { self.value() } else { tape.pos.clone() };
+        self.emit(format!(
+            "  {} = phi ptr [ {}, %{} ], [ {}, %{} ]",
+            start, tape.start, from, new_start, grew
+        ));
+        self.emit(format!(
+            "  {} = phi i64 [ {}, %{} ], [ {}, %{} ]",
+            len, tape.len, from, new_len, grew
+        ));
+        // Only growing backwards moves the position.
+        if backwards {
+            self.emit(format!(
+                "  {} = phi i64 [ {}, %{} ], [ {}, %{} ]",
+                pos, tape.pos, from, new_pos, grew
+            ));
+        }
+        self.emit(format!(
+            "  {} = call ptr @{}(ptr {}, i64 {})",
+            new_start, function, start, len
+        ));
+        self.emit(format!(
+            "  {} = add i64 {}, {}",
+            new_len, len, TAPE_CHUNK_SIZE
+        ));
+        if backwards {
+            self.emit(format!(
+                "  {} = add i64 {}, {}",
+                new_pos, pos, TAPE_CHUNK_SIZE
+            ));
+        }
+        let failed = self.value();
+        self.emit(format!("  {} = icmp eq ptr {}, null", failed, new_start));
+        self.branch_failure(&failed, &start, &grew);
+
+        self.label(&grew);
+        let condition = check(self, &new_pos, &new_len);
+        self.emit(format!(
+            "  br i1 {}, label %{}, label %{}",
+            condition, grow, grown
+        ));
+
+        self.label(&grown);
+        let merged_start = self.value();
+        self.emit(format!(
+            "  {} = phi ptr [ {}, %{} ], [ {}, %{} ]",
+            merged_start, tape.start, from, new_start, grew
+        ));
+        let merged_len = self.value();
+        self.emit(format!(
+            "  {} = phi i64 [ {}, %{} ], [ {}, %{} ]",
+            merged_len, tape.len, from, new_len, grew
+        ));
+        let merged_pos = if backwards {
+            let merged_pos = self.value();
+            self.emit(format!(
+                "  {} = phi i64 [ {}, %{} ], [ {}, %{} ]",
+                merged_pos, tape.pos, from, new_pos, grew
+            ));
+            merged_pos
+        } else {
+            tape.pos.clone()
+        };
+        Tape { start: merged_start, len: merged_len, pos: merged_pos, ..tape }
+    }
+
+    fn loop_(&mut self, body: &[Node], entry: Tape) -> Tape {
+        let id = self.next_loop;
+        self.next_loop += 1;
+        let header = format!("loop{}", id);
+        let body_block = format!("loop{}_body", id);
+        let end = format!("loop{}_end", id);
+
+        self.emit(format!("  br label %{}", header));
+        let entry_block = self.block.clone();
+        self.label(&header);
+        let phis = Tape {
+            start: self.value(),
+            len: self.value(),
+            pos: self.value(),
+            cell: self.value(),
+        };
+        let header =
+            LoopHeader { line: self.lines.len(), entry_block, entry, phis };
+        // Completed by `close_loop`.
+        for _ in 0 .. 4 {
+            self.emit(String::new());
+        }
+        let value = self.value();
+        self.emit(format!("  {} = load i8, ptr {}", value, header.phis.cell));
+        let zero = self.value();
+        self.emit(format!("  {} = icmp eq i8 {}, 0", zero, value));
+        self.emit(format!(
+            "  br i1 {}, label %{}, label %{}",
+            zero, end, body_block
+        ));
+
+        self.label(&body_block);
+        let latch = self.block(body, header.phis.clone());
+        self.emit(format!("  br label %loop{}, !llvm.loop !{}", id, id));
+        self.close_loop(&header, &latch);
+
+        self.label(&end);
+        header.phis
+    }
+
+    fn close_loop(&mut self, header: &LoopHeader, latch: &Tape) {
+        let latch_block = self.block.clone();
+        let rows = [
+            ("ptr", &header.phis.start, &header.entry.start, &latch.start),
+            ("i64", &header.phis.len, &header.entry.len, &latch.len),
+            ("i64", &header.phis.pos, &header.entry.pos, &latch.pos),
+            ("ptr", &header.phis.cell, &header.entry.cell, &latch.cell),
+        ];
+        for (i, (kind, phi, entry, latch)) in rows.into_iter().enumerate() {
+            self.lines[header.line + i] = format!(
+                "  {} = phi {} [ {}, %{} ], [ {}, %{} ]",
+                phi, kind, entry, header.entry_block, latch, latch_block
+            );
+        }
+    }
+}
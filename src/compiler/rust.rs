@@ -0,0 +1,224 @@
+//! Translation of programs into Rust source code: a module exporting the
+//! program as a function over `Read` and `Write`, with the semantics of
+//! `interpreter::Machine`, and optionally a crate scaffold building it into an
+//! executable.
+
+use crate::ir::{self, Instruction, Node, Program, Run, StructureError};
+use std::fmt::Write;
+
+/// Definitions shared by all generated modules.
+const PRELUDE: &str = r#"
+use std::io::{self, Read, Write};
+
+/// Size of each chunk the tape grows by.
+pub const TAPE_CHUNK_SIZE: usize = 8192;
+
+/// Tape of the program, infinite both forwards and backwards.
+#[derive(Debug, Clone)]
+pub struct Tape {
+    cells: Vec<u8>,
+    cursor: usize,
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[allow(dead_code)]
+impl Tape {
+    pub fn new() -> Self {
+        Self { cells: vec![0; TAPE_CHUNK_SIZE], cursor: 0 }
+    }
+
+    /// Cells allocated so far.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    /// Index of the current cell in `cells`.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    #[inline]
+    fn cell(&self) -> u8 {
+        self.cells[self.cursor]
+    }
+
+    #[inline]
+    fn add(&mut self, count: u8) {
+        self.cells[self.cursor] = self.cells[self.cursor].wrapping_add(count);
+    }
+
+    #[inline]
+    fn sub(&mut self, count: u8) {
+        self.cells[self.cursor] = self.cells[self.cursor].wrapping_sub(count);
+    }
+
+    /// Moves the cursor forwards, like `count` times `>`.
+    #[inline]
+    fn next(&mut self, count: usize) {
+        self.cursor += count;
+        while self.cursor >= self.cells.len() {
+            self.grow_next();
+        }
+    }
+
+    /// Moves the cursor backwards, like `count` times `<`.
+    #[inline]
+    fn prev(&mut self, count: usize) {
+        while self.cursor < count {
+            self.grow_prev();
+        }
+        self.cursor -= count;
+    }
+
+    #[cold]
+    fn grow_next(&mut self) {
+        let new_len = self.cells.len() + TAPE_CHUNK_SIZE;
+        self.cells.resize(new_len, 0);
+    }
+
+    #[cold]
+    fn grow_prev(&mut self) {
+        self.cells.splice(.. 0, std::iter::repeat_n(0, TAPE_CHUNK_SIZE));
+        self.cursor += TAPE_CHUNK_SIZE;
+    }
+
+    /// Writes whether a byte was read and the byte itself, which is zero on
+    /// EOF, into the current and the next cells.
+    fn get<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let mut buf = [0];
+        let (flag, byte) = match input.read_exact(&mut buf) {
+            Ok(()) => (1, buf[0]),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                (0, 0)
+            },
+            Err(error) => return Err(error),
+        };
+        self.cells[self.cursor] = flag;
+        self.next(1);
+        self.cells[self.cursor] = byte;
+        self.prev(1);
+        Ok(())
+    }
+
+    #[inline]
+    fn put<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&[self.cell()])
+    }
+}
+
+/// Runs the program on a new tape.
+pub fn run<R: Read, W: Write>(input: R, output: W) -> io::Result<()> {
+    run_with_tape(&mut Tape::new(), input, output)
+}
+"#;
+
+/// Entry point of the executable of a crate scaffold.
+const MAIN: &str = r#"mod prog;
+
+use std::{
+    io::{self, BufWriter, Write},
+    process,
+};
+
+fn main() {
+    let input = io::stdin().lock();
+    let mut output = BufWriter::new(io::stdout().lock());
+    let result = prog::run(input, &mut output).and_then(|()| output.flush());
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+"#;
+
+/// Generates a Rust module exporting `run` and `run_with_tape`, which run the
+/// given program on the given input and output.
+pub fn generate(program: &Program) -> Result<String, StructureError> {
+    let mut nodes = program.structured()?;
+    // The final halt is the tail expression of the function.
+    if let Some(Node::Instruction { instruction: Instruction::Halt, .. }) =
+        nodes.last()
+    {
+        nodes.pop();
+    }
+
+    let mut source = String::new();
+    source.push_str(concat!(
+        "//! Generated by catbf ",
+        env!("CARGO_PKG_VERSION"),
+        ".\n"
+    ));
+    source.push_str(PRELUDE);
+    source.push_str(concat!(
+        "\n/// Runs the program on the given tape, starting at its current ",
+        "cell.\n",
+        "#[allow(unused_mut, unused_variables)]\n",
+        "pub fn run_with_tape<R: Read, W: Write>(\n",
+        "    tape: &mut Tape,\n",
+        "    mut input: R,\n",
+        "    mut output: W,\n",
+        ") -> io::Result<()> {\n",
+    ));
+    write_block(&mut source, &nodes, 1);
+    source.push_str("    Ok(())\n}\n");
+    Ok(source)
+}
+
+/// Generates the `src/main.rs` of a crate scaffold, running the module
+/// `prog` on the standard input and output.
+pub fn main_source() -> &'static str {
+    MAIN
+}
+
+/// Generates the `Cargo.toml` of a crate scaffold with the given package
+/// name. The crate is its own workspace, so it builds even when placed inside
+/// another one.
+pub fn manifest(name: &str) -> String {
+    format!(
+        concat!(
+            "[package]\n",
+            "name = \"{}\"\n",
+            "version = \"0.1.0\"\n",
+            "edition = \"2021\"\n",
+            "\n",
+            "[workspace]\n",
+        ),
+        name
+    )
+}
+
+/// Writes the statements of a block. Runs of the same cell or cursor
+/// instruction become a single statement, which keeps the code readable and
+/// quick for rustc to optimize.
+fn write_block(source: &mut String, nodes: &[Node], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for run in ir::runs(nodes) {
+        match run {
+            Run::Instruction { instruction, count } => {
+                let statement = match instruction {
+                    Instruction::Halt => "return Ok(());".to_owned(),
+                    // Counts are taken modulo 256, see `ir::Run`.
+                    Instruction::Inc => format!("tape.add({});", count as u8),
+                    Instruction::Dec => format!("tape.sub({});", count as u8),
+                    Instruction::Next => format!("tape.next({});", count),
+                    Instruction::Prev => format!("tape.prev({});", count),
+                    Instruction::Get => "tape.get(&mut input)?;".to_owned(),
+                    Instruction::Put => "tape.put(&mut output)?;".to_owned(),
+                    // See `Program::structured`.
+                    Instruction::Jz(_) | Instruction::Jnz(_) => continue,
+                };
+                writeln!(source, "{}{}", indent, statement).ok();
+            },
+            Run::Loop { body } => {
+                writeln!(source, "{}while tape.cell() != 0 {{", indent).ok();
+                write_block(source, body, depth + 1);
+                writeln!(source, "{}}}", indent).ok();
+            },
+        }
+    }
+}
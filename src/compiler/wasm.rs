@@ -0,0 +1,531 @@
+//! Translation of programs into WebAssembly modules. The tape is the linear
+//! memory `memory`, grown with `memory.grow`, and the program is the exported
+//! function `main`, returning 0, or -1 if input, output or allocation failed.
+//!
+//! Input and output are the imported functions `catbf.get`, returning
+//! `(1 << 8) | byte`, 0 at the end of the input, or a negative number on
+//! error, and `catbf.put`, taking a byte and returning 0, or a negative number
+//! on error. Growing the tape backwards uses bulk memory instructions.
+
+use crate::{
+    compiler::dwarf::{put_sleb, put_uleb},
+    ir::{self, Instruction, Node, Program, Run, StructureError},
+};
+use std::fmt;
+
+mod validate;
+
+pub use self::validate::{validate, ValidationError};
+
+/// Size of a page of linear memory, by which the tape grows.
+pub const PAGE_SIZE: u32 = 0x10000;
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: u32 = 1;
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const TYPE_FUNC: u8 = 0x60;
+const TYPE_I32: u8 = 0x7f;
+const BLOCK_EMPTY: u8 = 0x40;
+
+const EXTERNAL_FUNC: u8 = 0;
+const EXTERNAL_MEMORY: u8 = 2;
+
+/// Index of the type `[] -> [i32]`.
+const TYPE_RESULT: u32 = 0;
+/// Index of the type `[i32] -> [i32]`.
+const TYPE_PARAM_RESULT: u32 = 1;
+
+const FUNC_GET: u32 = 0;
+const FUNC_PUT: u32 = 1;
+const FUNC_GROW_NEXT: u32 = 2;
+const FUNC_GROW_PREV: u32 = 3;
+const FUNC_MAIN: u32 = 4;
+
+/// Local of `main` holding the position of the current cell.
+const LOCAL_POS: u32 = 0;
+/// Local of `main` holding the result of `get`.
+const LOCAL_INPUT: u32 = 1;
+/// Local of `grow_prev` holding the length of the tape before growing.
+const LOCAL_OLD_LEN: u32 = 0;
+
+/// An instruction of the subset of WebAssembly used by generated modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Op {
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    I32Load8U { offset: u32 },
+    I32Store8 { offset: u32 },
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I32Eqz,
+    I32Eq,
+    I32LtS,
+    I32LtU,
+    I32GeU,
+    I32Add,
+    I32Sub,
+    I32Shl,
+    I32ShrU,
+    MemoryCopy,
+    MemoryFill,
+}
+
+impl Op {
+    fn encode(self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Block => buf.extend_from_slice(&[0x02, BLOCK_EMPTY]),
+            Self::Loop => buf.extend_from_slice(&[0x03, BLOCK_EMPTY]),
+            Self::If => buf.extend_from_slice(&[0x04, BLOCK_EMPTY]),
+            Self::End => buf.push(0x0b),
+            Self::Br(depth) => {
+                buf.push(0x0c);
+                put_uleb(buf, depth.into());
+            },
+            Self::BrIf(depth) => {
+                buf.push(0x0d);
+                put_uleb(buf, depth.into());
+            },
+            Self::Return => buf.push(0x0f),
+            Self::Call(function) => {
+                buf.push(0x10);
+                put_uleb(buf, function.into());
+            },
+            Self::LocalGet(local) => {
+                buf.push(0x20);
+                put_uleb(buf, local.into());
+            },
+            Self::LocalSet(local) => {
+                buf.push(0x21);
+                put_uleb(buf, local.into());
+            },
+            Self::I32Load8U { offset } => {
+                buf.extend_from_slice(&[0x2d, 0]);
+                put_uleb(buf, offset.into());
+            },
+            Self::I32Store8 { offset } => {
+                buf.extend_from_slice(&[0x3a, 0]);
+                put_uleb(buf, offset.into());
+            },
+            Self::MemorySize => buf.extend_from_slice(&[0x3f, 0]),
+            Self::MemoryGrow => buf.extend_from_slice(&[0x40, 0]),
+            Self::I32Const(value) => {
+                buf.push(0x41);
+                put_sleb(buf, value.into());
+            },
+            Self::I32Eqz => buf.push(0x45),
+            Self::I32Eq => buf.push(0x46),
+            Self::I32LtS => buf.push(0x48),
+            Self::I32LtU => buf.push(0x49),
+            Self::I32GeU => buf.push(0x4f),
+            Self::I32Add => buf.push(0x6a),
+            Self::I32Sub => buf.push(0x6b),
+            Self::I32Shl => buf.push(0x74),
+            Self::I32ShrU => buf.push(0x76),
+            Self::MemoryCopy => buf.extend_from_slice(&[0xfc, 10, 0, 0]),
+            Self::MemoryFill => buf.extend_from_slice(&[0xfc, 11, 0]),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Block => write!(fmtr, "block"),
+            Self::Loop => write!(fmtr, "loop"),
+            Self::If => write!(fmtr, "if"),
+            Self::End => write!(fmtr, "end"),
+            Self::Br(depth) => write!(fmtr, "br {}", depth),
+            Self::BrIf(depth) => write!(fmtr, "br_if {}", depth),
+            Self::Return => write!(fmtr, "return"),
+            Self::Call(function) => {
+                write!(fmtr, "call ${}", FUNCTION_NAMES[*function as usize])
+            },
+            Self::LocalGet(local) => write!(fmtr, "local.get {}", local),
+            Self::LocalSet(local) => write!(fmtr, "local.set {}", local),
+            Self::I32Load8U { offset: 0 } => write!(fmtr, "i32.load8_u"),
+            Self::I32Load8U { offset } => {
+                write!(fmtr, "i32.load8_u offset={}", offset)
+            },
+            Self::I32Store8 { offset: 0 } => write!(fmtr, "i32.store8"),
+            Self::I32Store8 { offset } => {
+                write!(fmtr, "i32.store8 offset={}", offset)
+            },
+            Self::MemorySize => write!(fmtr, "memory.size"),
+            Self::MemoryGrow => write!(fmtr, "memory.grow"),
+            Self::I32Const(value) => write!(fmtr, "i32.const {}", value),
+            Self::I32Eqz => write!(fmtr, "i32.eqz"),
+            Self::I32Eq => write!(fmtr, "i32.eq"),
+            Self::I32LtS => write!(fmtr, "i32.lt_s"),
+            Self::I32LtU => write!(fmtr, "i32.lt_u"),
+            Self::I32GeU => write!(fmtr, "i32.ge_u"),
+            Self::I32Add => write!(fmtr, "i32.add"),
+            Self::I32Sub => write!(fmtr, "i32.sub"),
+            Self::I32Shl => write!(fmtr, "i32.shl"),
+            Self::I32ShrU => write!(fmtr, "i32.shr_u"),
+            Self::MemoryCopy => write!(fmtr, "memory.copy"),
+            Self::MemoryFill => write!(fmtr, "memory.fill"),
+        }
+    }
+}
+
+/// Names of the functions in the text format, by index.
+const FUNCTION_NAMES: [&str; 5] =
+    ["get", "put", "grow_next", "grow_prev", "main"];
+
+/// A function defined by the module.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Function {
+    type_index: u32,
+    /// Number of `i32` locals besides the parameters.
+    locals: u32,
+    body: Vec<Op>,
+}
+
+/// A generated WebAssembly module. Its text format is given by `Display`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Module {
+    /// `grow_next`, `grow_prev` and `main`, in this order.
+    functions: [Function; 3],
+}
+
+/// Generates a module running the given program.
+pub fn generate(program: &Program) -> Result<Module, StructureError> {
+    let mut nodes = program.structured()?;
+    // The final halt is the end of the function.
+    if let Some(Node::Instruction { instruction: Instruction::Halt, .. }) =
+        nodes.last()
+    {
+        nodes.pop();
+    }
+
+    let mut body = Vec::new();
+    write_block(&mut body, &nodes);
+    body.push(Op::I32Const(0));
+    body.push(Op::End);
+
+    let main = Function { type_index: TYPE_RESULT, locals: 2, body };
+    Ok(Module { functions: [grow_next(), grow_prev(), main] })
+}
+
+impl Module {
+    /// Encodes the module in the binary format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut types = Vec::new();
+        put_uleb(&mut types, 2);
+        types.extend_from_slice(&[TYPE_FUNC, 0, 1, TYPE_I32]);
+        types.extend_from_slice(&[TYPE_FUNC, 1, TYPE_I32, 1, TYPE_I32]);
+        push_section(&mut buf, SECTION_TYPE, &types);
+
+        let mut imports = Vec::new();
+        put_uleb(&mut imports, 2);
+        for (name, type_index) in
+            [("get", TYPE_RESULT), ("put", TYPE_PARAM_RESULT)]
+        {
+            push_name(&mut imports, "catbf");
+            push_name(&mut imports, name);
+            imports.push(EXTERNAL_FUNC);
+            put_uleb(&mut imports, type_index.into());
+        }
+        push_section(&mut buf, SECTION_IMPORT, &imports);
+
+        let mut functions = Vec::new();
+        put_uleb(&mut functions, self.functions.len() as u64);
+        for function in &self.functions {
+            put_uleb(&mut functions, function.type_index.into());
+        }
+        push_section(&mut buf, SECTION_FUNCTION, &functions);
+
+        // A single memory of at least one page, without maximum.
+        push_section(&mut buf, SECTION_MEMORY, &[1, 0, 1]);
+
+        let mut exports = Vec::new();
+        put_uleb(&mut exports, 2);
+        push_name(&mut exports, "main");
+        exports.push(EXTERNAL_FUNC);
+        put_uleb(&mut exports, FUNC_MAIN.into());
+        push_name(&mut exports, "memory");
+        exports.push(EXTERNAL_MEMORY);
+        put_uleb(&mut exports, 0);
+        push_section(&mut buf, SECTION_EXPORT, &exports);
+
+        let mut code = Vec::new();
+        put_uleb(&mut code, self.functions.len() as u64);
+        for function in &self.functions {
+            let mut body = Vec::new();
+            if function.locals == 0 {
+                put_uleb(&mut body, 0);
+            } else {
+                put_uleb(&mut body, 1);
+                put_uleb(&mut body, function.locals.into());
+                body.push(TYPE_I32);
+            }
+            for op in &function.body {
+                op.encode(&mut body);
+            }
+            put_uleb(&mut code, body.len() as u64);
+            code.extend_from_slice(&body);
+        }
+        push_section(&mut buf, SECTION_CODE, &code);
+
+        buf
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmtr, "(module")?;
+        writeln!(fmtr, "  (type (func (result i32)))")?;
+        writeln!(fmtr, "  (type (func (param i32) (result i32)))")?;
+        writeln!(fmtr, "  (import \"catbf\" \"get\" (func $get (type 0)))")?;
+        writeln!(fmtr, "  (import \"catbf\" \"put\" (func $put (type 1)))")?;
+        writeln!(fmtr, "  (memory (export \"memory\") 1)")?;
+        for (i, function) in self.functions.iter().enumerate() {
+            let index = FUNC_GROW_NEXT as usize + i;
+            write!(fmtr, "  (func ${}", FUNCTION_NAMES[index])?;
+            if index == FUNC_MAIN as usize {
+                write!(fmtr, " (export \"main\")")?;
+            }
+            write!(fmtr, " (type {})", function.type_index)?;
+            for _ in 0 .. function.locals {
+                write!(fmtr, " (local i32)")?;
+            }
+            writeln!(fmtr)?;
+            let mut depth = 2;
+            // The last `end` closes the function itself.
+            let body = &function.body[.. function.body.len() - 1];
+            for op in body {
+                if matches!(op, Op::End) {
+                    depth -= 1;
+                }
+                writeln!(fmtr, "{}{}", "  ".repeat(depth), op)?;
+                if matches!(op, Op::Block | Op::Loop | Op::If) {
+                    depth += 1;
+                }
+            }
+            writeln!(fmtr, "  )")?;
+        }
+        writeln!(fmtr, ")")
+    }
+}
+
+fn push_section(buf: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    buf.push(id);
+    put_uleb(buf, contents.len() as u64);
+    buf.extend_from_slice(contents);
+}
+
+fn push_name(buf: &mut Vec<u8>, name: &str) {
+    put_uleb(buf, name.len() as u64);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+/// Grows the memory by a page at its end, returning 0, or -1 on failure.
+fn grow_next() -> Function {
+    let mut body = vec![Op::I32Const(1), Op::MemoryGrow];
+    push_grow_check(&mut body);
+    body.extend_from_slice(&[Op::I32Const(0), Op::End]);
+    Function { type_index: TYPE_RESULT, locals: 0, body }
+}
+
+/// Grows the memory by a page at its start, shifting the contents forwards,
+/// returning 0, or -1 on failure.
+fn grow_prev() -> Function {
+    let mut body = vec![
+        Op::MemorySize,
+        Op::I32Const(16),
+        Op::I32Shl,
+        Op::LocalSet(LOCAL_OLD_LEN),
+        Op::I32Const(1),
+        Op::MemoryGrow,
+    ];
+    push_grow_check(&mut body);
+    body.extend_from_slice(&[
+        Op::I32Const(PAGE_SIZE as i32),
+        Op::I32Const(0),
+        Op::LocalGet(LOCAL_OLD_LEN),
+        Op::MemoryCopy,
+        Op::I32Const(0),
+        Op::I32Const(0),
+        Op::I32Const(PAGE_SIZE as i32),
+        Op::MemoryFill,
+        Op::I32Const(0),
+        Op::End,
+    ]);
+    Function { type_index: TYPE_RESULT, locals: 1, body }
+}
+
+/// Returns -1 if `memory.grow` failed.
+fn push_grow_check(body: &mut Vec<Op>) {
+    body.extend_from_slice(&[
+        Op::I32Const(-1),
+        Op::I32Eq,
+        Op::If,
+        Op::I32Const(-1),
+        Op::Return,
+        Op::End,
+    ]);
+}
+
+/// Returns -1 if the result of a call is negative.
+fn push_call_check(body: &mut Vec<Op>) {
+    body.extend_from_slice(&[
+        Op::I32Const(0),
+        Op::I32LtS,
+        Op::If,
+        Op::I32Const(-1),
+        Op::Return,
+        Op::End,
+    ]);
+}
+
+/// Grows the tape forwards until the position plus `extra` is in it.
+fn push_ensure_len(body: &mut Vec<Op>, extra: i32) {
+    body.extend_from_slice(&[
+        Op::Block,
+        Op::Loop,
+        Op::LocalGet(LOCAL_POS),
+        Op::I32Const(extra),
+        Op::I32Add,
+        Op::MemorySize,
+        Op::I32Const(16),
+        Op::I32Shl,
+        Op::I32LtU,
+        Op::BrIf(1),
+        Op::Call(FUNC_GROW_NEXT),
+    ]);
+    push_call_check(body);
+    body.extend_from_slice(&[Op::Br(0), Op::End, Op::End]);
+}
+
+/// Writes the code of a block. Runs of the same cell or cursor instruction
+/// are merged.
+fn write_block(body: &mut Vec<Op>, nodes: &[Node]) {
+    for run in ir::runs(nodes) {
+        match run {
+            Run::Instruction { instruction, count } => {
+                write_instruction(body, instruction, count as u32);
+            },
+            Run::Loop { body: loop_body } => {
+                body.extend_from_slice(&[
+                    Op::Block,
+                    Op::Loop,
+                    Op::LocalGet(LOCAL_POS),
+                    Op::I32Load8U { offset: 0 },
+                    Op::I32Eqz,
+                    Op::BrIf(1),
+                ]);
+                write_block(body, loop_body);
+                body.extend_from_slice(&[Op::Br(0), Op::End, Op::End]);
+            },
+        }
+    }
+}
+
+/// Writes the code of an instruction repeated `count` times.
+fn write_instruction(body: &mut Vec<Op>, instruction: Instruction, count: u32) {
+    match instruction {
+        Instruction::Halt => {
+            body.extend_from_slice(&[Op::I32Const(0), Op::Return]);
+        },
+        Instruction::Inc | Instruction::Dec => {
+            // Counts are taken modulo 256, see `ir::Run`.
+            let amount = count as u8;
+            let amount = if instruction == Instruction::Inc {
+                amount
+            } else {
+                amount.wrapping_neg()
+            };
+            body.extend_from_slice(&[
+                Op::LocalGet(LOCAL_POS),
+                Op::LocalGet(LOCAL_POS),
+                Op::I32Load8U { offset: 0 },
+                Op::I32Const(amount.into()),
+                Op::I32Add,
+                Op::I32Store8 { offset: 0 },
+            ]);
+        },
+        Instruction::Next => {
+            body.extend_from_slice(&[
+                Op::LocalGet(LOCAL_POS),
+                Op::I32Const(count as i32),
+                Op::I32Add,
+                Op::LocalSet(LOCAL_POS),
+            ]);
+            push_ensure_len(body, 0);
+        },
+        Instruction::Prev => {
+            body.extend_from_slice(&[
+                Op::Block,
+                Op::Loop,
+                Op::LocalGet(LOCAL_POS),
+                Op::I32Const(count as i32),
+                Op::I32GeU,
+                Op::BrIf(1),
+                Op::Call(FUNC_GROW_PREV),
+            ]);
+            push_call_check(body);
+            body.extend_from_slice(&[
+                Op::LocalGet(LOCAL_POS),
+                Op::I32Const(PAGE_SIZE as i32),
+                Op::I32Add,
+                Op::LocalSet(LOCAL_POS),
+                Op::Br(0),
+                Op::End,
+                Op::End,
+                Op::LocalGet(LOCAL_POS),
+                Op::I32Const(count as i32),
+                Op::I32Sub,
+                Op::LocalSet(LOCAL_POS),
+            ]);
+        },
+        Instruction::Get => {
+            // Both the current and the next cell must exist.
+            push_ensure_len(body, 1);
+            body.extend_from_slice(&[
+                Op::Call(FUNC_GET),
+                Op::LocalSet(LOCAL_INPUT),
+                Op::LocalGet(LOCAL_INPUT),
+            ]);
+            push_call_check(body);
+            body.extend_from_slice(&[
+                Op::LocalGet(LOCAL_POS),
+                Op::LocalGet(LOCAL_INPUT),
+                Op::I32Const(8),
+                Op::I32ShrU,
+                Op::I32Store8 { offset: 0 },
+                Op::LocalGet(LOCAL_POS),
+                Op::LocalGet(LOCAL_INPUT),
+                Op::I32Store8 { offset: 1 },
+            ]);
+        },
+        Instruction::Put => {
+            body.extend_from_slice(&[
+                Op::LocalGet(LOCAL_POS),
+                Op::I32Load8U { offset: 0 },
+                Op::Call(FUNC_PUT),
+            ]);
+            push_call_check(body);
+        },
+        // See `Program::structured`.
+        Instruction::Jz(_) | Instruction::Jnz(_) => (),
+    }
+}
@@ -0,0 +1,571 @@
+//! Structural checker of WebAssembly modules, covering the subset of the
+//! format used by generated modules: section layout, indices, block nesting
+//! and the height of the operand stack. Everything else is rejected as
+//! unsupported, so no external runtime is needed to check generated modules.
+
+use super::{
+    EXTERNAL_FUNC, EXTERNAL_MEMORY, MAGIC, SECTION_CODE, SECTION_EXPORT,
+    SECTION_FUNCTION, SECTION_IMPORT, SECTION_MEMORY, SECTION_TYPE, TYPE_FUNC,
+    TYPE_I32, VERSION,
+};
+use std::collections::HashSet;
+use thiserror::Error;
+
+const SECTION_CUSTOM: u8 = 0;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("bad magic number or version")]
+    BadHeader,
+    #[error("unexpected end of module at byte {}", .0)]
+    UnexpectedEnd(usize),
+    #[error("malformed integer at byte {}", .0)]
+    BadInteger(usize),
+    #[error("malformed name at byte {}", .0)]
+    BadName(usize),
+    #[error("section {} is out of order at byte {}", .0, .1)]
+    SectionOrder(u8, usize),
+    #[error("section {} does not end at its declared size", .0)]
+    SectionSize(u8),
+    #[error("unsupported {} at byte {}", .0, .1)]
+    Unsupported(&'static str, usize),
+    #[error("{} index {} is out of bounds at byte {}", .0, .1, .2)]
+    BadIndex(&'static str, u32, usize),
+    #[error("export name {:?} is duplicated", .0)]
+    DuplicateExport(String),
+    #[error("{} function bodies given for {} functions", .0, .1)]
+    FunctionCount(u32, u32),
+    #[error("operand stack underflow at byte {}", .0)]
+    StackUnderflow(usize),
+    #[error("{} values left on the operand stack at byte {}", .0, .1)]
+    StackHeight(usize, usize),
+    #[error("function body does not end at byte {}", .0)]
+    Unbalanced(usize),
+}
+
+/// Arity of a function type. Only `i32` is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct FuncType {
+    params: u32,
+    results: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reader<'bytes> {
+    bytes: &'bytes [u8],
+    pos: usize,
+}
+
+impl<'bytes> Reader<'bytes> {
+    fn byte(&mut self) -> Result<u8, ValidationError> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or(ValidationError::UnexpectedEnd(self.pos))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'bytes [u8], ValidationError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(ValidationError::UnexpectedEnd(self.bytes.len()))?;
+        let bytes = &self.bytes[self.pos .. end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ValidationError> {
+        let start = self.pos;
+        let mut value = 0u64;
+        for shift in (0 .. 35).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return u32::try_from(value)
+                    .map_err(|_| ValidationError::BadInteger(start));
+            }
+        }
+        Err(ValidationError::BadInteger(start))
+    }
+
+    fn i32(&mut self) -> Result<i32, ValidationError> {
+        let start = self.pos;
+        let mut value = 0i64;
+        for shift in (0 .. 35).step_by(7) {
+            let byte = self.byte()?;
+            value |= i64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                if byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return i32::try_from(value)
+                    .map_err(|_| ValidationError::BadInteger(start));
+            }
+        }
+        Err(ValidationError::BadInteger(start))
+    }
+
+    fn name(&mut self) -> Result<String, ValidationError> {
+        let start = self.pos;
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| ValidationError::BadName(start))
+    }
+
+    fn expect(
+        &mut self,
+        expected: u8,
+        what: &'static str,
+    ) -> Result<(), ValidationError> {
+        let pos = self.pos;
+        if self.byte()? != expected {
+            Err(ValidationError::Unsupported(what, pos))?;
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+/// Everything declared by the module so far.
+#[derive(Debug, Default)]
+struct Declarations {
+    types: Vec<FuncType>,
+    /// Type indices of imported, then defined functions.
+    functions: Vec<u32>,
+    imported_functions: u32,
+    memories: u32,
+}
+
+impl Declarations {
+    fn func_type(
+        &self,
+        type_index: u32,
+        pos: usize,
+    ) -> Result<FuncType, ValidationError> {
+        self.types
+            .get(type_index as usize)
+            .copied()
+            .ok_or(ValidationError::BadIndex("type", type_index, pos))
+    }
+
+    fn function_type(
+        &self,
+        function: u32,
+        pos: usize,
+    ) -> Result<FuncType, ValidationError> {
+        let type_index = *self
+            .functions
+            .get(function as usize)
+            .ok_or(ValidationError::BadIndex("function", function, pos))?;
+        self.func_type(type_index, pos)
+    }
+
+    fn check_memory(&self, pos: usize) -> Result<(), ValidationError> {
+        if self.memories == 0 {
+            Err(ValidationError::BadIndex("memory", 0, pos))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the given bytes are a well-formed module in the subset of
+/// WebAssembly used by generated modules.
+pub fn validate(bytes: &[u8]) -> Result<(), ValidationError> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.bytes(MAGIC.len()).ok() != Some(MAGIC)
+        || reader.bytes(4).ok() != Some(&VERSION.to_le_bytes()[..])
+    {
+        Err(ValidationError::BadHeader)?;
+    }
+
+    let mut declarations = Declarations::default();
+    let mut last_id = SECTION_CUSTOM;
+    let mut bodies = None;
+    while !reader.is_empty() {
+        let pos = reader.pos;
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let contents = reader.bytes(size)?;
+        let mut section = Reader {
+            bytes: &bytes[.. reader.pos],
+            pos: reader.pos - contents.len(),
+        };
+        if id != SECTION_CUSTOM {
+            if id <= last_id {
+                Err(ValidationError::SectionOrder(id, pos))?;
+            }
+            last_id = id;
+        }
+        match id {
+            SECTION_CUSTOM => {
+                section.name()?;
+                section.pos = reader.pos;
+            },
+            SECTION_TYPE => type_section(&mut section, &mut declarations)?,
+            SECTION_IMPORT => import_section(&mut section, &mut declarations)?,
+            SECTION_FUNCTION => {
+                function_section(&mut section, &mut declarations)?
+            },
+            SECTION_MEMORY => memory_section(&mut section, &mut declarations)?,
+            SECTION_EXPORT => export_section(&mut section, &declarations)?,
+            SECTION_CODE => {
+                bodies = Some(code_section(&mut section, &declarations)?)
+            },
+            _ => Err(ValidationError::Unsupported("section", pos))?,
+        }
+        if section.pos != reader.pos {
+            Err(ValidationError::SectionSize(id))?;
+        }
+    }
+
+    let defined =
+        declarations.functions.len() as u32 - declarations.imported_functions;
+    let bodies = bodies.unwrap_or(0);
+    if bodies != defined {
+        Err(ValidationError::FunctionCount(bodies, defined))?;
+    }
+    Ok(())
+}
+
+fn value_types(reader: &mut Reader) -> Result<u32, ValidationError> {
+    let count = reader.u32()?;
+    for _ in 0 .. count {
+        reader.expect(TYPE_I32, "value type")?;
+    }
+    Ok(count)
+}
+
+fn type_section(
+    reader: &mut Reader,
+    declarations: &mut Declarations,
+) -> Result<(), ValidationError> {
+    let count = reader.u32()?;
+    for _ in 0 .. count {
+        reader.expect(TYPE_FUNC, "type")?;
+        let params = value_types(reader)?;
+        let results = value_types(reader)?;
+        declarations.types.push(FuncType { params, results });
+    }
+    Ok(())
+}
+
+fn import_section(
+    reader: &mut Reader,
+    declarations: &mut Declarations,
+) -> Result<(), ValidationError> {
+    let count = reader.u32()?;
+    for _ in 0 .. count {
+        reader.name()?;
+        reader.name()?;
+        reader.expect(EXTERNAL_FUNC, "import kind")?;
+        let pos = reader.pos;
+        let type_index = reader.u32()?;
+        declarations.func_type(type_index, pos)?;
+        declarations.functions.push(type_index);
+        declarations.imported_functions += 1;
+    }
+    Ok(())
+}
+
+fn function_section(
+    reader: &mut Reader,
+    declarations: &mut Declarations,
+) -> Result<(), ValidationError> {
+    let count = reader.u32()?;
+    for _ in 0 .. count {
+        let pos = reader.pos;
+        let type_index = reader.u32()?;
+        declarations.func_type(type_index, pos)?;
+        declarations.functions.push(type_index);
+    }
+    Ok(())
+}
+
+fn memory_section(
+    reader: &mut Reader,
+    declarations: &mut Declarations,
+) -> Result<(), ValidationError> {
+    let pos = reader.pos;
+    let count = reader.u32()?;
+    if count > 1 {
+        Err(ValidationError::Unsupported("multiple memories", pos))?;
+    }
+    for _ in 0 .. count {
+        let pos = reader.pos;
+        match reader.byte()? {
+            0 => {
+                reader.u32()?;
+            },
+            1 => {
+                let min = reader.u32()?;
+                let max = reader.u32()?;
+                if min > max {
+                    Err(ValidationError::Unsupported("memory limits", pos))?;
+                }
+            },
+            _ => Err(ValidationError::Unsupported("memory limits", pos))?,
+        }
+        declarations.memories += 1;
+    }
+    Ok(())
+}
+
+fn export_section(
+    reader: &mut Reader,
+    declarations: &Declarations,
+) -> Result<(), ValidationError> {
+    let count = reader.u32()?;
+    let mut names = HashSet::new();
+    for _ in 0 .. count {
+        let name = reader.name()?;
+        let pos = reader.pos;
+        let kind = reader.byte()?;
+        let index_pos = reader.pos;
+        let index = reader.u32()?;
+        match kind {
+            EXTERNAL_FUNC => {
+                declarations.function_type(index, index_pos)?;
+            },
+            EXTERNAL_MEMORY => {
+                if index >= declarations.memories {
+                    Err(ValidationError::BadIndex("memory", index, index_pos))?;
+                }
+            },
+            _ => Err(ValidationError::Unsupported("export kind", pos))?,
+        }
+        if !names.insert(name.clone()) {
+            Err(ValidationError::DuplicateExport(name))?;
+        }
+    }
+    Ok(())
+}
+
+fn code_section(
+    reader: &mut Reader,
+    declarations: &Declarations,
+) -> Result<u32, ValidationError> {
+    let count = reader.u32()?;
+    for i in 0 .. count {
+        let function = declarations.imported_functions + i;
+        let func_type = declarations
+            .functions
+            .get(function as usize)
+            .map(|type_index| declarations.types[*type_index as usize])
+            .ok_or(ValidationError::FunctionCount(count, function))?;
+        let size = reader.u32()? as usize;
+        let start = reader.pos;
+        reader.bytes(size)?;
+        let mut body =
+            Reader { bytes: &reader.bytes[.. reader.pos], pos: start };
+        function_body(&mut body, declarations, func_type)?;
+        if body.pos != reader.pos {
+            Err(ValidationError::Unbalanced(body.pos))?;
+        }
+    }
+    Ok(count)
+}
+
+/// A block being checked, with the height of the operand stack at its start.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    height: usize,
+    /// Values expected when branching to or ending the block.
+    results: usize,
+    /// Whether the rest of the block is unreachable, after a branch.
+    unreachable: bool,
+}
+
+#[derive(Debug, Default)]
+struct Stack {
+    height: usize,
+    frames: Vec<Frame>,
+}
+
+impl Stack {
+    fn pop(&mut self, count: usize, pos: usize) -> Result<(), ValidationError> {
+        let frame =
+            self.frames.last().ok_or(ValidationError::Unbalanced(pos))?;
+        let available = self.height - frame.height;
+        if available < count {
+            if !frame.unreachable {
+                Err(ValidationError::StackUnderflow(pos))?;
+            }
+            self.height = frame.height;
+        } else {
+            self.height -= count;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, count: usize) {
+        self.height += count;
+    }
+
+    fn label(&self, depth: u32, pos: usize) -> Result<Frame, ValidationError> {
+        self.frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .map(|index| self.frames[index])
+            .ok_or(ValidationError::BadIndex("label", depth, pos))
+    }
+
+    fn set_unreachable(&mut self) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.unreachable = true;
+            self.height = frame.height;
+        }
+    }
+}
+
+fn function_body(
+    reader: &mut Reader,
+    declarations: &Declarations,
+    func_type: FuncType,
+) -> Result<(), ValidationError> {
+    let mut locals = u64::from(func_type.params);
+    let groups = reader.u32()?;
+    for _ in 0 .. groups {
+        locals += u64::from(reader.u32()?);
+        reader.expect(TYPE_I32, "local type")?;
+    }
+
+    let results = func_type.results as usize;
+    let mut stack = Stack::default();
+    stack.frames.push(Frame { height: 0, results, unreachable: false });
+    while !stack.frames.is_empty() {
+        let pos = reader.pos;
+        let opcode = reader.byte()?;
+        match opcode {
+            // block, loop, if
+            0x02 ..= 0x04 => {
+                reader.expect(super::BLOCK_EMPTY, "block type")?;
+                if opcode == 0x04 {
+                    stack.pop(1, pos)?;
+                }
+                stack.frames.push(Frame {
+                    height: stack.height,
+                    results: 0,
+                    unreachable: false,
+                });
+            },
+            // end
+            0x0b => {
+                let frame = stack.frames[stack.frames.len() - 1];
+                stack.pop(frame.results, pos)?;
+                if stack.height != frame.height {
+                    Err(ValidationError::StackHeight(
+                        stack.height - frame.height,
+                        pos,
+                    ))?;
+                }
+                stack.frames.pop();
+                stack.push(frame.results);
+            },
+            // br, br_if
+            0x0c | 0x0d => {
+                let depth = reader.u32()?;
+                if opcode == 0x0d {
+                    stack.pop(1, pos)?;
+                }
+                let frame = stack.label(depth, pos)?;
+                // Only the function itself has results, and loops are never
+                // branched to with values.
+                let arity = if depth as usize + 1 == stack.frames.len() {
+                    frame.results
+                } else {
+                    0
+                };
+                stack.pop(arity, pos)?;
+                if opcode == 0x0c {
+                    stack.set_unreachable();
+                } else {
+                    stack.push(arity);
+                }
+            },
+            // return
+            0x0f => {
+                stack.pop(results, pos)?;
+                stack.set_unreachable();
+            },
+            // call
+            0x10 => {
+                let function = reader.u32()?;
+                let callee = declarations.function_type(function, pos)?;
+                stack.pop(callee.params as usize, pos)?;
+                stack.push(callee.results as usize);
+            },
+            // local.get, local.set
+            0x20 | 0x21 => {
+                let local = reader.u32()?;
+                if u64::from(local) >= locals {
+                    Err(ValidationError::BadIndex("local", local, pos))?;
+                }
+                if opcode == 0x20 {
+                    stack.push(1);
+                } else {
+                    stack.pop(1, pos)?;
+                }
+            },
+            // i32.load8_u, i32.store8
+            0x2d | 0x3a => {
+                declarations.check_memory(pos)?;
+                // Alignment of a byte can only be 2^0.
+                reader.expect(0, "alignment")?;
+                reader.u32()?;
+                if opcode == 0x2d {
+                    stack.pop(1, pos)?;
+                    stack.push(1);
+                } else {
+                    stack.pop(2, pos)?;
+                }
+            },
+            // memory.size, memory.grow
+            0x3f | 0x40 => {
+                declarations.check_memory(pos)?;
+                reader.expect(0, "memory index")?;
+                if opcode == 0x40 {
+                    stack.pop(1, pos)?;
+                }
+                stack.push(1);
+            },
+            // i32.const
+            0x41 => {
+                reader.i32()?;
+                stack.push(1);
+            },
+            // i32.eqz
+            0x45 => {
+                stack.pop(1, pos)?;
+                stack.push(1);
+            },
+            // i32 comparisons and arithmetic
+            0x46 ..= 0x4f | 0x6a ..= 0x78 => {
+                stack.pop(2, pos)?;
+                stack.push(1);
+            },
+            0xfc => {
+                declarations.check_memory(pos)?;
+                match reader.u32()? {
+                    // memory.copy
+                    10 => {
+                        reader.expect(0, "memory index")?;
+                        reader.expect(0, "memory index")?;
+                    },
+                    // memory.fill
+                    11 => reader.expect(0, "memory index")?,
+                    _ => Err(ValidationError::Unsupported("instruction", pos))?,
+                }
+                stack.pop(3, pos)?;
+            },
+            _ => Err(ValidationError::Unsupported("instruction", pos))?,
+        }
+    }
+    Ok(())
+}
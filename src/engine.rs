@@ -0,0 +1,543 @@
+//! A single way to run programs on any backend. An `EngineBuilder` is
+//! configured once, and switching backends only takes a different `Backend`.
+
+use crate::{
+    compiler::{aot, jit},
+    interpreter::{self, Interface, Machine, Tape},
+    io::FromRead,
+    ir::{ParseError, Program, StructureError},
+    source::Source,
+    stats::Stats,
+    tiered,
+};
+use std::{
+    env, fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
+use thiserror::Error;
+
+/// How programs are run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Backend {
+    /// Interprets the program.
+    #[default]
+    Interpreter,
+    /// Interprets the program, compiling loops that run often Just-In-Time.
+    Tiered,
+    /// Compiles the whole program Just-In-Time.
+    Jit,
+    /// Compiles an executable Ahead-Of-Time into a temporary directory, and
+    /// runs it as a child process.
+    Aot,
+}
+
+impl Backend {
+    /// Whether the backend runs on the target platform.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Interpreter | Self::Tiered => true,
+            Self::Jit => jit::TARGET_SUPPORTED,
+            // Falls back to C built by `cc` on other platforms.
+            Self::Aot => true,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Interpreter => write!(fmtr, "interpreter"),
+            Self::Tiered => write!(fmtr, "tiered"),
+            Self::Jit => write!(fmtr, "JIT"),
+            Self::Aot => write!(fmtr, "AOT"),
+        }
+    }
+}
+
+/// Semantics of the language.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Dialect {
+    /// The semantics of every backend: 8-bit wrapping cells, a tape growing
+    /// in both directions, and input writing whether a byte was read to the
+    /// current cell and the byte to the next one.
+    #[default]
+    Catbf,
+}
+
+/// How far the tape may grow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TapePolicy {
+    /// The tape grows as long as memory is available.
+    #[default]
+    Unbounded,
+    /// Running fails once the tape has more than the given number of cells.
+    /// Checked between steps, so only supported by the interpreter and the
+    /// tiered backend, where a compiled loop may grow the tape past the limit
+    /// before failing. Building an engine for another backend fails.
+    Bounded(usize),
+}
+
+#[derive(Debug, Error)]
+pub enum RunError {
+    #[error("{}", .0)]
+    Parse(#[from] ParseError),
+    #[error("{}", .0)]
+    Structure(#[from] StructureError),
+    #[error("{} backend does not support {}", .backend, .feature)]
+    Unsupported { backend: Backend, feature: &'static str },
+    #[error("{}", .0)]
+    Interpreter(#[from] interpreter::Error),
+    #[error("{}", .0)]
+    Jit(#[from] jit::Error),
+    #[error("{}", .0)]
+    Aot(#[from] aot::Error),
+    #[error("{}", .0)]
+    Io(#[from] io::Error),
+    #[error("step limit of {} reached", .0)]
+    StepLimit(u64),
+    #[error("tape limit of {} cells reached", .0)]
+    TapeLimit(usize),
+    #[error("executable failed with {}", .0)]
+    ExecutableFailed(ExitStatus),
+}
+
+/// A program ready to be run by some backend, any number of times.
+pub trait Engine {
+    /// The backend running the program.
+    fn backend(&self) -> Backend;
+
+    /// Runs the program until it halts, returning the statistics kept by the
+    /// backend.
+    fn run(
+        &self,
+        input: &mut (dyn Read + Send),
+        output: &mut (dyn Write + Send),
+    ) -> Result<Stats, RunError>;
+}
+
+/// Limits of runs of the interpreter, checked before every step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Limits {
+    /// Fails runs of programs taking more than the given number of steps.
+    pub max_steps: Option<u64>,
+    pub tape_policy: TapePolicy,
+}
+
+impl Limits {
+    /// Checks the limits before a step, given the number of steps that ran and
+    /// whether the machine is about to halt. The step limit fails before the
+    /// step past it runs, so programs taking exactly that many steps finish.
+    pub fn check(
+        &self,
+        steps: u64,
+        halting: bool,
+        tape: &Tape,
+    ) -> Result<(), RunError> {
+        if let Some(max_steps) =
+            self.max_steps.filter(|&max| steps >= max && !halting)
+        {
+            Err(RunError::StepLimit(max_steps))?;
+        }
+        if let TapePolicy::Bounded(max_cells) = self.tape_policy {
+            if tape.cells().len() > max_cells {
+                Err(RunError::TapeLimit(max_cells))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of engines, built for a given program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineBuilder {
+    backend: Backend,
+    dialect: Dialect,
+    limits: Limits,
+    opt_level: u8,
+    hot_loop_threshold: u64,
+    jit_options: jit::Options,
+    jit_cache: Option<jit::Cache>,
+    inherit_stdin: bool,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            dialect: Dialect::default(),
+            limits: Limits::default(),
+            opt_level: 0,
+            hot_loop_threshold: tiered::DEFAULT_HOT_LOOP_THRESHOLD,
+            jit_options: jit::Options::default(),
+            jit_cache: None,
+            inherit_stdin: false,
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn new(backend: Backend) -> Self {
+        Self { backend, ..Self::default() }
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn with_tape_policy(mut self, tape_policy: TapePolicy) -> Self {
+        self.limits.tape_policy = tape_policy;
+        self
+    }
+
+    /// Fails runs of programs taking more than the given number of steps.
+    /// Only supported by the interpreter and the tiered backend, where a
+    /// compiled loop counts as a single step.
+    pub fn with_max_steps(mut self, max_steps: u64) -> Self {
+        self.limits.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Optimization level: 0 keeps the program as parsed, 1 and above apply
+    /// `Program::optimized`.
+    pub fn with_opt_level(mut self, opt_level: u8) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Number of iterations after which the tiered backend compiles a loop.
+    pub fn with_hot_loop_threshold(mut self, threshold: u64) -> Self {
+        self.hot_loop_threshold = threshold;
+        self
+    }
+
+    /// Options of the JIT backend. Resumable code is unsupported.
+    pub fn with_jit_options(mut self, options: jit::Options) -> Self {
+        self.jit_options = options;
+        self
+    }
+
+    /// Makes the JIT backend reuse code from the cache, and store newly
+    /// compiled code there.
+    pub fn with_jit_cache(mut self, cache: jit::Cache) -> Self {
+        self.jit_cache = Some(cache);
+        self
+    }
+
+    /// Makes the executable of the AOT backend read the input of this process
+    /// directly, ignoring the input given to `Engine::run`.
+    pub fn with_inherited_stdin(mut self, inherit_stdin: bool) -> Self {
+        self.inherit_stdin = inherit_stdin;
+        self
+    }
+
+    /// Prepares the program to be run, compiling it if the backend does so.
+    pub fn build(
+        &self,
+        program: &Program,
+    ) -> Result<Box<dyn Engine>, RunError> {
+        // Every backend implements the only dialect so far.
+        let Dialect::Catbf = self.dialect;
+        if matches!(self.backend, Backend::Jit | Backend::Aot) {
+            if self.limits.max_steps.is_some() {
+                Err(RunError::Unsupported {
+                    backend: self.backend,
+                    feature: "step limits",
+                })?;
+            }
+            if self.limits.tape_policy != TapePolicy::Unbounded {
+                Err(RunError::Unsupported {
+                    backend: self.backend,
+                    feature: "bounded tapes",
+                })?;
+            }
+        }
+        if self.backend == Backend::Jit && self.jit_options.resumable {
+            Err(RunError::Unsupported {
+                backend: self.backend,
+                feature: "resumable code",
+            })?;
+        }
+
+        let program = if self.opt_level >= 1 {
+            program.optimized()?
+        } else {
+            program.clone()
+        };
+
+        let engine: Box<dyn Engine> = match self.backend {
+            Backend::Interpreter | Backend::Tiered => Box::new(Interpreted {
+                program,
+                tiered: self.backend == Backend::Tiered,
+                hot_loop_threshold: self.hot_loop_threshold,
+                limits: self.limits,
+            }),
+            Backend::Jit => {
+                let executable = match &self.jit_cache {
+                    Some(cache) => {
+                        cache.compile(&program, &self.jit_options)?
+                    },
+                    None => jit::compile_with(&program, &self.jit_options)?,
+                };
+                Box::new(JitCompiled(executable))
+            },
+            Backend::Aot => {
+                Box::new(AotCompiled::new(&program, self.inherit_stdin)?)
+            },
+        };
+        Ok(engine)
+    }
+
+    /// Parses the source and prepares it to be run.
+    pub fn build_source<R>(
+        &self,
+        source: R,
+    ) -> Result<Box<dyn Engine>, RunError>
+    where
+        R: Read,
+    {
+        self.build(&Program::parse(Source::new(FromRead(source)))?)
+    }
+}
+
+/// The interpreter or the tiered backend.
+#[derive(Debug, Clone)]
+struct Interpreted {
+    program: Program,
+    tiered: bool,
+    hot_loop_threshold: u64,
+    limits: Limits,
+}
+
+impl Engine for Interpreted {
+    fn backend(&self) -> Backend {
+        if self.tiered {
+            Backend::Tiered
+        } else {
+            Backend::Interpreter
+        }
+    }
+
+    fn run(
+        &self,
+        input: &mut (dyn Read + Send),
+        output: &mut (dyn Write + Send),
+    ) -> Result<Stats, RunError> {
+        let interface = Interface::from_std(input, &mut *output);
+        let program = self.program.clone();
+        let start = Instant::now();
+        let mut steps = 0;
+        let stats = if self.tiered {
+            let mut machine =
+                tiered::Machine::new(program, Tape::new(), interface)
+                    .with_threshold(self.hot_loop_threshold);
+            loop {
+                self.limits.check(
+                    steps,
+                    machine.is_halting(),
+                    machine.tape(),
+                )?;
+                if !machine.step()? {
+                    break;
+                }
+                steps += 1;
+            }
+            machine.stats()
+        } else {
+            let mut machine = Machine::new(program, Tape::new(), interface);
+            loop {
+                self.limits.check(
+                    steps,
+                    machine.is_halting(),
+                    machine.tape(),
+                )?;
+                if !machine.step()? {
+                    break;
+                }
+                steps += 1;
+            }
+            machine.stats()
+        };
+        output.flush()?;
+        Ok(Stats { time: start.elapsed(), ..stats })
+    }
+}
+
+/// The whole program compiled Just-In-Time.
+#[derive(Debug)]
+struct JitCompiled(jit::Executable);
+
+impl Engine for JitCompiled {
+    fn backend(&self) -> Backend {
+        Backend::Jit
+    }
+
+    fn run(
+        &self,
+        input: &mut (dyn Read + Send),
+        output: &mut (dyn Write + Send),
+    ) -> Result<Stats, RunError> {
+        let stats = self.0.run(input, &mut *output)?;
+        output.flush()?;
+        Ok(stats)
+    }
+}
+
+/// An executable compiled Ahead-Of-Time into a temporary directory, removed
+/// when dropped.
+#[derive(Debug)]
+struct AotCompiled {
+    directory: PathBuf,
+    inherit_stdin: bool,
+}
+
+impl AotCompiled {
+    fn new(program: &Program, inherit_stdin: bool) -> Result<Self, RunError> {
+        let engine = Self { directory: temp_directory()?, inherit_stdin };
+        aot::compile_executable(program, engine.directory.clone())?;
+        Ok(engine)
+    }
+}
+
+/// Creates a new directory only this user can access in the temporary
+/// directory. Names already taken, possibly by another user expecting the
+/// executable to be placed there, are skipped.
+fn temp_directory() -> io::Result<PathBuf> {
+    // Engines may be built concurrently by the same process.
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    loop {
+        let directory = env::temp_dir().join(format!(
+            "catbf-engine-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match builder.create(&directory) {
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => (),
+            result => break result.map(|()| directory),
+        }
+    }
+}
+
+impl Engine for AotCompiled {
+    fn backend(&self) -> Backend {
+        Backend::Aot
+    }
+
+    fn run(
+        &self,
+        input: &mut (dyn Read + Send),
+        output: &mut (dyn Write + Send),
+    ) -> Result<Stats, RunError> {
+        let input = (!self.inherit_stdin).then_some(input);
+        let stats =
+            run_executable(&self.directory.join("prog"), input, output)?;
+        output.flush()?;
+        Ok(stats)
+    }
+}
+
+impl Drop for AotCompiled {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.directory).ok();
+    }
+}
+
+/// Runs an executable compiled Ahead-Of-Time, copying its output. The input
+/// is copied to the executable until it ends or the executable stops reading
+/// it, and the executable reads the input of this process when none is given.
+///
+/// Only the time, which includes starting the executable, and the bytes
+/// written are counted. Input may be copied to the executable ahead of what
+/// it reads, so the bytes read are not.
+pub fn run_executable(
+    path: &Path,
+    input: Option<&mut (dyn Read + Send)>,
+    output: &mut dyn Write,
+) -> Result<Stats, RunError> {
+    let start = Instant::now();
+    let stdin = match input {
+        Some(_) => Stdio::piped(),
+        None => Stdio::inherit(),
+    };
+    let mut child = ChildGuard(
+        process::Command::new(path)
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .spawn()?,
+    );
+    let stdin = child.0.stdin.take();
+    let stdout = child.0.stdout.take();
+
+    let bytes_written = thread::scope(|scope| {
+        let feeder = stdin.zip(input).map(|(mut stdin, input)| {
+            scope.spawn(move || match io::copy(input, &mut stdin) {
+                // The program may halt before reading the whole input.
+                Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {
+                    Ok(0)
+                },
+                result => result,
+            })
+        });
+        let copied = match stdout {
+            Some(mut stdout) => io::copy(&mut stdout, output),
+            None => Ok(0),
+        };
+        if copied.is_err() {
+            // The feeder may be blocked until the executable reads.
+            child.0.kill().ok();
+        }
+        let fed = match feeder {
+            Some(feeder) => feeder
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Ok(0),
+        };
+        copied.and_then(|copied| fed.map(|_| copied))
+    })?;
+
+    let status = child.0.wait()?;
+    if !status.success() {
+        Err(RunError::ExecutableFailed(status))?;
+    }
+    Ok(Stats { bytes_written, time: start.elapsed(), ..Stats::default() })
+}
+
+/// A child process, killed and waited for when dropped, so that returning
+/// early leaves no process behind.
+#[derive(Debug)]
+struct ChildGuard(process::Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        // Does nothing if the child was already waited for.
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+/// Runs the code interpreted with the given input, returning its output.
+pub fn run_bytes(code: &[u8], input: &[u8]) -> Result<Vec<u8>, RunError> {
+    run_bytes_with(code, input, &EngineBuilder::default())
+}
+
+/// Runs the code with the given input on an engine configured by the
+/// builder, returning its output.
+pub fn run_bytes_with(
+    code: &[u8],
+    input: &[u8],
+    builder: &EngineBuilder,
+) -> Result<Vec<u8>, RunError> {
+    let mut input = input;
+    let mut output = Vec::new();
+    builder.build_source(code)?.run(&mut input, &mut output)?;
+    Ok(output)
+}
@@ -1,13 +1,22 @@
 //! Basic Brainfuck interpreter.
 
-use crate::ir::{Instruction, Program};
-use std::{io, iter};
+use crate::{
+    io::{self, Input, Output},
+    ir::{Instruction, Program},
+    stats::Stats,
+};
+use alloc::{vec, vec::Vec};
+use core::iter;
+#[cfg(feature = "std")]
+use std::time::Instant;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ControlError {
     #[error("label {} is out of bounds", .0)]
     BadLabel(usize),
+    #[error("machine is not waiting for input")]
+    NotWaitingInput,
 }
 
 #[derive(Debug, Error)]
@@ -18,6 +27,15 @@ pub enum Error {
     Io(#[from] io::Error),
 }
 
+/// Why a machine returned control to its caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Status {
+    /// The program ran until its end.
+    Halted,
+    /// The program is waiting for input, to be given when resuming.
+    NeedsInput,
+}
+
 /// A tape allocated for the interpreter.
 #[derive(Debug, Clone)]
 pub struct Tape {
@@ -25,8 +43,15 @@ pub struct Tape {
     cursor: usize,
 }
 
+impl Default for Tape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Tape {
-    const CHUNK_SIZE: usize = 8192;
+    /// Cells the tape starts with and grows by.
+    pub const CHUNK_SIZE: usize = 8192;
 
     pub fn new() -> Self {
         Self { cells: vec![0; Self::CHUNK_SIZE], cursor: 0 }
@@ -54,35 +79,65 @@ impl Tape {
         self.cursor -= 1;
     }
 
+    /// Index of the current cell.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Cells allocated so far.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_cursor(&mut self, cursor: usize) {
+        self.cursor = cursor;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.cells.as_mut_ptr()
+    }
+
     /// Grows the tape by a chunk (currently 8k) forwards.
-    fn grow_next(&mut self) {
+    pub(crate) fn grow_next(&mut self) {
         let new_len = self.cells.len() + Self::CHUNK_SIZE;
         self.cells.resize(new_len, 0);
     }
 
     /// Grows the tape by a chunk (currently 8k) backwards.
     fn grow_prev(&mut self) {
-        self.cells.splice(.. 0, iter::repeat(0).take(Self::CHUNK_SIZE));
+        self.grow_prev_cells();
         self.cursor += Self::CHUNK_SIZE;
     }
 
+    /// Grows the tape by a chunk backwards without adjusting the cursor.
+    pub(crate) fn grow_prev_cells(&mut self) {
+        self.cells.splice(.. 0, iter::repeat_n(0, Self::CHUNK_SIZE));
+    }
+
+    /// Writes whether a byte was read and the byte itself, which is zero on
+    /// EOF, into the current and the next cells, like the compilers do.
     fn input(&mut self, result: Option<u8>) {
-        match result {
-            Some(byte) => {
-                self.cells[self.cursor] = 1;
-                self.next();
-                self.cells[self.cursor] = byte;
-                self.prev();
-            },
-            None => self.cells[self.cursor] = 0,
-        }
+        let (flag, byte) = match result {
+            Some(byte) => (1, byte),
+            None => (0, 0),
+        };
+        self.cells[self.cursor] = flag;
+        self.next();
+        self.cells[self.cursor] = byte;
+        self.prev();
     }
 
     fn output(&self) -> u8 {
         self.cells[self.cursor]
     }
 
-    fn is_zero(&self) -> bool {
+    pub(crate) fn is_zero(&self) -> bool {
         self.cells[self.cursor] == 0
     }
 }
@@ -95,29 +150,33 @@ pub struct Interface<I, O> {
 
 impl<I, O> Interface<I, O>
 where
-    I: io::Read,
-    O: io::Write,
+    I: Input,
+    O: Output,
 {
     pub fn new(input: I, output: O) -> Self {
         Self { input, output }
     }
 
-    pub fn get(&mut self) -> io::Result<Option<u8>> {
-        let mut buf = [0];
-        if let Err(error) = self.input.read_exact(&mut buf) {
-            if error.kind() == io::ErrorKind::UnexpectedEof {
-                Ok(None)
-            } else {
-                Err(error)
-            }
-        } else {
-            Ok(Some(buf[0]))
-        }
+    pub fn get(&mut self) -> Result<Option<u8>, io::Error> {
+        self.input.get()
     }
 
-    pub fn put(&mut self, byte: u8) -> io::Result<()> {
-        self.output.write_all(&[byte])?;
-        Ok(())
+    pub fn put(&mut self, byte: u8) -> Result<(), io::Error> {
+        self.output.put(byte)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R, W> Interface<io::FromRead<R>, io::FromWrite<W>>
+where
+    R: std::io::Read,
+    W: std::io::Write,
+{
+    /// Reads from a `std::io::Read` and writes to a `std::io::Write`, which is
+    /// what `Interface::new` took before input and output went through
+    /// `catbf::io`.
+    pub fn from_std(reader: R, writer: W) -> Self {
+        Self::new(io::FromRead(reader), io::FromWrite(writer))
     }
 }
 
@@ -152,47 +211,202 @@ pub struct Machine<I, O> {
     control: Control,
     tape: Tape,
     interface: Interface<I, O>,
+    /// Whether the machine stopped right before reading input.
+    waiting_input: bool,
+    stats: Stats,
+    /// Position of the cursor relative to the cell where it started.
+    position: i64,
 }
 
 impl<I, O> Machine<I, O>
 where
-    I: io::Read,
-    O: io::Write,
+    I: Input,
+    O: Output,
 {
     pub fn new(
         program: Program,
         tape: Tape,
         interface: Interface<I, O>,
     ) -> Self {
-        Self { control: Control::new(program), tape, interface }
+        Self {
+            control: Control::new(program),
+            tape,
+            interface,
+            waiting_input: false,
+            stats: Stats::counting_all(),
+            position: 0,
+        }
     }
 
     pub fn step(&mut self) -> Result<bool, Error> {
-        match self.control.fetch()? {
+        let instruction = self.control.fetch()?;
+        let len = self.tape.len();
+        match instruction {
             Instruction::Halt => return Ok(false),
             Instruction::Inc => self.tape.inc(),
             Instruction::Dec => self.tape.dec(),
-            Instruction::Next => self.tape.next(),
-            Instruction::Prev => self.tape.prev(),
-            Instruction::Get => self.tape.input(self.interface.get()?),
-            Instruction::Put => self.interface.put(self.tape.output())?,
+            Instruction::Next => {
+                self.tape.next();
+                self.move_cursor(1);
+            },
+            Instruction::Prev => {
+                self.tape.prev();
+                self.move_cursor(-1);
+            },
+            Instruction::Get => {
+                let input = self.interface.get()?;
+                self.input(input);
+            },
+            Instruction::Put => {
+                self.interface.put(self.tape.output())?;
+                self.stats.bytes_written += 1;
+            },
             Instruction::Jz(label) => {
                 if self.tape.is_zero() {
                     self.control.jump(label);
+                } else {
+                    self.count_loop_iteration();
                 }
             },
             Instruction::Jnz(label) => {
                 if !self.tape.is_zero() {
                     self.control.jump(label);
+                    self.count_loop_iteration();
                 }
             },
         }
+        self.count_growths(len);
+        if let Some(instructions) = &mut self.stats.instructions {
+            *instructions += 1;
+        }
 
         Ok(true)
     }
 
-    pub fn run(mut self) -> Result<(), Error> {
-        while self.step()? {}
-        Ok(())
+    /// Runs the program until it halts, returning the statistics of the run.
+    pub fn run(mut self) -> Result<Stats, Error> {
+        self.timed(|machine| {
+            while machine.step()? {}
+            Ok(())
+        })?;
+        Ok(self.stats)
+    }
+
+    /// Runs the program until it halts or needs input, without reading from
+    /// the interface. Input is then given through `resume`.
+    pub fn start(&mut self) -> Result<Status, Error> {
+        self.timed(Self::run_until_input)
+    }
+
+    /// Runs the function, counting the time it takes. Time is only counted
+    /// with the `std` feature.
+    fn timed<T, F>(&mut self, run: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        let result = run(self);
+        #[cfg(feature = "std")]
+        {
+            self.stats.time += start.elapsed();
+        }
+        result
+    }
+
+    fn run_until_input(&mut self) -> Result<Status, Error> {
+        loop {
+            if self.control.program.code.get(self.control.ip)
+                == Some(&Instruction::Get)
+            {
+                self.waiting_input = true;
+                return Ok(Status::NeedsInput);
+            }
+            if !self.step()? {
+                return Ok(Status::Halted);
+            }
+        }
+    }
+
+    /// Continues a machine waiting for input with the given byte, or `None`
+    /// for EOF.
+    pub fn resume(&mut self, input: Option<u8>) -> Result<Status, Error> {
+        if !self.waiting_input {
+            Err(ControlError::NotWaitingInput)?;
+        }
+        self.waiting_input = false;
+        self.control.fetch()?;
+        let len = self.tape.len();
+        self.input(input);
+        self.count_growths(len);
+        if let Some(instructions) = &mut self.stats.instructions {
+            *instructions += 1;
+        }
+        self.start()
+    }
+
+    /// Statistics of the run so far. Time is only counted by `run`, `start`
+    /// and `resume`.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn input(&mut self, input: Option<u8>) {
+        self.tape.input(input);
+        if input.is_some() {
+            self.stats.bytes_read += 1;
+        }
+    }
+
+    fn move_cursor(&mut self, offset: i64) {
+        self.position += offset;
+        self.stats.move_cursor(self.position);
+    }
+
+    fn count_loop_iteration(&mut self) {
+        if let Some(loop_iterations) = &mut self.stats.loop_iterations {
+            *loop_iterations += 1;
+        }
+    }
+
+    /// Counts the chunks the tape grew by since it had the given length.
+    fn count_growths(&mut self, len: usize) {
+        self.stats.tape_growths +=
+            ((self.tape.len() - len) / Tape::CHUNK_SIZE) as u64;
+    }
+
+    /// Index of the next instruction to be executed.
+    pub fn ip(&self) -> usize {
+        self.control.ip
+    }
+
+    /// Whether the next instruction is the final `Halt`.
+    pub fn is_halting(&self) -> bool {
+        self.program().code.get(self.ip()) == Some(&Instruction::Halt)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn jump(&mut self, label: usize) {
+        self.control.jump(label);
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.control.program
+    }
+
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// Gives the tape back, so that it can be used by another machine.
+    pub fn into_tape(self) -> Tape {
+        self.tape
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn parts_mut(
+        &mut self,
+    ) -> (&mut Tape, &mut Interface<I, O>, &mut Stats) {
+        (&mut self.tape, &mut self.interface, &mut self.stats)
     }
 }
@@ -0,0 +1,110 @@
+//! Byte-wise input and output of the parser and the interpreter, usable
+//! without `std`. With the `std` feature, `FromRead` and `FromWrite` adapt
+//! `std::io` readers and writers.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{}", .0)]
+    Other(&'static str),
+    #[cfg(feature = "std")]
+    #[error("{}", .0)]
+    Std(#[from] std::io::Error),
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Other(message) => Self::other(message),
+            Error::Std(error) => error,
+        }
+    }
+}
+
+/// Source of bytes, read one at a time.
+pub trait Input {
+    /// Reads a byte, or `None` at the end of the input.
+    fn get(&mut self) -> Result<Option<u8>, Error>;
+}
+
+/// Sink of bytes, written one at a time.
+pub trait Output {
+    fn put(&mut self, byte: u8) -> Result<(), Error>;
+}
+
+impl Input for &[u8] {
+    fn get(&mut self) -> Result<Option<u8>, Error> {
+        let Some((&byte, rest)) = self.split_first() else {
+            return Ok(None);
+        };
+        *self = rest;
+        Ok(Some(byte))
+    }
+}
+
+impl Output for alloc::vec::Vec<u8> {
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        self.push(byte);
+        Ok(())
+    }
+}
+
+impl<T> Input for &mut T
+where
+    T: Input + ?Sized,
+{
+    fn get(&mut self) -> Result<Option<u8>, Error> {
+        (**self).get()
+    }
+}
+
+impl<T> Output for &mut T
+where
+    T: Output + ?Sized,
+{
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        (**self).put(byte)
+    }
+}
+
+/// Input reading from a `std::io::Read`, a byte per call, so it should be
+/// buffered.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct FromRead<R>(pub R);
+
+#[cfg(feature = "std")]
+impl<R> Input for FromRead<R>
+where
+    R: std::io::Read,
+{
+    fn get(&mut self) -> Result<Option<u8>, Error> {
+        let mut buf = [0];
+        match self.0.read_exact(&mut buf) {
+            Ok(()) => Ok(Some(buf[0])),
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Ok(None)
+            },
+            Err(error) => Err(error)?,
+        }
+    }
+}
+
+/// Output writing to a `std::io::Write`, a byte per call, so it should be
+/// buffered.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct FromWrite<W>(pub W);
+
+#[cfg(feature = "std")]
+impl<W> Output for FromWrite<W>
+where
+    W: std::io::Write,
+{
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        self.0.write_all(&[byte])?;
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 //! Intermediate Representation (IR) of Brainfuck programs.
 
-use crate::source::{Location, Source};
-use std::{collections::HashSet, fmt, io};
+use crate::{
+    io::{self, Input},
+    source::{Location, Source},
+};
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+use core::{fmt, iter, str::FromStr};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,6 +18,12 @@ pub enum ParseError {
     UnmatchedLoopClose(Location),
 }
 
+#[derive(Debug, Error)]
+pub enum StructureError {
+    #[error("jump at instruction {} does not delimit a loop", .0)]
+    BadJump(usize),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Instruction {
     /// Inserted when a Brainfuck program reaches its end.
@@ -54,24 +64,136 @@ impl fmt::Display for Instruction {
     }
 }
 
+/// A node of the structured form of a program, in which loops are nested
+/// blocks instead of pairs of jumps.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    /// An instruction other than a jump, at the given index of the code.
+    Instruction { ip: usize, instruction: Instruction },
+    /// Runs the body while the current cell is not zero. Equivalent to `[`
+    /// and `]`, the `Jz` being at the given index of the code.
+    Loop { ip: usize, body: Vec<Node> },
+}
+
+/// A node of a block with runs of the same instruction merged, yielded by
+/// `runs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Run<'nodes> {
+    /// An instruction repeated `count` times in a row. Only `Inc`, `Dec`,
+    /// `Next` and `Prev` are ever repeated. Cells wrap around, so for `Inc`
+    /// and `Dec` only `count` modulo 256 matters.
+    Instruction {
+        instruction: Instruction,
+        count: usize,
+    },
+    Loop {
+        body: &'nodes [Node],
+    },
+}
+
+/// Merges runs of the same cell or cursor instruction in a block, for
+/// backends emitting a single operation per run.
+pub fn runs(nodes: &[Node]) -> impl Iterator<Item = Run<'_>> {
+    let mut i = 0;
+    iter::from_fn(move || {
+        let node = nodes.get(i)?;
+        i += 1;
+        Some(match node {
+            Node::Instruction { instruction, .. } => {
+                let mut count = 1;
+                let repeatable = matches!(
+                    instruction,
+                    Instruction::Inc
+                        | Instruction::Dec
+                        | Instruction::Next
+                        | Instruction::Prev
+                );
+                while let Some(Node::Instruction {
+                    instruction: next, ..
+                }) = nodes.get(i)
+                {
+                    if !repeatable || next != instruction {
+                        break;
+                    }
+                    count += 1;
+                    i += 1;
+                }
+                Run::Instruction { instruction: *instruction, count }
+            },
+            Node::Loop { body, .. } => Run::Loop { body },
+        })
+    })
+}
+
+/// A suspicious construct found by `Program::lint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    /// A loop, whose `Jz` is at the given index, that never runs since the
+    /// cell is zero whenever it is reached.
+    DeadLoop(usize),
+    /// An instruction at the given index immediately undone by the next one,
+    /// such as `+-` or `<>`.
+    Undone(usize),
+    /// A loop, whose `Jz` is at the given index, with an empty body, which
+    /// never ends once entered.
+    EmptyLoop(usize),
+}
+
+impl Lint {
+    /// Index of the instruction the lint is about.
+    pub fn ip(self) -> usize {
+        match self {
+            Self::DeadLoop(ip) | Self::Undone(ip) | Self::EmptyLoop(ip) => ip,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DeadLoop(_) => {
+                write!(fmtr, "loop never runs, the cell is always zero here")
+            },
+            Self::Undone(_) => {
+                write!(fmtr, "instruction is undone by the next one")
+            },
+            Self::EmptyLoop(_) => {
+                write!(fmtr, "empty loop never ends once entered")
+            },
+        }
+    }
+}
+
 /// A complete Brainfuck program in the IR format.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Program {
     /// Serial list of instructions.
     pub code: Vec<Instruction>,
+    /// Source code location of each instruction, indexed in parallel with
+    /// `code`. The final `Halt` is located right after the end of the source.
+    /// May be empty if the program was not parsed from source code.
+    pub locations: Vec<Location>,
 }
 
 impl Program {
+    /// Returns the source location of the instruction at the given index, if
+    /// known.
+    pub fn location(&self, ip: usize) -> Option<Location> {
+        self.locations.get(ip).copied()
+    }
+
     /// Parses from the given source code reader, yielding a program in the IR
     /// format.
     pub fn parse<R>(mut source: Source<R>) -> Result<Self, ParseError>
     where
-        R: io::Read,
+        R: Input,
     {
         let mut code = Vec::new();
+        let mut locations = Vec::new();
         let mut loop_starts = Vec::new();
 
         while let Some((byte, location)) = source.try_next()? {
+            let ip = code.len();
             match byte {
                 b'+' => code.push(Instruction::Inc),
                 b'-' => code.push(Instruction::Dec),
@@ -80,7 +202,6 @@ impl Program {
                 b',' => code.push(Instruction::Get),
                 b'.' => code.push(Instruction::Put),
                 b'[' => {
-                    let ip = code.len();
                     loop_starts.push((ip, location));
                     code.push(Instruction::Jz(0));
                 },
@@ -95,6 +216,9 @@ impl Program {
                 },
                 _ => (),
             }
+            if code.len() > ip {
+                locations.push(location);
+            }
         }
 
         if let Some((_, location)) = loop_starts.first() {
@@ -102,14 +226,167 @@ impl Program {
         }
 
         code.push(Instruction::Halt);
+        locations.push(source.curr_location());
+
+        Ok(Self { code, locations })
+    }
+
+    /// Converts the code into its structured form, failing if jumps do not
+    /// delimit properly nested loops. Jumps are turned into `Node::Loop`, so
+    /// `Jz` and `Jnz` are never found in a `Node::Instruction`.
+    pub fn structured(&self) -> Result<Vec<Node>, StructureError> {
+        // Index of the `Jz` of each enclosing loop, and the nodes of each
+        // enclosing block, the outermost first.
+        let mut loop_starts = Vec::new();
+        let mut blocks = vec![Vec::new()];
+
+        for (ip, instruction) in self.code.iter().copied().enumerate() {
+            match instruction {
+                Instruction::Jz(end) => {
+                    let closes = end
+                        .checked_sub(1)
+                        .and_then(|last| self.code.get(last))
+                        .is_some_and(|last| *last == Instruction::Jnz(ip + 1));
+                    if !closes {
+                        Err(StructureError::BadJump(ip))?;
+                    }
+                    loop_starts.push(ip);
+                    blocks.push(Vec::new());
+                },
+                Instruction::Jnz(label) => {
+                    let Some(start) = loop_starts.pop() else {
+                        Err(StructureError::BadJump(ip))?
+                    };
+                    if label != start + 1 {
+                        Err(StructureError::BadJump(ip))?;
+                    }
+                    let body = blocks.pop().unwrap_or_default();
+                    if let Some(block) = blocks.last_mut() {
+                        block.push(Node::Loop { ip: start, body });
+                    }
+                },
+                _ => {
+                    if let Some(block) = blocks.last_mut() {
+                        block.push(Node::Instruction { ip, instruction });
+                    }
+                },
+            }
+        }
+
+        if let Some(start) = loop_starts.pop() {
+            Err(StructureError::BadJump(start))?;
+        }
+        Ok(blocks.pop().unwrap_or_default())
+    }
+
+    /// Removes loops that never run and instructions undone by the next one,
+    /// without changing what the program does.
+    pub fn optimized(&self) -> Result<Self, StructureError> {
+        let mut optimized = Self { code: Vec::new(), locations: Vec::new() };
+        optimized.append_optimized(self, &self.structured()?, true);
+        Ok(optimized)
+    }
+
+    /// Finds suspicious constructs, which are harmless but likely mistakes.
+    pub fn lint(&self) -> Result<Vec<Lint>, StructureError> {
+        let mut lints = Vec::new();
+        self.lint_block(&self.structured()?, true, &mut lints);
+        lints.sort_by_key(|lint| lint.ip());
+        Ok(lints)
+    }
+
+    /// Appends the optimized nodes of `source`, given whether the cell is
+    /// known to be zero before them.
+    fn append_optimized(
+        &mut self,
+        source: &Program,
+        nodes: &[Node],
+        mut zero: bool,
+    ) {
+        for node in nodes {
+            match node {
+                Node::Instruction { ip, instruction } => {
+                    if undoes(self.code.last().copied(), *instruction) {
+                        self.code.pop();
+                        self.locations.truncate(self.code.len());
+                    } else {
+                        self.push(*instruction, source.location(*ip));
+                    }
+                    zero = false;
+                },
+                Node::Loop { .. } if zero => (),
+                Node::Loop { ip, body } => {
+                    let start = self.code.len();
+                    self.push(Instruction::Jz(0), source.location(*ip));
+                    self.append_optimized(source, body, false);
+                    let end_location = match source.code[*ip] {
+                        Instruction::Jz(end) => source.location(end - 1),
+                        _ => None,
+                    };
+                    self.push(Instruction::Jnz(start + 1), end_location);
+                    self.code[start] = Instruction::Jz(self.code.len());
+                    zero = true;
+                },
+            }
+        }
+    }
+
+    fn push(&mut self, instruction: Instruction, location: Option<Location>) {
+        self.code.push(instruction);
+        self.locations.extend(location);
+    }
+
+    fn lint_block(
+        &self,
+        nodes: &[Node],
+        mut zero: bool,
+        lints: &mut Vec<Lint>,
+    ) {
+        let mut previous = None;
+        for node in nodes {
+            match node {
+                Node::Instruction { ip, instruction } => {
+                    match previous {
+                        Some((previous_ip, previous_instruction))
+                            if undoes(
+                                Some(previous_instruction),
+                                *instruction,
+                            ) =>
+                        {
+                            lints.push(Lint::Undone(previous_ip));
+                            // `+-+` is a single mistake.
+                            previous = None;
+                        },
+                        _ => previous = Some((*ip, *instruction)),
+                    }
+                    zero = false;
+                },
+                Node::Loop { ip, body } => {
+                    if zero {
+                        lints.push(Lint::DeadLoop(*ip));
+                    } else if body.is_empty() {
+                        lints.push(Lint::EmptyLoop(*ip));
+                    }
+                    self.lint_block(body, false, lints);
+                    previous = None;
+                    zero = true;
+                },
+            }
+        }
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
 
-        Ok(Self { code })
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Self::parse(Source::new(code.as_bytes()))
     }
 }
 
 impl fmt::Display for Program {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
-        let labels: HashSet<usize> = self
+        let labels: BTreeSet<usize> = self
             .code
             .iter()
             .filter_map(|instruction| match *instruction {
@@ -119,10 +396,21 @@ impl fmt::Display for Program {
             .collect();
         for (i, instruction) in self.code.iter().enumerate() {
             if labels.contains(&i) {
-                write!(fmtr, "label_{}:\n", i)?;
+                writeln!(fmtr, "label_{}:", i)?;
             }
-            write!(fmtr, "    {}\n", instruction)?;
+            writeln!(fmtr, "    {}", instruction)?;
         }
         Ok(())
     }
 }
+
+/// Whether an instruction undoes the previous one.
+fn undoes(previous: Option<Instruction>, instruction: Instruction) -> bool {
+    matches!(
+        (previous, instruction),
+        (Some(Instruction::Inc), Instruction::Dec)
+            | (Some(Instruction::Dec), Instruction::Inc)
+            | (Some(Instruction::Next), Instruction::Prev)
+            | (Some(Instruction::Prev), Instruction::Next)
+    )
+}
@@ -1,4 +1,22 @@
+//! Brainfuck interpreter and compilers. Without the default `std` feature,
+//! only the parser, the IR and the interpreter are available, needing just
+//! `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod io;
 pub mod source;
 pub mod ir;
 pub mod interpreter;
+#[cfg(feature = "std")]
 pub mod compiler;
+#[cfg(feature = "std")]
+pub mod tiered;
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod engine;
+
+#[cfg(feature = "std")]
+pub use engine::{run_bytes, run_bytes_with};
@@ -1,12 +1,17 @@
 //! Utilities to help tracking source code locations and emitting reasonable
 //! information in parse error messages.
 
-use std::{fmt, io};
+use crate::io::{self, Input};
+use alloc::vec::{self, Vec};
+use core::fmt;
 
 /// Location of an object in the source code.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Location {
-    /// Absolute bytewise position.
+    /// Index of the file among the ones read one after the other by the
+    /// source, zero if there is only one.
+    pub file: usize,
+    /// Absolute bytewise position, within the file.
     pub position: u64,
     /// Line number.
     pub line: u64,
@@ -22,7 +27,7 @@ impl Default for Location {
 
 impl Location {
     /// Location of the beginning of the the source file.
-    pub const START: Self = Self { position: 0, line: 1, column: 1 };
+    pub const START: Self = Self { file: 0, position: 0, line: 1, column: 1 };
 
     /// Advances the location given the current byte.
     pub fn next(&mut self, byte: u8) {
@@ -44,22 +49,43 @@ impl fmt::Display for Location {
 
 #[derive(Debug)]
 pub struct Source<R> {
-    bytes: io::Bytes<R>,
+    /// Reader of the current file, `None` once every file was read.
+    reader: Option<R>,
+    /// Readers of the files after the current one.
+    next_files: vec::IntoIter<R>,
     curr_location: Location,
 }
 
 impl<R> Source<R>
 where
-    R: io::Read,
+    R: Input,
 {
+    /// Creates a source from the given reader. The reader is consumed byte by
+    /// byte, so it should be buffered.
     pub fn new(reader: R) -> Self {
-        Self { bytes: reader.bytes(), curr_location: Location::START }
+        Self::concat([reader])
+    }
+
+    /// Creates a source reading the given readers one after the other, as a
+    /// single program, with locations telling their files apart. Readers are
+    /// consumed byte by byte, so they should be buffered.
+    pub fn concat<I>(readers: I) -> Self
+    where
+        I: IntoIterator<Item = R>,
+    {
+        let mut next_files =
+            readers.into_iter().collect::<Vec<_>>().into_iter();
+        Self {
+            reader: next_files.next(),
+            next_files,
+            curr_location: Location::START,
+        }
     }
 }
 
 impl<R> From<R> for Source<R>
 where
-    R: io::Read,
+    R: Input,
 {
     fn from(reader: R) -> Self {
         Self::new(reader)
@@ -68,28 +94,39 @@ where
 
 impl<R> Source<R>
 where
-    R: io::Read,
+    R: Input,
 {
     pub fn curr_location(&self) -> Location {
         self.curr_location
     }
 
-    pub fn try_next(&mut self) -> io::Result<Option<(u8, Location)>> {
-        let Some(byte) = self.bytes.next().transpose()? else {
-            return Ok(None);
-        };
-
-        let location = self.curr_location();
-        self.curr_location.next(byte);
-        Ok(Some((byte, location)))
+    pub fn try_next(&mut self) -> Result<Option<(u8, Location)>, io::Error> {
+        loop {
+            let Some(reader) = &mut self.reader else {
+                return Ok(None);
+            };
+            if let Some(byte) = reader.get()? {
+                let location = self.curr_location();
+                self.curr_location.next(byte);
+                return Ok(Some((byte, location)));
+            }
+            self.reader = self.next_files.next();
+            if self.reader.is_none() {
+                return Ok(None);
+            }
+            self.curr_location = Location {
+                file: self.curr_location.file + 1,
+                ..Location::START
+            };
+        }
     }
 }
 
 impl<R> Iterator for Source<R>
 where
-    R: io::Read,
+    R: Input,
 {
-    type Item = io::Result<(u8, Location)>;
+    type Item = Result<(u8, Location), io::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.try_next().transpose()
@@ -0,0 +1,84 @@
+//! Statistics of a run of a program.
+
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+};
+use core::{fmt, time::Duration};
+
+/// What happened during a run of a program. Counters that a backend does not
+/// keep are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Stats {
+    /// Instructions executed, not counting the final `Halt`.
+    pub instructions: Option<u64>,
+    /// Times the body of a loop was entered.
+    pub loop_iterations: Option<u64>,
+    /// Bytes read from the input, not counting EOF.
+    pub bytes_read: u64,
+    /// Bytes written to the output.
+    pub bytes_written: u64,
+    /// Lowest position of the cursor, relative to the cell where it started.
+    pub min_cursor: Option<i64>,
+    /// Highest position of the cursor, relative to the cell where it started.
+    pub max_cursor: Option<i64>,
+    /// Times the tape grew by a chunk, in either direction.
+    pub tape_growths: u64,
+    /// Loops compiled Just-In-Time by the tiered backend.
+    pub compiled_loops: Option<u64>,
+    /// Time spent running the program. Only counted with the `std` feature.
+    pub time: Duration,
+}
+
+impl Stats {
+    /// Statistics of a run that did not start yet, with every counter kept
+    /// by the interpreter.
+    pub fn counting_all() -> Self {
+        Self {
+            instructions: Some(0),
+            loop_iterations: Some(0),
+            min_cursor: Some(0),
+            max_cursor: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// Records that the cursor moved to the given position.
+    pub(crate) fn move_cursor(&mut self, position: i64) {
+        if let Some(min_cursor) = &mut self.min_cursor {
+            *min_cursor = (*min_cursor).min(position);
+        }
+        if let Some(max_cursor) = &mut self.max_cursor {
+            *max_cursor = (*max_cursor).max(position);
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmtr, "instructions:    {}", optional(self.instructions))?;
+        writeln!(fmtr, "loop iterations: {}", optional(self.loop_iterations))?;
+        writeln!(fmtr, "bytes read:      {}", self.bytes_read)?;
+        writeln!(fmtr, "bytes written:   {}", self.bytes_written)?;
+        writeln!(fmtr, "min cursor:      {}", optional(self.min_cursor))?;
+        writeln!(fmtr, "max cursor:      {}", optional(self.max_cursor))?;
+        writeln!(fmtr, "tape growths:    {}", self.tape_growths)?;
+        writeln!(fmtr, "compiled loops:  {}", optional(self.compiled_loops))?;
+        write!(
+            fmtr,
+            "time:            {:.3}ms",
+            self.time.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Shows a counter that may not be kept, as `-` if it is not.
+fn optional<T>(value: Option<T>) -> String
+where
+    T: fmt::Display,
+{
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".to_owned(),
+    }
+}
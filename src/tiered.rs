@@ -0,0 +1,362 @@
+//! Tiered execution: programs start interpreted, and loops that run often are
+//! compiled Just-In-Time into native fragments running on the interpreter's
+//! own tape. Gives fast startup to short programs and native speed to long
+//! running loops.
+
+use crate::{
+    compiler::jit::{self, Fragment, FragmentRuntime, FragmentTape},
+    interpreter::{self, Error, Interface, Tape},
+    io::{self, Input, Output},
+    ir::{Instruction, Program},
+    stats::Stats,
+};
+use std::{collections::HashMap, time::Instant};
+
+/// Default number of iterations after which a loop is compiled.
+pub const DEFAULT_HOT_LOOP_THRESHOLD: u64 = 1000;
+
+// Fragments and the interpreter grow the shared tape by the same amount.
+const _: () = assert!(Tape::CHUNK_SIZE == jit::TAPE_CHUNK_SIZE);
+
+#[derive(Debug)]
+enum LoopState {
+    /// Number of iterations run so far by the interpreter.
+    Counting(u64),
+    Compiled(Fragment),
+    /// The loop could not be compiled, so it is always interpreted.
+    Interpreted,
+}
+
+#[derive(Debug)]
+pub struct Machine<I, O> {
+    inner: interpreter::Machine<I, O>,
+    threshold: u64,
+    /// State of each loop, indexed by the position of its `Jz`.
+    loops: HashMap<usize, LoopState>,
+    /// Times a compiled loop was run.
+    native_runs: u64,
+}
+
+impl<I, O> Machine<I, O>
+where
+    I: Input,
+    O: Output,
+{
+    pub fn new(
+        program: Program,
+        tape: Tape,
+        interface: Interface<I, O>,
+    ) -> Self {
+        Self {
+            inner: interpreter::Machine::new(program, tape, interface),
+            threshold: DEFAULT_HOT_LOOP_THRESHOLD,
+            loops: HashMap::new(),
+            native_runs: 0,
+        }
+    }
+
+    /// Sets after how many iterations a loop is compiled.
+    pub fn with_threshold(mut self, threshold: u64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Number of loops currently running as native code.
+    pub fn compiled_loops(&self) -> usize {
+        self.loops
+            .values()
+            .filter(|state| matches!(state, LoopState::Compiled(_)))
+            .count()
+    }
+
+    pub fn tape(&self) -> &Tape {
+        self.inner.tape()
+    }
+
+    /// Whether the next instruction is the final `Halt`.
+    pub fn is_halting(&self) -> bool {
+        self.inner.is_halting()
+    }
+
+    pub fn step(&mut self) -> Result<bool, Error> {
+        let ip = self.inner.ip();
+        let loop_start = match self.inner.program().code.get(ip) {
+            Some(Instruction::Jz(_)) => Some(ip),
+            Some(Instruction::Jnz(label)) if !self.inner.tape().is_zero() => {
+                label.checked_sub(1)
+            },
+            _ => None,
+        };
+
+        if let Some(loop_start) = loop_start {
+            if self.enter_loop(loop_start)? {
+                return Ok(true);
+            }
+        }
+
+        self.inner.step()
+    }
+
+    /// Runs the program until it halts, returning the statistics of the run.
+    /// Instructions and loop iterations are only counted while interpreted,
+    /// and the cursor is not followed once a compiled loop ran.
+    pub fn run(mut self) -> Result<Stats, Error> {
+        let start = Instant::now();
+        while self.step()? {}
+        Ok(Stats { time: start.elapsed(), ..self.stats() })
+    }
+
+    /// Statistics of the run so far. Time is only counted by `run`.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.inner.stats();
+        stats.compiled_loops = Some(self.compiled_loops() as u64);
+        if self.native_runs > 0 {
+            stats.min_cursor = None;
+            stats.max_cursor = None;
+        }
+        stats
+    }
+
+    /// Counts an iteration of the loop, running it natively if hot. Returns
+    /// whether the loop was run to its end.
+    fn enter_loop(&mut self, loop_start: usize) -> Result<bool, Error> {
+        let threshold = self.threshold;
+        let state =
+            self.loops.entry(loop_start).or_insert(LoopState::Counting(0));
+        if let LoopState::Counting(count) = state {
+            *count += 1;
+            if *count >= threshold {
+                *state = compile::<I, O>(self.inner.program(), loop_start);
+            }
+        }
+        let LoopState::Compiled(fragment) = state else {
+            return Ok(false);
+        };
+        let Some(Instruction::Jz(loop_end)) =
+            self.inner.program().code.get(loop_start).copied()
+        else {
+            return Ok(false);
+        };
+
+        self.native_runs += 1;
+        let (tape, interface, stats) = self.inner.parts_mut();
+        let mut context = Context {
+            tape: FragmentTape {
+                start: tape.as_mut_ptr(),
+                len: tape.len(),
+                cursor: tape.cursor(),
+            },
+            machine_tape: tape,
+            interface,
+            stats,
+            error: None,
+        };
+        let status = unsafe {
+            fragment.call(&mut context as *mut Context<I, O> as *mut _)
+        };
+        let cursor = context.tape.cursor;
+        let error = context.error.take();
+        tape.set_cursor(cursor);
+        if status < 0 {
+            Err(error.unwrap_or(io::Error::Other("native loop failed")))?;
+        }
+
+        self.inner.jump(loop_end);
+        Ok(true)
+    }
+}
+
+fn compile<I, O>(program: &Program, loop_start: usize) -> LoopState
+where
+    I: Input,
+    O: Output,
+{
+    let runtime = FragmentRuntime {
+        grow_next: grow_next::<I, O> as *const () as usize,
+        grow_prev: grow_prev::<I, O> as *const () as usize,
+        get: get::<I, O> as *const () as usize,
+        put: put::<I, O> as *const () as usize,
+    };
+    match jit::compile_fragment(program, loop_start, &runtime) {
+        Ok(fragment) => LoopState::Compiled(fragment),
+        Err(_) => LoopState::Interpreted,
+    }
+}
+
+/// Context passed to fragments.
+#[repr(C)]
+struct Context<I, O> {
+    tape: FragmentTape,
+    machine_tape: *mut Tape,
+    interface: *mut Interface<I, O>,
+    stats: *mut Stats,
+    error: Option<io::Error>,
+}
+
+unsafe extern "sysv64" fn grow_next<I, O>(
+    _start: *mut u8,
+    _len: usize,
+    context: *mut Context<I, O>,
+) -> *mut u8 {
+    let tape = &mut *(*context).machine_tape;
+    tape.grow_next();
+    (*(*context).stats).tape_growths += 1;
+    tape.as_mut_ptr()
+}
+
+unsafe extern "sysv64" fn grow_prev<I, O>(
+    _start: *mut u8,
+    _len: usize,
+    context: *mut Context<I, O>,
+) -> *mut u8 {
+    let tape = &mut *(*context).machine_tape;
+    tape.grow_prev_cells();
+    (*(*context).stats).tape_growths += 1;
+    tape.as_mut_ptr()
+}
+
+unsafe extern "sysv64" fn get<I, O>(context: *mut Context<I, O>) -> i16
+where
+    I: Input,
+    O: Output,
+{
+    match (*(*context).interface).get() {
+        Ok(Some(byte)) => {
+            (*(*context).stats).bytes_read += 1;
+            (1 << 8) | i16::from(byte)
+        },
+        Ok(None) => 0,
+        Err(error) => {
+            (*context).error = Some(error);
+            -1
+        },
+    }
+}
+
+unsafe extern "sysv64" fn put<I, O>(context: *mut Context<I, O>, ch: u8) -> i8
+where
+    I: Input,
+    O: Output,
+{
+    match (*(*context).interface).put(ch) {
+        Ok(()) => {
+            (*(*context).stats).bytes_written += 1;
+            0
+        },
+        Err(error) => {
+            (*context).error = Some(error);
+            -1
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Machine;
+    use crate::{
+        compiler::jit,
+        interpreter::{self, Error, Interface, Tape},
+        io::{self, Output},
+        ir::Program,
+    };
+
+    /// Runs the program on the tiered machine and on the interpreter,
+    /// checking that they end with the same output and tape. Returns the
+    /// number of loops compiled.
+    fn compare(code: &str, input: &[u8]) -> usize {
+        let program: Program = code.parse().unwrap();
+
+        let mut expected = Vec::new();
+        let interface = Interface::new(input, &mut expected);
+        let mut interpreter =
+            interpreter::Machine::new(program.clone(), Tape::new(), interface);
+        while interpreter.step().unwrap() {}
+
+        let mut output = Vec::new();
+        let interface = Interface::new(input, &mut output);
+        let mut machine =
+            Machine::new(program, Tape::new(), interface).with_threshold(1);
+        while machine.step().unwrap() {}
+        let compiled_loops = machine.compiled_loops();
+
+        assert_eq!(machine.tape().cells(), interpreter.tape().cells());
+        assert_eq!(machine.tape().cursor(), interpreter.tape().cursor());
+        drop((machine, interpreter));
+        assert_eq!(output, expected);
+        compiled_loops
+    }
+
+    #[test]
+    fn compiles_hot_loops() {
+        if !jit::TARGET_SUPPORTED {
+            return;
+        }
+        let program: Program = "++++++++[>++++++++<-]>+.".parse().unwrap();
+        let mut output = Vec::new();
+        let interface = Interface::new(&[][..], &mut output);
+        let mut machine =
+            Machine::new(program, Tape::new(), interface).with_threshold(3);
+        while machine.step().unwrap() {}
+        assert!(machine.compiled_loops() > 0);
+        let stats = machine.stats();
+        assert_eq!(stats.compiled_loops, Some(1));
+        assert_eq!(stats.bytes_written, 1);
+        drop(machine);
+        assert_eq!(output, b"A");
+    }
+
+    #[test]
+    fn compiled_loops_grow_the_tape() {
+        if !jit::TARGET_SUPPORTED {
+            return;
+        }
+        // Each loop runs once and moves past a whole chunk.
+        let far = Tape::CHUNK_SIZE + 10;
+        let code = format!(
+            "+[{right}+{left}-]+[{left}++{right}-]{right}.{left}{left}.",
+            right = ">".repeat(far),
+            left = "<".repeat(far),
+        );
+        assert_eq!(compare(&code, b""), 2);
+    }
+
+    #[test]
+    fn compiled_loops_read_until_eof() {
+        if !jit::TARGET_SUPPORTED {
+            return;
+        }
+        assert_eq!(compare(",[>.<,]", b"hello"), 1);
+        assert_eq!(compare(",[>+.>,]<<.", b"abc"), 1);
+    }
+
+    /// An output failing on every byte.
+    struct Full;
+
+    impl Output for Full {
+        fn put(&mut self, _byte: u8) -> Result<(), io::Error> {
+            Err(io::Error::Other("output is full"))
+        }
+    }
+
+    #[test]
+    fn compiled_loops_fail_on_output_errors() {
+        if !jit::TARGET_SUPPORTED {
+            return;
+        }
+        let program: Program = "+[.]".parse().unwrap();
+        let interface = Interface::new(&[][..], Full);
+        let mut machine =
+            Machine::new(program, Tape::new(), interface).with_threshold(1);
+        let error = loop {
+            if let Err(error) = machine.step() {
+                break error;
+            }
+        };
+        assert_eq!(machine.compiled_loops(), 1);
+        assert!(
+            matches!(error, Error::Io(io::Error::Other("output is full"))),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Builds assembly of every built-in target with `cc`, if installed, and checks
+//! its line information and the comparison of its sizes.
+
+mod common;
+
+use catbf::{
+    compiler::aot::{self, Format, Options, BUILTIN_TARGETS},
+    ir::Program,
+};
+use common::FIXTURES;
+use std::fs;
+
+/// Spread over lines, with runs of instructions merged into one.
+const MULTILINE: &str = "+\n >>[-]\n\t.\n";
+
+#[test]
+fn debug_info_points_at_the_source() {
+    if !aot::TARGET_SUPPORTED || !common::installed("cc") {
+        return;
+    }
+    let program = MULTILINE.parse::<Program>().unwrap();
+    for target in BUILTIN_TARGETS {
+        let directory = common::directory(&format!("loc-{}", target));
+        let options = Options {
+            source_name: Some("prog.bf".to_owned()),
+            target: Some(target.to_string()),
+            ..Options::default()
+        };
+        aot::compile_with(&program, &directory, &options).unwrap();
+        let asm = fs::read_to_string(directory.join("prog.s")).unwrap();
+        let output = common::run(&directory.join("prog"), b"");
+        fs::remove_dir_all(&directory).ok();
+
+        assert!(asm.contains(".file 1 \"prog.bf\""), "{}", target);
+        let mut label = None;
+        let mut locations = Vec::new();
+        for line in asm.lines().map(str::trim) {
+            if let Some(id) = line.strip_prefix(".label_") {
+                label = id.strip_suffix(':').map(|id| id.parse().unwrap());
+            } else if let Some(loc) = line.strip_prefix(".loc 1 ") {
+                let id: usize = label.take().expect(".loc without a label");
+                let location = program.location(id).unwrap();
+                assert_eq!(
+                    loc,
+                    format!("{} {}", location.line, location.column),
+                    "{}: instruction {}",
+                    target,
+                    id
+                );
+                locations.push((location.line, location.column));
+            }
+        }
+        assert_eq!(
+            locations,
+            // The halt is at the end of the source.
+            [(1, 1), (2, 2), (2, 4), (2, 5), (2, 6), (3, 2), (4, 1)],
+            "{}",
+            target
+        );
+        assert_eq!(output, [0], "{}", target);
+    }
+}
+
+#[test]
+fn sizes_are_compared_for_each_target() {
+    if !aot::TARGET_SUPPORTED || !common::installed("cc") {
+        return;
+    }
+    let fixture = FIXTURES.iter().find(|f| f.name == "loop").unwrap();
+    let program = fixture.code.parse::<Program>().unwrap();
+    for target in BUILTIN_TARGETS {
+        let directory = common::directory(&format!("sizes-{}", target));
+        let options = Options {
+            // Ignored by the comparison.
+            format: Format::C,
+            target: Some(target.to_string()),
+            ..Options::default()
+        };
+        let sizes = aot::compare_sizes(&program, &directory, &options);
+        let output = common::run(&directory.join("prog"), fixture.input);
+        fs::remove_dir_all(&directory).ok();
+
+        let sizes = sizes.unwrap();
+        assert!(sizes.inline.binary > 0, "{}: {:?}", target, sizes);
+        assert!(sizes.shared.binary > 0, "{}: {:?}", target, sizes);
+        assert!(
+            sizes.shared.assembly < sizes.inline.assembly,
+            "{}: {:?}",
+            target,
+            sizes
+        );
+        assert_eq!(output, fixture.expected(), "{}", target);
+    }
+}
@@ -0,0 +1,32 @@
+//! Builds generated C with `cc`, if installed, and checks that it behaves
+//! like the interpreter.
+
+mod common;
+
+use catbf::{compiler::c, ir::Program};
+use common::FIXTURES;
+use std::{fs, process::Command};
+
+#[test]
+fn generated_c_builds_and_runs() {
+    if !common::installed("cc") {
+        return;
+    }
+    let directory = common::directory("c");
+    for fixture in FIXTURES {
+        let source = c::generate(&fixture.code.parse::<Program>().unwrap());
+        let path = directory.join(format!("{}.c", fixture.name));
+        fs::write(&path, source.unwrap()).unwrap();
+        common::build(
+            &directory,
+            Command::new("cc")
+                .args(["-std=c89", "-pedantic", "-Wall", "-Werror", "-O1"])
+                .arg(&path)
+                .arg("-o")
+                .arg(fixture.name),
+        );
+        let output = common::run(&directory.join(fixture.name), fixture.input);
+        assert_eq!(output, fixture.expected(), "{}", fixture.name);
+    }
+    fs::remove_dir_all(&directory).ok();
+}
@@ -0,0 +1,88 @@
+//! Programs and helpers shared by the tests building generated code with
+//! external tools, which are skipped when the tools are not installed.
+
+// Each test crate uses only some of these.
+#![allow(dead_code)]
+
+use catbf::engine::run_bytes;
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+
+/// A program built by every test, with the input it is run on.
+#[derive(Debug, Clone, Copy)]
+pub struct Fixture {
+    pub name: &'static str,
+    pub code: &'static str,
+    pub input: &'static [u8],
+}
+
+pub const FIXTURES: [Fixture; 3] = [
+    Fixture { name: "cat", code: ",[>.<,]", input: b"meow\n\xff\0" },
+    Fixture {
+        name: "hello",
+        code:
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++\
+               ..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+        input: b"",
+    },
+    // Nested loops, and the tape growing past its start.
+    Fixture {
+        name: "loop",
+        code: "++[>+++[>++<-]<-]>>.<<<<<<<<+[>+<-]>.>>>>>>>>+.",
+        input: b"",
+    },
+];
+
+impl Fixture {
+    /// Output of the program, as interpreted.
+    pub fn expected(&self) -> Vec<u8> {
+        run_bytes(self.code.as_bytes(), self.input).unwrap()
+    }
+}
+
+/// Whether the tool can be run.
+pub fn installed(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// An empty directory for the artifacts of a test.
+pub fn directory(name: &str) -> PathBuf {
+    let directory =
+        env::temp_dir().join(format!("catbf-test-{}-{}", name, process::id()));
+    fs::remove_dir_all(&directory).ok();
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Runs a command in the directory, failing with its errors if it fails.
+pub fn build(directory: &Path, command: &mut Command) {
+    let output = command.current_dir(directory).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed:\n{}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Runs an executable on the input, returning its output.
+pub fn run(executable: &Path, input: &[u8]) -> Vec<u8> {
+    let mut child = Command::new(executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output.status);
+    output.stdout
+}
@@ -0,0 +1,99 @@
+//! Limits enforced by engines.
+
+use catbf::{
+    compiler::aot,
+    engine::{run_bytes_with, Backend, EngineBuilder, RunError, TapePolicy},
+    ir::Program,
+};
+use std::io::{self, Write};
+
+#[test]
+fn step_limit_allows_programs_of_that_many_steps() {
+    for backend in [Backend::Interpreter, Backend::Tiered] {
+        let builder = EngineBuilder::new(backend).with_max_steps(4);
+        assert_eq!(run_bytes_with(b"+++.", b"", &builder).unwrap(), [3]);
+        let error = run_bytes_with(b"++++.", b"", &builder).unwrap_err();
+        assert!(matches!(error, RunError::StepLimit(4)), "{:?}", error);
+    }
+}
+
+#[test]
+fn tape_limit_fails_runs_growing_past_it() {
+    for backend in [Backend::Interpreter, Backend::Tiered] {
+        let builder = EngineBuilder::new(backend)
+            .with_tape_policy(TapePolicy::Bounded(1 << 16));
+        assert!(run_bytes_with(b">+<<+", b"", &builder).is_ok());
+        // Loops compiled by the tiered backend are only stopped once they
+        // end, so the tape grows without loops.
+        let code = [b'>'; 1 << 17];
+        let error = run_bytes_with(&code, b"", &builder).unwrap_err();
+        assert!(matches!(error, RunError::TapeLimit(_)), "{:?}", error);
+    }
+}
+
+#[test]
+fn compiled_backends_reject_limits() {
+    for backend in [Backend::Jit, Backend::Aot] {
+        let limited = EngineBuilder::new(backend).with_max_steps(10);
+        let bounded = EngineBuilder::new(backend)
+            .with_tape_policy(TapePolicy::Bounded(10));
+        for builder in [limited, bounded] {
+            let error = run_bytes_with(b"+.", b"", &builder).unwrap_err();
+            assert!(
+                matches!(error, RunError::Unsupported { .. }),
+                "{:?}",
+                error
+            );
+        }
+    }
+}
+
+/// A writer failing once it got the given number of bytes.
+struct Limited(usize);
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.0 {
+            return Err(io::Error::new(io::ErrorKind::StorageFull, "full"));
+        }
+        self.0 -= buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn aot_output_errors_stop_the_executable() {
+    if !aot::TARGET_SUPPORTED {
+        return;
+    }
+    // Prints forever unless stopped.
+    let engine = EngineBuilder::new(Backend::Aot)
+        .build(&"+[.]".parse::<Program>().unwrap())
+        .unwrap();
+    let error =
+        engine.run(&mut io::empty(), &mut Limited(1 << 16)).unwrap_err();
+    assert!(
+        matches!(&error, RunError::Io(error) if error.kind() == io::ErrorKind::StorageFull),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn aot_counts_output() {
+    if !aot::TARGET_SUPPORTED {
+        return;
+    }
+    let engine = EngineBuilder::new(Backend::Aot)
+        .build(&",[>.<,]".parse::<Program>().unwrap())
+        .unwrap();
+    let mut output = Vec::new();
+    let stats = engine.run(&mut &b"abc"[..], &mut output).unwrap();
+    assert_eq!(output, b"abc");
+    assert_eq!(stats.bytes_written, 3);
+    assert_eq!(stats.instructions, None);
+}
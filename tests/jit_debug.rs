@@ -0,0 +1,287 @@
+//! Reads back the perf map and the GDB JIT object describing code compiled
+//! Just-In-Time.
+
+use catbf::{compiler::jit, ir::Program};
+use std::{env, fs, process};
+
+/// Two loops, on lines 1 and 2.
+const CODE: &str = "+[>+<-]\n>[-].";
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+#[test]
+fn perf_map_lists_symbols() {
+    if !jit::TARGET_SUPPORTED {
+        return;
+    }
+    let path =
+        env::temp_dir().join(format!("catbf-test-{}.map", process::id()));
+    fs::remove_file(&path).ok();
+    let options = jit::Options {
+        perf_map: true,
+        perf_map_path: Some(path.clone()),
+        ..jit::Options::default()
+    };
+    let executable =
+        jit::compile_with(&CODE.parse::<Program>().unwrap(), &options).unwrap();
+    let map = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).ok();
+
+    let entries: Vec<_> = map
+        .lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let mut hex =
+                || usize::from_str_radix(fields.next().unwrap(), 16).unwrap();
+            let (address, size) = (hex(), hex());
+            (address, size, fields.next().unwrap().to_owned())
+        })
+        .collect();
+    let names: Vec<_> =
+        entries.iter().map(|(_, _, name)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "catbf_main",
+            "catbf_loop_1_2",
+            "catbf_main",
+            "catbf_loop_2_2",
+            "catbf_main"
+        ]
+    );
+    assert_eq!(entries[0].0, executable.address());
+    for (entry, next) in entries.iter().zip(&entries[1 ..]) {
+        assert!(entry.1 > 0);
+        assert_eq!(entry.0 + entry.1, next.0);
+    }
+    for ((address, size, name), symbol) in
+        entries.iter().zip(executable.symbols())
+    {
+        assert_eq!(*address, executable.address() + symbol.offset);
+        assert_eq!(*size, symbol.len);
+        assert_eq!(*name, symbol.name);
+    }
+}
+
+#[test]
+fn debug_object_has_symbols_and_lines() {
+    if !jit::TARGET_SUPPORTED {
+        return;
+    }
+    let options = jit::Options {
+        gdb_jit: true,
+        source_name: Some("test.bf".to_owned()),
+        ..jit::Options::default()
+    };
+    let program = CODE.parse::<Program>().unwrap();
+    if !cfg!(feature = "gdb-jit") {
+        let error = jit::compile_with(&program, &options).unwrap_err();
+        assert!(matches!(error, jit::Error::GdbJitDisabled), "{:?}", error);
+        return;
+    }
+    let executable = jit::compile_with(&program, &options).unwrap();
+    let object = executable.debug_object().unwrap();
+    let sections = sections(object);
+    let code_len: usize =
+        executable.symbols().iter().map(|symbol| symbol.len).sum();
+
+    let text = section(&sections, ".text");
+    assert_eq!(text.addr, executable.address() as u64);
+
+    let strtab = section(&sections, ".strtab").data(object);
+    let symbols: Vec<_> = section(&sections, ".symtab")
+        .data(object)
+        .chunks(24)
+        .skip(1)
+        .map(|entry| {
+            let name = u32_at(entry, 0) as usize;
+            let len = strtab[name ..].iter().position(|&byte| byte == 0);
+            let name = &strtab[name .. name + len.unwrap()];
+            (
+                String::from_utf8(name.to_vec()).unwrap(),
+                entry[4] >> 4,
+                u64_at(entry, 8),
+                u64_at(entry, 16),
+            )
+        })
+        .collect();
+    let mains: Vec<_> =
+        symbols.iter().filter(|symbol| symbol.0 == "catbf_main").collect();
+    assert_eq!(
+        mains,
+        [&("catbf_main".to_owned(), STB_GLOBAL, 0, code_len as u64)]
+    );
+    let loops: Vec<_> =
+        symbols.iter().filter(|symbol| symbol.0 != "catbf_main").collect();
+    let expected: Vec<_> = executable
+        .symbols()
+        .iter()
+        .filter(|symbol| symbol.name != "catbf_main")
+        .map(|symbol| {
+            (
+                symbol.name.clone(),
+                STB_LOCAL,
+                symbol.offset as u64,
+                symbol.len as u64,
+            )
+        })
+        .collect();
+    assert_eq!(loops.len(), 2);
+    assert!(loops.into_iter().eq(&expected));
+
+    let rows = line_rows(section(&sections, ".debug_line").data(object));
+    let start = executable.address() as u64;
+    assert_eq!(rows.first(), Some(&(start, 1, 1)));
+    for (row, next) in rows.iter().zip(&rows[1 ..]) {
+        assert!(row.0 < next.0);
+    }
+    assert!(rows.iter().all(|row| row.0 < start + code_len as u64));
+    let locations: Vec<_> = rows.iter().map(|row| (row.1, row.2)).collect();
+    // Every instruction, then the final `Halt` right after the end.
+    assert_eq!(
+        locations,
+        [
+            (1, 1),
+            (1, 2),
+            (1, 3),
+            (1, 4),
+            (1, 5),
+            (1, 6),
+            (1, 7),
+            (2, 1),
+            (2, 2),
+            (2, 3),
+            (2, 4),
+            (2, 5),
+            (2, 6)
+        ]
+    );
+}
+
+#[derive(Debug, Clone)]
+struct SectionHeader {
+    name: String,
+    addr: u64,
+    offset: u64,
+    size: u64,
+}
+
+impl SectionHeader {
+    fn data<'object>(&self, object: &'object [u8]) -> &'object [u8] {
+        &object[self.offset as usize .. (self.offset + self.size) as usize]
+    }
+}
+
+fn sections(object: &[u8]) -> Vec<SectionHeader> {
+    assert_eq!(&object[.. 4], b"\x7fELF");
+    let shoff = u64_at(object, 0x28) as usize;
+    let count = usize::from(u16_at(object, 0x3c));
+    let shstrndx = usize::from(u16_at(object, 0x3e));
+    let header = |i: usize| &object[shoff + i * 64 .. shoff + (i + 1) * 64];
+    let shstrtab = header(shstrndx);
+    let shstrtab = &object[u64_at(shstrtab, 24) as usize ..];
+    (1 .. count)
+        .map(|i| {
+            let header = header(i);
+            let name = &shstrtab[u32_at(header, 0) as usize ..];
+            let len = name.iter().position(|&byte| byte == 0).unwrap();
+            SectionHeader {
+                name: String::from_utf8(name[.. len].to_vec()).unwrap(),
+                addr: u64_at(header, 16),
+                offset: u64_at(header, 24),
+                size: u64_at(header, 32),
+            }
+        })
+        .collect()
+}
+
+fn section<'sections>(
+    sections: &'sections [SectionHeader],
+    name: &str,
+) -> &'sections SectionHeader {
+    sections.iter().find(|section| section.name == name).unwrap()
+}
+
+/// Runs the line number program, returning the address, line and column of
+/// each row.
+fn line_rows(debug_line: &[u8]) -> Vec<(u64, u64, u64)> {
+    let unit_end = 4 + u32_at(debug_line, 0) as usize;
+    assert_eq!(u16_at(debug_line, 4), 4);
+    let mut pos = 10 + u32_at(debug_line, 6) as usize;
+    let (mut address, mut line, mut column) = (0, 1, 0);
+    let mut rows = Vec::new();
+    while pos < unit_end {
+        let opcode = debug_line[pos];
+        pos += 1;
+        match opcode {
+            0 => {
+                let len = uleb(debug_line, &mut pos) as usize;
+                match debug_line[pos] {
+                    DW_LNE_SET_ADDRESS => address = u64_at(debug_line, pos + 1),
+                    DW_LNE_END_SEQUENCE => break,
+                    other => panic!("unexpected extended opcode {}", other),
+                }
+                pos += len;
+            },
+            DW_LNS_COPY => rows.push((address, line, column)),
+            DW_LNS_ADVANCE_PC => address += uleb(debug_line, &mut pos),
+            DW_LNS_ADVANCE_LINE => {
+                line = (line as i64 + sleb(debug_line, &mut pos)) as u64
+            },
+            DW_LNS_SET_COLUMN => column = uleb(debug_line, &mut pos),
+            other => panic!("unexpected opcode {}", other),
+        }
+    }
+    rows
+}
+
+fn uleb(bytes: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+}
+
+fn sleb(bytes: &[u8], pos: &mut usize) -> i64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= i64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if byte & 0x40 != 0 && shift < 64 {
+                value |= -1 << shift;
+            }
+            return value;
+        }
+    }
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(bytes[pos .. pos + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos .. pos + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(bytes[pos .. pos + 8].try_into().unwrap())
+}
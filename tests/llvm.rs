@@ -0,0 +1,53 @@
+//! Builds generated LLVM IR with `llc` and its runtime with `cc`, if both are
+//! installed, and checks that it behaves like the interpreter.
+
+mod common;
+
+use catbf::{
+    compiler::aot::{self, Format, Options},
+    ir::Program,
+};
+use common::FIXTURES;
+use std::{fs, process::Command};
+
+/// Major version of the installed `llc`.
+fn llc_version() -> Option<u32> {
+    let output = Command::new("llc").arg("--version").output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let (_, version) = output.split_once("LLVM version ")?;
+    version.split('.').next()?.parse().ok()
+}
+
+#[test]
+fn generated_llvm_builds_and_runs() {
+    // Opaque pointers are only the default since LLVM 15, and only
+    // supported well enough since LLVM 14.
+    let Some(version) = llc_version().filter(|&version| version >= 14) else {
+        return;
+    };
+    if !common::installed("cc") {
+        return;
+    }
+    let pointers = if version < 15 { &["-opaque-pointers"][..] } else { &[] };
+    let options = Options { format: Format::Llvm, ..Options::default() };
+    for fixture in FIXTURES {
+        // Writes `prog.ll` and the `runtime.c` it is linked with.
+        let directory = common::directory(&format!("llvm-{}", fixture.name));
+        let program = fixture.code.parse::<Program>().unwrap();
+        aot::compile_with(&program, &directory, &options).unwrap();
+        common::build(
+            &directory,
+            Command::new("llc")
+                .args(["-O2", "-filetype=obj", "-relocation-model=pic"])
+                .args(pointers)
+                .args(["prog.ll", "-o", "prog.o"]),
+        );
+        common::build(
+            &directory,
+            Command::new("cc").args(["runtime.c", "prog.o", "-o", "prog"]),
+        );
+        let output = common::run(&directory.join("prog"), fixture.input);
+        assert_eq!(output, fixture.expected(), "{}", fixture.name);
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,122 @@
+//! Runs suspended on input and resumed, by the interpreter and by resumable
+//! code compiled Just-In-Time.
+
+use catbf::{
+    compiler::jit::{self, Status},
+    interpreter::{Error, Interface, Machine, Tape},
+    ir::Program,
+};
+use std::io::{self, Write};
+
+/// Prints 1, echoes its input, then prints 1 again.
+const ECHO: &str = "+.,[>.<,]>+.";
+
+fn resumable(code: &str) -> jit::Executable {
+    let options = jit::Options { resumable: true, ..jit::Options::default() };
+    jit::compile_with(&code.parse::<Program>().unwrap(), &options).unwrap()
+}
+
+#[test]
+fn interpreter_suspends_on_input() {
+    let mut output = Vec::new();
+    let interface = Interface::from_std(io::empty(), &mut output);
+    let mut machine =
+        Machine::new(ECHO.parse().unwrap(), Tape::new(), interface);
+    assert!(matches!(machine.resume(Some(b'a')), Err(Error::Control(_))));
+
+    assert_eq!(machine.start().unwrap(), Status::NeedsInput);
+    assert_eq!(machine.stats().bytes_written, 1);
+    assert_eq!(machine.resume(Some(b'a')).unwrap(), Status::NeedsInput);
+    assert_eq!(machine.resume(Some(b'b')).unwrap(), Status::NeedsInput);
+    assert_eq!(machine.stats().bytes_written, 3);
+    assert_eq!(machine.resume(None).unwrap(), Status::Halted);
+    assert!(machine.resume(None).is_err());
+
+    let stats = machine.stats();
+    assert_eq!((stats.bytes_read, stats.bytes_written), (2, 4));
+    assert_eq!(machine.tape().cursor(), 1);
+    assert_eq!(machine.tape().cells()[.. 2], [0, 1]);
+    drop(machine);
+    assert_eq!(output, b"\x01ab\x01");
+}
+
+#[test]
+fn jit_continuation_suspends_on_input() {
+    if !jit::TARGET_SUPPORTED {
+        return;
+    }
+    let executable = resumable(ECHO);
+    let mut continuation = executable.continuation().unwrap();
+    assert!(executable
+        .resume(&mut continuation, None, &mut io::sink())
+        .is_err());
+
+    // Each call may write to a different output.
+    let mut outputs = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+    let status = executable.start(&mut continuation, &mut outputs[0]);
+    assert_eq!(status.unwrap(), Status::NeedsInput);
+    assert!(executable.start(&mut continuation, &mut outputs[0]).is_err());
+    for (input, output) in
+        [Some(b'a'), Some(b'b')].into_iter().zip(&mut outputs[1 ..])
+    {
+        let status = executable.resume(&mut continuation, input, output);
+        assert_eq!(status.unwrap(), Status::NeedsInput);
+    }
+    let status = executable.resume(&mut continuation, None, &mut outputs[3]);
+    assert_eq!(status.unwrap(), Status::Halted);
+    assert_eq!(continuation.status(), Some(Status::Halted));
+    assert!(executable
+        .resume(&mut continuation, None, &mut io::sink())
+        .is_err());
+
+    assert_eq!(outputs, [&b"\x01"[..], b"a", b"b", b"\x01"]);
+    let stats = continuation.stats();
+    assert_eq!((stats.bytes_read, stats.bytes_written), (2, 4));
+    assert_eq!(continuation.cursor(), 1);
+    assert_eq!(continuation.tape()[.. 2], [0, 1]);
+}
+
+/// A writer that always fails.
+struct Full;
+
+impl Write for Full {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::StorageFull, "full"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn jit_continuation_fails_with_the_output_error() {
+    if !jit::TARGET_SUPPORTED {
+        return;
+    }
+    let executable = resumable(ECHO);
+    let mut continuation = executable.continuation().unwrap();
+    let status = executable.start(&mut continuation, &mut Vec::new());
+    assert_eq!(status.unwrap(), Status::NeedsInput);
+    let error = executable
+        .resume(&mut continuation, Some(b'a'), &mut Full)
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+
+    let error = jit::compile(&"+.".parse().unwrap())
+        .unwrap()
+        .run(io::empty(), Full)
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+}
+
+#[test]
+fn continuations_belong_to_their_executable() {
+    if !jit::TARGET_SUPPORTED {
+        return;
+    }
+    let (first, second) = (resumable(ECHO), resumable(ECHO));
+    let mut continuation = first.continuation().unwrap();
+    assert!(second.start(&mut continuation, &mut io::sink()).is_err());
+    assert!(first.start(&mut continuation, &mut io::sink()).is_ok());
+}
@@ -0,0 +1,34 @@
+//! Running programs given as bytes or strings.
+
+use catbf::{
+    engine::{Backend, EngineBuilder},
+    ir::{ParseError, Program},
+    run_bytes, run_bytes_with,
+};
+
+#[test]
+fn run_bytes_returns_output() {
+    let code = b"++++++++[>++++++++<-]>+.+.";
+    assert_eq!(run_bytes(code, b"").unwrap(), b"AB");
+}
+
+#[test]
+fn run_bytes_with_reads_input() {
+    let builder = EngineBuilder::new(Backend::Tiered).with_opt_level(1);
+    let output = run_bytes_with(include_bytes!("../cat.bf"), b"meow", &builder);
+    assert_eq!(output.unwrap(), b"meow");
+}
+
+#[test]
+fn parsing_unbalanced_brackets_fails() {
+    let error = "+[>+\n[-]".parse::<Program>().unwrap_err();
+    match error {
+        ParseError::UnmatchedLoopOpen(location) => {
+            assert_eq!((location.line, location.column), (1, 2))
+        },
+        error => panic!("unexpected error: {}", error),
+    }
+    let error = "+]".parse::<Program>().unwrap_err();
+    assert!(matches!(error, ParseError::UnmatchedLoopClose(_)), "{}", error);
+    assert!(run_bytes(b"[[]", b"").is_err());
+}
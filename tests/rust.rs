@@ -0,0 +1,31 @@
+//! Builds generated Rust with `rustc`, if installed, and checks that it
+//! behaves like the interpreter.
+
+mod common;
+
+use catbf::{compiler::rust, ir::Program};
+use common::FIXTURES;
+use std::{fs, process::Command};
+
+#[test]
+fn generated_rust_builds_and_runs() {
+    if !common::installed("rustc") {
+        return;
+    }
+    for fixture in FIXTURES {
+        // The module is always `prog`, so each program gets a directory.
+        let directory = common::directory(&format!("rust-{}", fixture.name));
+        let source = rust::generate(&fixture.code.parse::<Program>().unwrap());
+        fs::write(directory.join("prog.rs"), source.unwrap()).unwrap();
+        fs::write(directory.join("main.rs"), rust::main_source()).unwrap();
+        common::build(
+            &directory,
+            Command::new("rustc")
+                .args(["--edition", "2021", "-D", "warnings", "main.rs"])
+                .args(["-o", "prog"]),
+        );
+        let output = common::run(&directory.join("prog"), fixture.input);
+        assert_eq!(output, fixture.expected(), "{}", fixture.name);
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,171 @@
+//! Checks generated WebAssembly modules, and that the checker rejects broken
+//! ones.
+
+use catbf::{
+    compiler::wasm::{self, ValidationError},
+    ir::Program,
+};
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+/// Type section declaring `[] -> [i32]`.
+const TYPES: &[u8] = &[1, 0x60, 0, 1, 0x7f];
+/// Function section declaring a function of the first type.
+const FUNCTIONS: &[u8] = &[1, 0];
+
+fn generated(code: &str) -> Vec<u8> {
+    wasm::generate(&code.parse::<Program>().unwrap()).unwrap().encode()
+}
+
+/// A module with the given sections, all shorter than 128 bytes.
+fn module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    for (id, contents) in sections {
+        bytes.push(*id);
+        bytes.push(contents.len() as u8);
+        bytes.extend_from_slice(contents);
+    }
+    bytes
+}
+
+/// Code section with a single body without locals.
+fn code(body: &[u8]) -> Vec<u8> {
+    let mut code = vec![1, body.len() as u8 + 1, 0];
+    code.extend_from_slice(body);
+    code
+}
+
+#[test]
+fn generated_modules_are_valid() {
+    for code in [
+        "",
+        "+++.",
+        // Nested loops.
+        "++[>+++[>++<-]<-]>>.",
+        // Growth past the start of the tape, and back.
+        "<<<<<<<<+.>>>>>>>>[-]<<<<<<<<[>+<-]>.",
+        "+[<+]",
+        // Input.
+        ",[>.<,]",
+        ",>,<[->+<]>.",
+    ] {
+        let bytes = generated(code);
+        if let Err(error) = wasm::validate(&bytes) {
+            panic!("module of {:?} is invalid: {}", code, error);
+        }
+    }
+}
+
+#[test]
+fn hand_written_module_is_valid() {
+    let bytes = module(&[
+        (SECTION_TYPE, TYPES),
+        (SECTION_FUNCTION, FUNCTIONS),
+        // i32.const 0, end
+        (SECTION_CODE, &code(&[0x41, 0, 0x0b])),
+    ]);
+    wasm::validate(&bytes).unwrap();
+}
+
+#[test]
+fn bad_header_is_rejected() {
+    let mut bytes = generated("+.");
+    bytes[1] = b'x';
+    assert!(matches!(wasm::validate(&bytes), Err(ValidationError::BadHeader)));
+}
+
+#[test]
+fn truncated_module_is_rejected() {
+    let bytes = generated(",[>.<,]");
+    let truncated = &bytes[.. bytes.len() - 3];
+    assert!(matches!(
+        wasm::validate(truncated),
+        Err(ValidationError::UnexpectedEnd(_))
+    ));
+}
+
+#[test]
+fn sections_out_of_order_are_rejected() {
+    let bytes = module(&[(SECTION_FUNCTION, &[0]), (SECTION_TYPE, &[0])]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::SectionOrder(SECTION_TYPE, _))
+    ));
+}
+
+#[test]
+fn bad_type_index_is_rejected() {
+    let bytes = module(&[(SECTION_TYPE, TYPES), (SECTION_FUNCTION, &[1, 5])]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::BadIndex("type", 5, _))
+    ));
+}
+
+#[test]
+fn duplicate_export_is_rejected() {
+    let bytes = module(&[
+        (SECTION_TYPE, TYPES),
+        (SECTION_FUNCTION, FUNCTIONS),
+        (SECTION_EXPORT, &[2, 1, b'f', 0, 0, 1, b'f', 0, 0]),
+        (SECTION_CODE, &code(&[0x41, 0, 0x0b])),
+    ]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::DuplicateExport(name)) if name == "f"
+    ));
+}
+
+#[test]
+fn missing_body_is_rejected() {
+    let bytes = module(&[(SECTION_TYPE, TYPES), (SECTION_FUNCTION, FUNCTIONS)]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::FunctionCount(0, 1))
+    ));
+}
+
+#[test]
+fn stack_underflow_is_rejected() {
+    let bytes = module(&[
+        (SECTION_TYPE, TYPES),
+        (SECTION_FUNCTION, FUNCTIONS),
+        // end, without the result
+        (SECTION_CODE, &code(&[0x0b])),
+    ]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::StackUnderflow(_))
+    ));
+}
+
+#[test]
+fn values_left_on_the_stack_are_rejected() {
+    let bytes = module(&[
+        (SECTION_TYPE, TYPES),
+        (SECTION_FUNCTION, FUNCTIONS),
+        // i32.const 0, i32.const 0, end
+        (SECTION_CODE, &code(&[0x41, 0, 0x41, 0, 0x0b])),
+    ]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::StackHeight(1, _))
+    ));
+}
+
+#[test]
+fn unbalanced_body_is_rejected() {
+    let bytes = module(&[
+        (SECTION_TYPE, TYPES),
+        (SECTION_FUNCTION, FUNCTIONS),
+        // i32.const 0, end, then a stray end
+        (SECTION_CODE, &code(&[0x41, 0, 0x0b, 0x0b])),
+    ]);
+    assert!(matches!(
+        wasm::validate(&bytes),
+        Err(ValidationError::Unbalanced(_))
+    ));
+}